@@ -0,0 +1,52 @@
+use std::sync::Arc;
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+
+use barista::bar::Bar;
+
+fn show_unshown(c: &mut Criterion) {
+    let n = 10;
+    let mut bar = Bar::new(n, "[", " | ", "]", ' ', '_');
+    for i in 0..n {
+        bar.set(i, "0123456789");
+    }
+    c.bench_function("show_unshown changed", |b| {
+        b.iter(|| {
+            bar.set(black_box(3), black_box("9876543210"));
+            black_box(bar.show_unshown().map(str::len));
+        })
+    });
+    c.bench_function("show_unshown unchanged", |b| {
+        b.iter(|| {
+            black_box(bar.show_unshown().map(str::len));
+        })
+    });
+}
+
+/// One output interval of 10 feeds, each sending a new line, as they would
+/// at 100 Hz.
+fn input_10_feeds(c: &mut Criterion) {
+    let n = 10;
+    let mut bar = Bar::new(n, "[", " | ", "]", ' ', '_');
+    c.bench_function("input 10 feeds copied", |b| {
+        b.iter(|| {
+            for i in 0..n {
+                let data = black_box(format!("feed {} output", i));
+                bar.set(i, &data);
+            }
+            black_box(bar.show_unshown().map(str::len));
+        })
+    });
+    c.bench_function("input 10 feeds shared", |b| {
+        b.iter(|| {
+            for i in 0..n {
+                let data = black_box(format!("feed {} output", i));
+                bar.set_shared(i, Arc::new(data));
+            }
+            black_box(bar.show_unshown().map(str::len));
+        })
+    });
+}
+
+criterion_group!(benches, show_unshown, input_10_feeds);
+criterion_main!(benches);
@@ -2,24 +2,131 @@ use std::{
     io,
     path::{Path, PathBuf},
     process::Stdio,
-    time::SystemTime,
+    sync::{
+        atomic::{AtomicU32, AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, SystemTime},
 };
 
 use anyhow::{anyhow, bail, Context};
+use futures::{Future, Stream, StreamExt};
 use tokio::{
     fs,
-    io::AsyncBufReadExt,
+    io::{AsyncBufRead, AsyncBufReadExt, AsyncRead, AsyncWriteExt},
     process::{self, Command},
     task::{spawn_blocking, JoinHandle},
 };
 use tokio_util::sync::CancellationToken;
 use tracing::{info_span, Instrument};
 
-use crate::{bar, conf};
+use crate::{bar, conf, ps};
+
+/// Tells apart the feeds started over the life of the server, including
+/// those which took turns at the same position.
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+const EMFILE_RETRIES: usize = 5;
+const EMFILE_RETRY_INTERVAL: Duration = Duration::from_millis(50);
+/// Before giving up on systemctl and killing the feed's process group.
+const SYSTEMD_STOP_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Programs to run feeds in transient systemd units with, see
+/// conf::Feed::use_systemd_run.
+#[derive(Debug, Clone)]
+pub struct Systemd {
+    pub run: PathBuf,
+    pub ctl: PathBuf,
+}
+
+impl Systemd {
+    /// None if systemctl doesn't run.
+    pub async fn detect() -> Option<Self> {
+        let selph = Self {
+            run: PathBuf::from("systemd-run"),
+            ctl: PathBuf::from("systemctl"),
+        };
+        let result = Command::new(&selph.ctl)
+            .arg("--version")
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .await;
+        match result {
+            Ok(status) if status.success() => Some(selph),
+            result => {
+                tracing::debug!(?result, "systemd not available.");
+                None
+            }
+        }
+    }
+
+    /// Stops the unit and everything in it.
+    async fn stop(
+        &self,
+        unit: &str,
+        timeout: Duration,
+    ) -> anyhow::Result<()> {
+        let out = Command::new(&self.ctl)
+            .args(["--user", "stop", unit])
+            .stdin(Stdio::null())
+            .kill_on_drop(true)
+            .output();
+        let out = tokio::time::timeout(timeout, out)
+            .await
+            .map_err(|_| {
+                anyhow!(
+                    "Timed out after {:?} stopping unit {:?}",
+                    timeout,
+                    unit
+                )
+            })?
+            .context(format!("Failed to run {:?}", &self.ctl))?;
+        if !out.status.success() {
+            bail!(
+                "Failed to stop unit {:?}: {}: {}",
+                unit,
+                out.status,
+                String::from_utf8_lossy(&out.stderr).trim()
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Unique per feed directory and server process. Characters systemd does
+/// not allow in unit names are replaced.
+fn systemd_unit_name(dir: &Path) -> String {
+    let dir_name = dir.file_name().unwrap_or_default().to_string_lossy();
+    let dir_name: String = dir_name
+        .chars()
+        .map(|c| match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' | '-' | '_' | '.' => c,
+            _ => '_',
+        })
+        .collect();
+    format!(
+        "{}-feed-{}-{}.scope",
+        crate::NAME!(),
+        dir_name,
+        std::process::id()
+    )
+}
+
+/// Kept track of by the output reader, as it reads, and shared with the
+/// Feed.
+#[derive(Debug, Clone, Default)]
+struct OutputStats {
+    lines: Arc<AtomicU64>,
+    last_at: Arc<Mutex<Option<SystemTime>>>,
+    line_errors: Arc<AtomicU32>,
+}
 
 #[derive(Debug)]
 pub struct Feed {
     pos: usize,
+    id: u64,
     name: String,
     dir: PathBuf,
     log_file: PathBuf,
@@ -27,9 +134,12 @@ pub struct Feed {
     life: CancellationToken,
     pid: u32,
     pgid: nix::unistd::Pid,
+    spawned_at: SystemTime,
     output_reader: Option<JoinHandle<anyhow::Result<()>>>,
+    stderr_reader: Option<JoinHandle<anyhow::Result<()>>>,
     waiter_and_killer: Option<JoinHandle<anyhow::Result<()>>>,
-    last_output: Option<SystemTime>,
+    output_stats: OutputStats,
+    stderr_line_count: Arc<AtomicU64>,
 }
 
 impl Feed {
@@ -37,6 +147,11 @@ impl Feed {
         self.name.as_str()
     }
 
+    /// Sent along with the feed's outputs, see NEXT_ID.
+    pub fn get_id(&self) -> u64 {
+        self.id
+    }
+
     pub fn get_dir_path(&self) -> &Path {
         self.dir.as_path()
     }
@@ -46,7 +161,18 @@ impl Feed {
     }
 
     pub fn get_last_output_time(&self) -> Option<SystemTime> {
-        self.last_output
+        *self
+            .output_stats
+            .last_at
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Time of the last line read from the feed's stdout, recorded by the
+    /// output reader as soon as the line is read, rather than when the
+    /// server gets to it.
+    pub fn last_output_shared(&self) -> Arc<Mutex<Option<SystemTime>>> {
+        self.output_stats.last_at.clone()
     }
 
     pub fn get_pid(&self) -> u32 {
@@ -57,14 +183,35 @@ impl Feed {
         self.pgid.as_raw().unsigned_abs()
     }
 
-    pub fn set_last_output_time(&mut self) {
-        self.last_output = Some(SystemTime::now());
+    /// When the feed's process started, as far as the kernel knows, or,
+    /// failing that, right after it was spawned.
+    pub fn get_spawned_at(&self) -> SystemTime {
+        self.spawned_at
+    }
+
+    /// Lines read from the feed's stdout so far. A cheap proxy for the
+    /// number of lines in the log, for feeds which log per output.
+    pub fn get_output_lines(&self) -> u64 {
+        self.output_stats.lines.load(Ordering::Relaxed)
+    }
+
+    /// Output lines which could not be read and were skipped.
+    pub fn get_line_errors(&self) -> u32 {
+        self.output_stats.line_errors.load(Ordering::Relaxed)
+    }
+
+    /// Lines written by the feed to its stderr, i.e. to its log, since it
+    /// was started.
+    pub fn get_stderr_lines(&self) -> u64 {
+        self.stderr_line_count.load(Ordering::Relaxed)
     }
 
     pub async fn start(
         cfg: &conf::Feed,
         dir: &Path,
         pos: usize,
+        timezone: Option<&str>,
+        systemd: Option<&Systemd>,
         dst: bar::server::ApiSender,
     ) -> anyhow::Result<Self> {
         let dir = dir.to_path_buf();
@@ -73,40 +220,99 @@ impl Feed {
             &dir
         ))?;
         let log_file_path = conf::path_feed_log(&dir);
-        let log_file: std::fs::File = {
-            // XXX Can't use tokio::fs::File because std::process::Stdio::from
-            //     can't work with it and tokio offers no analogue. Possible
-            //     workarounds:
-            //     a. use std inside spawn_blocking;
-            //     b. use tokio and then unsafely convert to raw fd
-            //        and then use Stdio::from_raw_fd.
-            let log_file_path = log_file_path.clone();
-            spawn_blocking(move || {
-                std::fs::OpenOptions::new()
-                    .append(true)
-                    .create(true)
-                    .open(log_file_path)
-            })
-            .await??
-        };
+        let log_file = retry_on_emfile(|| async {
+            fs::OpenOptions::new()
+                .append(true)
+                .create(true)
+                .open(&log_file_path)
+                .await
+        })
+        .await
+        .context(format!("Failed to open log file: {:?}", &log_file_path))?;
         let shell = cfg.shell.clone().unwrap_or(conf::default_shell());
-        let mut child = Command::new(shell)
-            .arg("-c") // FIXME Some shells may use a different argument flag?
-            .arg(&cfg.cmd)
-            .current_dir(&dir)
+        let unit = match (cfg.get_use_systemd_run(), systemd) {
+            (false, _) => None,
+            (true, None) => {
+                tracing::warn!(
+                    name = cfg.name,
+                    "systemd not available. Running feed without it."
+                );
+                None
+            }
+            (true, Some(systemd)) => {
+                Some((systemd.clone(), systemd_unit_name(&dir)))
+            }
+        };
+        let mut cmd = match &unit {
+            None => Command::new(&shell),
+            Some((systemd, unit)) => {
+                // A scope runs the command in place, so it stays our child,
+                // with the same PID and pipes.
+                let mut cmd = Command::new(&systemd.run);
+                cmd.args(["--user", "--scope", "--quiet", "--unit", unit])
+                    .arg("--")
+                    .arg(&shell);
+                cmd
+            }
+        };
+        let cmd_str = match &cfg.cmd {
+            conf::FeedCmd::Cmd(cmd_str) => {
+                // FIXME Some shells may use a different argument flag?
+                cmd.arg("-c").arg(cmd_str);
+                cmd_str.to_string()
+            }
+            conf::FeedCmd::Script(script) => {
+                let path = write_script(&dir, script).await?;
+                cmd.arg(&path);
+                path.to_string_lossy().to_string()
+            }
+        };
+        cmd.current_dir(&dir)
             .stdout(Stdio::piped())
-            .stderr(Stdio::from(log_file))
-            .process_group(0) // XXX Sets PGID to PID.
-            .spawn()
-            .context(format!(
-                "Failed to spawn feed. Dir: {:?}. Feed: {:?}",
-                &dir, cfg,
-            ))?;
+            .stderr(Stdio::piped())
+            .process_group(0); // XXX Sets PGID to PID.
+        if let Some(timezone) = timezone {
+            cmd.env("TZ", timezone);
+        }
+        if let Some(stdin_file) = &cfg.stdin_file {
+            cmd.stdin(open_stdin(&dir.join(stdin_file)).await);
+        }
+        let no_new_privs = cfg.no_new_privs.unwrap_or(false);
+        let seccomp_filter = match &cfg.seccomp_filter {
+            None => None,
+            Some(path) => Some(read_seccomp_filter(&dir.join(path)).await?),
+        };
+        if no_new_privs || seccomp_filter.is_some() {
+            // SAFETY: Between fork and exec, so only making syscalls, without
+            //         allocating.
+            unsafe {
+                cmd.pre_exec(move || {
+                    if no_new_privs {
+                        nix::sys::prctl::set_no_new_privs()?;
+                    }
+                    if let Some(filter) = &seccomp_filter {
+                        load_seccomp_filter(filter)?;
+                    }
+                    Ok(())
+                });
+            }
+        }
+        let mut child = cmd.spawn().context(format!(
+            "Failed to spawn feed. Dir: {:?}. Feed: {:?}",
+            &dir, cfg,
+        ))?;
 
         let pid = child.id().ok_or(anyhow!(
             "Failed to get child process PID for feed: {:?}",
             cfg
         ))?;
+        let spawned_at = match ps::start_time(pid).await {
+            Ok(time) => time,
+            Err(error) => {
+                tracing::warn!(?error, pid, "Failed to get start time.");
+                SystemTime::now()
+            }
+        };
         let pid_file = conf::path_feed_pid(&dir);
         fs::write(&pid_file, pid.to_string())
             .await
@@ -115,24 +321,82 @@ impl Feed {
         // XXX Assuming Command.process_group(0) was called.
         // TODO Should Err(_) actually be unreachable!() here?
         let pgid = to_nix_pid(pid)?;
+        if let Err(error) = conf::FeedStartInfo::new(pid, pid, cmd_str, shell)
+            .write(&dir)
+            .await
+        {
+            // Nothing would be tracking it otherwise.
+            if let Err(errno) = nix::sys::signal::killpg(
+                pgid,
+                nix::sys::signal::Signal::SIGKILL,
+            ) {
+                tracing::error!(pid, ?errno, "Failed to kill feed.");
+            }
+            if let Err(error) = child.wait().await {
+                tracing::error!(pid, ?error, "Failed to wait for feed.");
+            }
+            if let Err(error) = fs::remove_file(&pid_file).await {
+                tracing::error!(?error, "Failed to remove PID file.");
+            }
+            return Err(error);
+        }
 
         let stdout = child.stdout.take().unwrap_or_else(|| {
             unreachable!("stdout not requested at process spawn.")
         });
-        let span = info_span!("feed", pos = pos + 1, name = cfg.name, pid);
-        let output_reader = tokio::spawn(
-            output_reader(stdout, pos, dst.clone())
+        let stderr = child.stderr.take().unwrap_or_else(|| {
+            unreachable!("stderr not requested at process spawn.")
+        });
+        let span = info_span!(
+            "feed",
+            pos = pos + 1,
+            name = cfg.name,
+            pid,
+            log_level = cfg.log_level.map(tracing::field::display)
+        );
+        let stderr_line_count = Arc::new(AtomicU64::new(0));
+        let log = Log {
+            file: log_file,
+            path: log_file_path.clone(),
+            rotated_path: conf::path_feed_log_rotated(&dir),
+            max_lines: cfg.max_log_lines,
+            max_line_bytes: cfg.get_max_stderr_bytes_per_line(),
+        };
+        let stderr_reader = tokio::spawn(
+            stderr_reader(stderr, log, stderr_line_count.clone())
                 .instrument(span.clone())
                 .in_current_span(),
         );
+        let output_stats = OutputStats::default();
+        let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+        let output_reader = tokio::spawn(
+            output_reader(
+                stdout,
+                pos,
+                id,
+                cfg.clone(),
+                output_stats.clone(),
+                dst.clone(),
+            )
+            .instrument(span.clone())
+            .in_current_span(),
+        );
         let life = CancellationToken::new();
         let waiter_and_killer = tokio::spawn(
-            waiter_and_killer(dst.clone(), life.clone(), pos, pgid, child)
-                .instrument(span)
-                .in_current_span(),
+            waiter_and_killer(
+                dst.clone(),
+                life.clone(),
+                pos,
+                pgid,
+                unit,
+                child,
+            )
+            .instrument(span)
+            .in_current_span(),
         );
         let selph = Self {
             pos,
+            id,
             name: cfg.name.to_string(),
             dir,
             log_file: log_file_path,
@@ -140,9 +404,12 @@ impl Feed {
             life,
             pid,
             pgid,
+            spawned_at,
             output_reader: Some(output_reader),
+            stderr_reader: Some(stderr_reader),
             waiter_and_killer: Some(waiter_and_killer),
-            last_output: None,
+            output_stats,
+            stderr_line_count,
         };
         Ok(selph)
     }
@@ -168,34 +435,210 @@ impl Feed {
             name = self.name
         )
     )]
-    pub async fn clean_up(&mut self) -> anyhow::Result<()> {
+    pub async fn clean_up(
+        &mut self,
+        timeout: Duration,
+    ) -> anyhow::Result<()> {
         tracing::debug!("Starting.");
-        self.waiter_and_killer
+        let waiter_and_killer = self
+            .waiter_and_killer
             .take()
-            .unwrap_or_else(|| unreachable!("Redundant feed stop attempt."))
-            .await??;
-        self.output_reader
+            .unwrap_or_else(|| unreachable!("Redundant feed stop attempt."));
+        let output_reader = self
+            .output_reader
             .take()
-            .unwrap_or_else(|| unreachable!("Redundant feed stop attempt."))
-            .await??;
+            .unwrap_or_else(|| unreachable!("Redundant feed stop attempt."));
+        let stderr_reader = self
+            .stderr_reader
+            .take()
+            .unwrap_or_else(|| unreachable!("Redundant feed stop attempt."));
+        join_or_abort(
+            vec![waiter_and_killer, output_reader, stderr_reader],
+            timeout,
+        )
+        .await?;
         fs::remove_file(self.pid_file.as_path()).await?;
+        // Missing after a failed write, or if written by an older server.
+        match fs::remove_file(conf::path_feed_start_info(&self.dir)).await {
+            Err(error) if error.kind() == io::ErrorKind::NotFound => {}
+            result => result?,
+        }
         tracing::info!("Done.");
         Ok(())
     }
 }
 
+async fn write_script(dir: &Path, script: &str) -> anyhow::Result<PathBuf> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let path = conf::path_feed_script(dir);
+    fs::write(&path, script)
+        .await
+        .context(format!("Failed to write script file: {:?}", &path))?;
+    fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755))
+        .await
+        .context(format!("Failed to make script executable: {:?}", &path))?;
+    Ok(path)
+}
+
+/// Reads a raw array of `struct sock_filter`, in native byte order.
+async fn read_seccomp_filter(
+    path: &Path,
+) -> anyhow::Result<Vec<nix::libc::sock_filter>> {
+    const INSN_SIZE: usize = std::mem::size_of::<nix::libc::sock_filter>();
+    const MAX_INSNS: usize = 4096; // BPF_MAXINSNS
+
+    let data = fs::read(path)
+        .await
+        .context(format!("Failed to read seccomp filter: {:?}", path))?;
+    if data.is_empty() || data.len() % INSN_SIZE != 0 {
+        bail!(
+            "Invalid seccomp filter size: {} bytes, in {:?}. \
+            Expected a non-zero multiple of {}.",
+            data.len(),
+            path,
+            INSN_SIZE
+        );
+    }
+    if data.len() / INSN_SIZE > MAX_INSNS {
+        bail!(
+            "Seccomp filter too long: {} instructions, in {:?}. Max: {}.",
+            data.len() / INSN_SIZE,
+            path,
+            MAX_INSNS
+        );
+    }
+    let filter = data
+        .chunks_exact(INSN_SIZE)
+        .map(|insn| nix::libc::sock_filter {
+            code: u16::from_ne_bytes([insn[0], insn[1]]),
+            jt: insn[2],
+            jf: insn[3],
+            k: u32::from_ne_bytes([insn[4], insn[5], insn[6], insn[7]]),
+        })
+        .collect();
+    Ok(filter)
+}
+
+/// Called in the child, between fork and exec.
+fn load_seccomp_filter(filter: &[nix::libc::sock_filter]) -> io::Result<()> {
+    let prog = nix::libc::sock_fprog {
+        // XXX Length already checked against BPF_MAXINSNS when read.
+        len: filter.len() as nix::libc::c_ushort,
+        filter: filter.as_ptr().cast_mut(),
+    };
+    // SAFETY: prog points to a valid filter which outlives the call.
+    let result = unsafe {
+        nix::libc::prctl(
+            nix::libc::PR_SET_SECCOMP,
+            nix::libc::SECCOMP_MODE_FILTER,
+            &prog as *const nix::libc::sock_fprog,
+        )
+    };
+    if result == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Await tasks in order, aborting all that remain if they don't finish
+/// within timeout altogether.
+async fn join_or_abort(
+    mut tasks: Vec<JoinHandle<anyhow::Result<()>>>,
+    timeout: Duration,
+) -> anyhow::Result<()> {
+    let joined = tokio::time::timeout(timeout, async {
+        for task in tasks.iter_mut() {
+            task.await??;
+        }
+        Ok::<(), anyhow::Error>(())
+    })
+    .await;
+    match joined {
+        Ok(result) => result,
+        Err(_) => {
+            for task in &tasks {
+                task.abort();
+            }
+            tracing::error!(?timeout, "Clean up timed out. Aborted tasks.");
+            // TODO Post notification.
+            Err(anyhow!("Forced abort of feed tasks after {:?}", timeout))
+        }
+    }
+}
+
+/// Running out of file descriptors is likely to be temporary when many
+/// feeds are (re)started at once, so give the others a moment to settle.
+async fn retry_on_emfile<T, F, Fut>(mut op: F) -> io::Result<T>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = io::Result<T>>,
+{
+    let mut retries = 0;
+    loop {
+        match op().await {
+            Err(error)
+                if error.raw_os_error() == Some(nix::libc::EMFILE)
+                    && retries < EMFILE_RETRIES =>
+            {
+                retries += 1;
+                tracing::warn!(?error, retries, "Too many open files.");
+                tokio::time::sleep(EMFILE_RETRY_INTERVAL).await;
+            }
+            result => return result,
+        }
+    }
+}
+
+async fn open_stdin(path: &Path) -> Stdio {
+    let path_buf = path.to_path_buf();
+    let result: io::Result<std::fs::File> =
+        spawn_blocking(move || std::fs::File::open(path_buf))
+            .await
+            .map_err(io::Error::from)
+            .and_then(|result| result);
+    match result {
+        Ok(file) => Stdio::from(file),
+        Err(error) => {
+            tracing::warn!(
+                ?path,
+                ?error,
+                "Failed to open stdin file. Falling back to null."
+            );
+            Stdio::null()
+        }
+    }
+}
+
 #[tracing::instrument(skip_all)]
 async fn waiter_and_killer(
     dst_tx: bar::server::ApiSender,
     life: CancellationToken,
     pos: usize,
     pgid: nix::unistd::Pid,
+    unit: Option<(Systemd, String)>,
     mut child: process::Child,
 ) -> anyhow::Result<()> {
     tracing::info!("Starting.");
     let result: io::Result<std::process::ExitStatus> = async {
         tokio::select! {
             () = life.cancelled() => {
+                if let Some((systemd, unit)) = &unit {
+                    match systemd.stop(unit, SYSTEMD_STOP_TIMEOUT).await {
+                        Ok(()) => {
+                            tracing::debug!(unit, "Unit stopped.");
+                            child.start_kill()?;
+                            return child.wait().await;
+                        }
+                        Err(error) => {
+                            tracing::error!(
+                                ?error,
+                                "Failed to stop unit. \
+                                Killing process group instead."
+                            );
+                        }
+                    }
+                }
                 nix::sys::signal::killpg(
                     pgid,
                     nix::sys::signal::Signal::SIGKILL
@@ -220,14 +663,16 @@ async fn waiter_and_killer(
             //     after .spawn() if/when we actually need it.
             result = child.wait() => {
                 tracing::error!(?result, "Unsolicited feed process exit.");
-                // TODO Post notification.
                 // TODO Should we try to kill the process group here anyway?
                 result
             }
         }
     }
     .await;
-    if let Err(error) = bar::server::feed_exit(&dst_tx, pos, result) {
+    // Expected only if we were the ones who killed it.
+    let expected = life.is_cancelled();
+    if let Err(error) = bar::server::feed_exit(&dst_tx, pos, result, expected)
+    {
         tracing::error!(
             ?error,
             "Failed to report feed exit back to the bar server."
@@ -239,50 +684,357 @@ async fn waiter_and_killer(
 
 #[tracing::instrument(skip_all)]
 async fn output_reader(
-    stdout: process::ChildStdout,
+    stdout: impl AsyncRead + Unpin,
     pos: usize,
+    id: u64,
+    cfg: conf::Feed,
+    stats: OutputStats,
     dst_tx: bar::server::ApiSender,
 ) -> anyhow::Result<()> {
     tracing::info!("Starting.");
-    let mut lines = tokio::io::BufReader::new(stdout).lines();
-    while let Some(line) = lines.next_line().await? {
+    let lines = limited_lines(
+        tokio::io::BufReader::new(stdout),
+        cfg.get_max_stdout_bytes_per_line(),
+        cfg.get_encoding(),
+    );
+    let max_line_errors = cfg.get_max_line_errors();
+    let sentinel = cfg.get_output_sentinel()?;
+    let joiner = cfg.get_output_joiner();
+    let prefix = cfg.get_output_prefix();
+    let suffix = cfg.get_output_suffix();
+    // Lines of the block until the sentinel, limited in total size the
+    // same way a single line is.
+    let max_block_bytes = cfg.get_max_stdout_bytes_per_line();
+    let mut block: Vec<String> = Vec::new();
+    let mut block_bytes: usize = 0;
+    futures::pin_mut!(lines);
+    while let Some(line) = lines.next().await {
+        let line = match line {
+            Ok(line) => line,
+            // The feed is gone, or going, rather than its output bad.
+            Err(error)
+                if matches!(
+                    error.kind(),
+                    io::ErrorKind::BrokenPipe | io::ErrorKind::UnexpectedEof
+                ) =>
+            {
+                tracing::debug!(?error, "Output closed.");
+                break;
+            }
+            Err(error) => {
+                let errors =
+                    stats.line_errors.fetch_add(1, Ordering::Relaxed) + 1;
+                if errors > max_line_errors {
+                    tracing::error!(?error, errors, "Too many line errors.");
+                    return Err(error.into());
+                }
+                tracing::warn!(?error, errors, "Skipping unreadable line.");
+                continue;
+            }
+        };
         tracing::debug!(?line, "New");
-        bar::server::feed_data(&dst_tx, pos, line)?;
+        stats.lines.fetch_add(1, Ordering::Relaxed);
+        *stats.last_at.lock().unwrap_or_else(|e| e.into_inner()) =
+            Some(SystemTime::now());
+        let data = match &sentinel {
+            None => line,
+            Some(sentinel) if sentinel.is_match(&line) => {
+                block_bytes = 0;
+                std::mem::take(&mut block).join(joiner)
+            }
+            Some(_) => {
+                if block_bytes + line.len() > max_block_bytes {
+                    tracing::warn!(
+                        block_bytes,
+                        max_block_bytes,
+                        "Block too big. Skipping line until sentinel."
+                    );
+                } else {
+                    block_bytes += line.len();
+                    block.push(line);
+                }
+                continue;
+            }
+        };
+        let data = if prefix.is_empty() && suffix.is_empty() {
+            data
+        } else {
+            format!("{}{}{}", prefix, data, suffix)
+        };
+        bar::server::feed_data(&dst_tx, pos, id, data)?;
     }
     tracing::debug!("Exiting.");
     Ok(())
 }
 
-/// Try to find and kill all previously saved PIDs.
-pub async fn try_kill_all(dir: &Path) -> anyhow::Result<()> {
+/// The feed's log file, as written by stderr_reader.
+struct Log {
+    file: fs::File,
+    path: PathBuf,
+    rotated_path: PathBuf,
+    max_lines: Option<usize>,
+    max_line_bytes: usize,
+}
+
+impl Log {
+    /// Starts a new file in place of the current one, which replaces the
+    /// previously rotated one.
+    async fn rotate(&mut self) -> anyhow::Result<()> {
+        self.file.flush().await?;
+        fs::rename(&self.path, &self.rotated_path)
+            .await
+            .context(format!(
+                "Failed to rotate log file: {:?} -> {:?}",
+                &self.path, &self.rotated_path
+            ))?;
+        self.file = fs::OpenOptions::new()
+            .append(true)
+            .create(true)
+            .open(&self.path)
+            .await
+            .context(format!("Failed to open log file: {:?}", &self.path))?;
+        tracing::debug!(path = ?self.path, "Rotated log.");
+        Ok(())
+    }
+}
+
+/// Copies stderr to the log as is, counting lines on the way. Lines longer
+/// than the log's max_line_bytes are truncated, same as output lines, so
+/// that a feed can't make us buffer unbounded amounts of data.
+#[tracing::instrument(skip_all)]
+async fn stderr_reader(
+    stderr: impl AsyncRead + Unpin,
+    mut log: Log,
+    line_count: Arc<AtomicU64>,
+) -> anyhow::Result<()> {
+    tracing::info!("Starting.");
+    // The log is appended to across restarts, so may not start empty.
+    let mut lines_in_file = match log.max_lines {
+        None => 0,
+        Some(_) => count_log_lines(&log.path).await?,
+    };
+    let mut stderr = tokio::io::BufReader::new(stderr);
+    while let Some((mut line, truncated)) =
+        read_limited_line_bytes(&mut stderr, log.max_line_bytes).await?
+    {
+        if truncated {
+            tracing::warn!(
+                max = log.max_line_bytes,
+                "Log line too long. Truncated."
+            );
+        }
+        if log.max_lines.is_some_and(|max| lines_in_file >= max) {
+            log.rotate().await?;
+            lines_in_file = 0;
+        }
+        line.push(b'\n');
+        log.file.write_all(&line).await?;
+        lines_in_file += 1;
+        line_count.fetch_add(1, Ordering::Relaxed);
+    }
+    log.file.flush().await?;
+    tracing::debug!("Exiting.");
+    Ok(())
+}
+
+/// Like `AsyncBufReadExt::lines`, but lines longer than `max_bytes` are
+/// truncated (at a UTF-8 character boundary) and the rest of such a line is
+/// discarded, so a misbehaving feed can't make us buffer unbounded amounts
+/// of data.
+pub fn limited_lines<R: AsyncBufRead + Unpin>(
+    reader: R,
+    max_bytes: usize,
+    encoding: conf::FeedEncoding,
+) -> impl Stream<Item = io::Result<String>> {
+    futures::stream::unfold(reader, move |mut reader| async move {
+        match read_limited_line(&mut reader, max_bytes, encoding).await {
+            Ok(None) => None,
+            Ok(Some(line)) => Some((Ok(line), reader)),
+            Err(error) => Some((Err(error), reader)),
+        }
+    })
+}
+
+async fn read_limited_line<R: AsyncBufRead + Unpin>(
+    reader: &mut R,
+    max_bytes: usize,
+    encoding: conf::FeedEncoding,
+) -> io::Result<Option<String>> {
+    let Some((mut buf, truncated)) =
+        read_limited_line_bytes(reader, max_bytes).await?
+    else {
+        return Ok(None);
+    };
+    if !truncated && buf.last() == Some(&b'\r') {
+        buf.pop();
+    }
+    let line = decode(buf, truncated, encoding)?;
+    Ok(Some(line))
+}
+
+/// The line without its newline, at most max_bytes of it, and whether the
+/// rest of it was discarded. None at the end of input.
+async fn read_limited_line_bytes<R: AsyncBufRead + Unpin>(
+    reader: &mut R,
+    max_bytes: usize,
+) -> io::Result<Option<(Vec<u8>, bool)>> {
+    let mut buf: Vec<u8> = Vec::new();
+    let mut consumed: usize = 0;
+    let mut truncated = false;
+    loop {
+        let available = reader.fill_buf().await?;
+        if available.is_empty() {
+            if consumed == 0 {
+                return Ok(None);
+            }
+            break;
+        }
+        let (chunk, found_newline) =
+            match available.iter().position(|b| *b == b'\n') {
+                Some(i) => (&available[..i], true),
+                None => (available, false),
+            };
+        let room = max_bytes.saturating_sub(buf.len());
+        if chunk.len() > room {
+            truncated = true;
+        }
+        buf.extend_from_slice(&chunk[..chunk.len().min(room)]);
+        let n = chunk.len() + usize::from(found_newline);
+        reader.consume(n);
+        consumed += n;
+        if found_newline {
+            break;
+        }
+    }
+    Ok(Some((buf, truncated)))
+}
+
+fn decode(
+    mut buf: Vec<u8>,
+    truncated: bool,
+    encoding: conf::FeedEncoding,
+) -> io::Result<String> {
+    if let conf::FeedEncoding::Latin1 = encoding {
+        // Each byte is the code point of the same value.
+        return Ok(buf.into_iter().map(char::from).collect());
+    }
+    if truncated {
+        if let Err(error) = std::str::from_utf8(&buf) {
+            if error.error_len().is_none() {
+                // Cut in the middle of a multi-byte character.
+                buf.truncate(error.valid_up_to());
+            }
+        }
+    }
+    match String::from_utf8(buf) {
+        Ok(line) => Ok(line),
+        Err(error) => match encoding {
+            conf::FeedEncoding::Utf8Lossy => {
+                Ok(String::from_utf8_lossy(error.as_bytes()).into_owned())
+            }
+            conf::FeedEncoding::Utf8Strict | conf::FeedEncoding::Latin1 => {
+                Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    error.utf8_error(),
+                ))
+            }
+        },
+    }
+}
+
+/// Truncate the feed's log file. The feed's stderr reader (if still
+/// running) keeps its descriptor, which was opened in append mode, so its
+/// subsequent writes simply continue from the new end of the file.
+pub async fn clear_log(log_file: &Path) -> anyhow::Result<()> {
+    fs::OpenOptions::new()
+        .write(true)
+        .truncate(true)
+        .open(log_file)
+        .await
+        .context(format!("Failed to truncate log file: {:?}", log_file))?;
+    Ok(())
+}
+
+/// Reads the whole file, so only worth it when an exact count is wanted.
+pub async fn count_log_lines(log_file: &Path) -> anyhow::Result<usize> {
+    let log = fs::read_to_string(log_file)
+        .await
+        .context(format!("Failed to read log file: {:?}", log_file))?;
+    Ok(log.lines().count())
+}
+
+/// Find up to `max_lines` lines in the feed's log file matching the regex
+/// `pattern`, most recent first.
+pub async fn search_log(
+    log_file: &Path,
+    pattern: String,
+    max_lines: usize,
+) -> anyhow::Result<Vec<String>> {
+    let log_file = log_file.to_path_buf();
+    spawn_blocking(move || {
+        let regex = regex::Regex::new(&pattern)
+            .context(format!("Invalid search pattern: {:?}", &pattern))?;
+        let log = std::fs::read_to_string(&log_file)
+            .context(format!("Failed to read log file: {:?}", &log_file))?;
+        let lines: Vec<String> = log
+            .lines()
+            .rev()
+            .filter(|line| regex.is_match(line))
+            .take(max_lines)
+            .map(String::from)
+            .collect();
+        Ok(lines)
+    })
+    .await?
+}
+
+/// Outcome of try_kill_all, by feed directory.
+#[derive(Debug, Default)]
+pub struct KillReport {
+    pub killed: Vec<PathBuf>,
+    pub failed: Vec<(PathBuf, anyhow::Error)>,
+}
+
+impl KillReport {
+    pub fn has_failures(&self) -> bool {
+        !self.failed.is_empty()
+    }
+
+    pub fn display(&self) -> String {
+        let mut lines = Vec::new();
+        for path in &self.killed {
+            lines.push(format!("killed: {:?}", path));
+        }
+        for (path, error) in &self.failed {
+            lines.push(format!("failed: {:?}: {:#}", path, error));
+        }
+        lines.push(format!(
+            "{} killed, {} failed",
+            self.killed.len(),
+            self.failed.len()
+        ));
+        lines.join("\n")
+    }
+}
+
+/// Try to find and kill all previously saved PIDs. Failures to kill are
+/// reported rather than returned as errors, which are only for failures
+/// to look for PIDs at all.
+pub async fn try_kill_all(feeds_dir: &Path) -> anyhow::Result<KillReport> {
     tracing::warn!(
-        ?dir,
+        ?feeds_dir,
         "Attempting to find and kill PIDs in feed PID files."
     );
-    let feeds_dir = conf::path_feeds_dir(dir);
     let mut feeds_dir_entries = fs::read_dir(&feeds_dir).await?;
-    let mut total: usize = 0;
-    let mut failed: usize = 0;
+    let mut report = KillReport::default();
     while let Some(entry) = feeds_dir_entries.next_entry().await? {
-        total += 1;
         let path = entry.path();
-        if let Err(error) = try_kill(entry).await {
-            failed += 1;
-            tracing::error!(
-                ?error,
-                ?path,
-                "Failed to lookup and kill feed process group."
-            );
+        match try_kill(entry).await {
+            Ok(()) => report.killed.push(path),
+            Err(error) => report.failed.push((path, error)),
         }
     }
-    if failed > 0 {
-        Err(anyhow!("{} out of {} kill attempts failed.", failed, total))
-    } else {
-        tracing::info!(
-            "Killed all found process groups of previously started feeds."
-        );
-        Ok(())
-    }
+    Ok(report)
 }
 
 async fn try_kill(entry: fs::DirEntry) -> anyhow::Result<()> {
@@ -313,7 +1065,14 @@ async fn try_kill(entry: fs::DirEntry) -> anyhow::Result<()> {
         .parse()
         .context(format!("Failed to parse feed PID file: {:?}", &pid_file))?;
     let pid = to_nix_pid(pid)?;
-    let pgrp = pid;
+    // Older servers didn't leave start info, but their PGIDs were PIDs.
+    let pgrp = match conf::FeedStartInfo::read(&entry_path).await {
+        Ok(info) => to_nix_pid(info.pgid)?,
+        Err(error) => {
+            tracing::warn!(?error, "Assuming the PGID is the PID.");
+            pid
+        }
+    };
     nix::sys::signal::killpg(pgrp, nix::sys::signal::Signal::SIGKILL)
         .context(format!(
             "Failed to kill process group: {}. PID: {}. PID file: {:?}.",
@@ -323,6 +1082,13 @@ async fn try_kill(entry: fs::DirEntry) -> anyhow::Result<()> {
         "Failed to remove feed PID file: {:?}",
         &pid_file
     ))?;
+    let start_info_file = conf::path_feed_start_info(&entry_path);
+    if fs::try_exists(&start_info_file).await? {
+        fs::remove_file(&start_info_file).await.context(format!(
+            "Failed to remove feed start info: {:?}",
+            &start_info_file
+        ))?;
+    }
     Ok(())
 }
 
@@ -331,3 +1097,657 @@ fn to_nix_pid(pid: u32) -> anyhow::Result<nix::unistd::Pid> {
     let pid: i32 = pid.try_into()?;
     Ok(nix::unistd::Pid::from_raw(pid))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn fail_with_emfile_n_times(n: usize) -> (io::Result<()>, usize) {
+        let mut attempts = 0;
+        let result = retry_on_emfile(|| {
+            attempts += 1;
+            let attempt = attempts;
+            async move {
+                if attempt <= n {
+                    Err(io::Error::from_raw_os_error(nix::libc::EMFILE))
+                } else {
+                    Ok(())
+                }
+            }
+        })
+        .await;
+        (result, attempts)
+    }
+
+    #[tokio::test]
+    async fn retry_on_emfile_succeeds() {
+        let (result, attempts) = fail_with_emfile_n_times(0).await;
+        assert!(result.is_ok());
+        assert_eq!(1, attempts);
+
+        let (result, attempts) = fail_with_emfile_n_times(3).await;
+        assert!(result.is_ok());
+        assert_eq!(4, attempts);
+
+        let (result, attempts) =
+            fail_with_emfile_n_times(EMFILE_RETRIES).await;
+        assert!(result.is_ok());
+        assert_eq!(EMFILE_RETRIES + 1, attempts);
+    }
+
+    #[tokio::test]
+    async fn retry_on_emfile_gives_up() {
+        let (result, attempts) =
+            fail_with_emfile_n_times(EMFILE_RETRIES + 1).await;
+        assert_eq!(
+            Some(nix::libc::EMFILE),
+            result.unwrap_err().raw_os_error()
+        );
+        assert_eq!(EMFILE_RETRIES + 1, attempts);
+    }
+
+    #[tokio::test]
+    async fn retry_on_emfile_other_errors() {
+        let mut attempts = 0;
+        let result: io::Result<()> = retry_on_emfile(|| {
+            attempts += 1;
+            async { Err(io::Error::from(io::ErrorKind::NotFound)) }
+        })
+        .await;
+        assert!(result.is_err());
+        assert_eq!(1, attempts);
+    }
+
+    async fn collect_limited_lines(
+        input: &[u8],
+        max_bytes: usize,
+    ) -> Vec<String> {
+        limited_lines(input, max_bytes, conf::FeedEncoding::Utf8Strict)
+            .map(|line| line.unwrap())
+            .collect()
+            .await
+    }
+
+    #[tokio::test]
+    async fn limited_lines_short() {
+        assert_eq!(
+            vec!["abc", "", "def"],
+            collect_limited_lines(b"abc\n\ndef\r\n", 5).await
+        );
+        assert_eq!(vec!["abc"], collect_limited_lines(b"abc", 5).await);
+        assert!(collect_limited_lines(b"", 5).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn limited_lines_long() {
+        let long = "x".repeat(1024 * 1024);
+        let input = format!("{}\nshort\n{}", long, long);
+        assert_eq!(
+            vec!["x".repeat(16), "short".to_string(), "x".repeat(16)],
+            collect_limited_lines(input.as_bytes(), 16).await
+        );
+    }
+
+    #[tokio::test]
+    async fn limited_lines_utf8_boundary() {
+        // "é" is 2 bytes, so 3 bytes would split the second one.
+        assert_eq!(
+            vec!["é", "ab"],
+            collect_limited_lines("éé\nab".as_bytes(), 3).await
+        );
+    }
+
+    #[tokio::test]
+    async fn limited_lines_encoding() {
+        use conf::FeedEncoding::{Latin1, Utf8Lossy, Utf8Strict};
+
+        let input = &b"a\xffb\nok\n"[..];
+        let lines: Vec<io::Result<String>> =
+            limited_lines(input, 16, Utf8Strict).collect().await;
+        assert!(lines[0].is_err());
+
+        let lines: Vec<String> = limited_lines(input, 16, Utf8Lossy)
+            .map(|line| line.unwrap())
+            .collect()
+            .await;
+        assert_eq!(vec!["a\u{FFFD}b", "ok"], lines);
+
+        let lines: Vec<String> = limited_lines(input, 16, Latin1)
+            .map(|line| line.unwrap())
+            .collect()
+            .await;
+        assert_eq!(vec!["a\u{FF}b", "ok"], lines);
+
+        // Character cut by truncation is dropped, not replaced.
+        let lines: Vec<String> =
+            limited_lines("éé\n".as_bytes(), 3, Utf8Lossy)
+                .map(|line| line.unwrap())
+                .collect()
+                .await;
+        assert_eq!(vec!["é"], lines);
+    }
+
+    #[tokio::test]
+    async fn stdin_file() {
+        let path = crate::tmp_path("stdin_file");
+        fs::write(&path, "hello\n").await.unwrap();
+        let out = Command::new("cat")
+            .stdin(open_stdin(&path).await)
+            .output()
+            .await
+            .unwrap();
+        assert_eq!(b"hello\n", out.stdout.as_slice());
+        fs::remove_file(&path).await.unwrap();
+
+        // Missing file falls back to null stdin.
+        let out = Command::new("cat")
+            .stdin(open_stdin(&crate::tmp_path("stdin_file_missing")).await)
+            .output()
+            .await
+            .unwrap();
+        assert!(out.status.success());
+        assert!(out.stdout.is_empty());
+    }
+
+    #[tokio::test]
+    async fn join_or_abort_timeout() {
+        let quick = tokio::spawn(async { Ok(()) });
+        let (alive_tx, alive_rx) = tokio::sync::oneshot::channel::<()>();
+        let stuck = tokio::spawn(async move {
+            let _alive_tx = alive_tx;
+            tokio::time::sleep(Duration::from_secs(10)).await;
+            Ok(())
+        });
+        let result =
+            join_or_abort(vec![quick, stuck], Duration::from_millis(50))
+                .await;
+        assert!(result.unwrap_err().to_string().starts_with("Forced abort"));
+        // Aborted task was dropped, and alive_tx along with it.
+        assert!(alive_rx.await.is_err());
+
+        let quick = tokio::spawn(async { Ok(()) });
+        let result = join_or_abort(vec![quick], Duration::from_secs(5)).await;
+        assert!(result.is_ok());
+
+        let failed = tokio::spawn(async { Err(anyhow!("failed")) });
+        let result =
+            join_or_abort(vec![failed], Duration::from_secs(5)).await;
+        assert_eq!("failed", result.unwrap_err().to_string());
+    }
+
+    #[tokio::test]
+    async fn stderr_line_count() {
+        let dir = crate::tmp_path("stderr_line_count");
+        let (tx, _rx) = tokio::sync::mpsc::unbounded_channel();
+        let mut cfg = conf::Conf::default().feeds[0].clone();
+        cfg.cmd = conf::FeedCmd::Cmd(
+            "for i in 1 2 3 4 5; do echo $i >&2; done; sleep 10".to_string(),
+        );
+        let mut feed =
+            Feed::start(&cfg, &dir, 0, None, None, tx).await.unwrap();
+        let counted = tokio::time::timeout(Duration::from_secs(5), async {
+            while feed.get_stderr_lines() < 5 {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await;
+        assert!(counted.is_ok());
+        feed.stop();
+        feed.clean_up(cfg.get_stop_timeout()).await.unwrap();
+        assert_eq!(5, feed.get_stderr_lines());
+        assert_eq!(5, count_log_lines(feed.get_log_path()).await.unwrap());
+        fs::remove_dir_all(&dir).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn start_info_missing() {
+        let dir = crate::tmp_path("start_info_missing");
+        let (tx, _rx) = tokio::sync::mpsc::unbounded_channel();
+        let mut cfg = conf::Conf::default().feeds[0].clone();
+        cfg.cmd = conf::FeedCmd::Cmd("sleep 10".to_string());
+
+        // Not a reason to fail the clean-up.
+        let mut feed = Feed::start(&cfg, &dir, 0, None, None, tx.clone())
+            .await
+            .unwrap();
+        fs::remove_file(conf::path_feed_start_info(&dir))
+            .await
+            .unwrap();
+        feed.stop();
+        feed.clean_up(cfg.get_stop_timeout()).await.unwrap();
+
+        // Failing to write it doesn't leave the feed running.
+        let tmp = conf::path_feed_start_info(&dir).with_extension("json.tmp");
+        fs::create_dir_all(&tmp).await.unwrap();
+        assert!(Feed::start(&cfg, &dir, 0, None, None, tx).await.is_err());
+        assert!(!conf::path_feed_pid(&dir).exists());
+        let mut procs = fs::read_dir("/proc").await.unwrap();
+        while let Some(entry) = procs.next_entry().await.unwrap() {
+            if let Ok(cwd) = fs::read_link(entry.path().join("cwd")).await {
+                assert_ne!(dir, cwd, "Feed left running: {:?}", entry.path());
+            }
+        }
+        fs::remove_dir_all(&dir).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn max_log_lines() {
+        let dir = crate::tmp_path("max_log_lines");
+        let (tx, _rx) = tokio::sync::mpsc::unbounded_channel();
+        let mut cfg = conf::Conf::default().feeds[0].clone();
+        cfg.cmd = conf::FeedCmd::Cmd(
+            "for i in $(seq 20); do echo $i >&2; done; sleep 10".to_string(),
+        );
+        cfg.max_log_lines = Some(10);
+        let mut feed =
+            Feed::start(&cfg, &dir, 0, None, None, tx).await.unwrap();
+        let counted = tokio::time::timeout(Duration::from_secs(5), async {
+            while feed.get_stderr_lines() < 20 {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await;
+        assert!(counted.is_ok());
+        feed.stop();
+        feed.clean_up(cfg.get_stop_timeout()).await.unwrap();
+        let log = fs::read_to_string(feed.get_log_path()).await.unwrap();
+        let rotated =
+            fs::read_to_string(conf::path_feed_log_rotated(&dir)).await;
+        let expected: Vec<String> =
+            (11..=20).map(|i| i.to_string()).collect();
+        assert_eq!(expected, log.lines().collect::<Vec<_>>());
+        let expected: Vec<String> = (1..=10).map(|i| i.to_string()).collect();
+        assert_eq!(expected, rotated.unwrap().lines().collect::<Vec<_>>());
+        fs::remove_dir_all(&dir).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn stderr_reader_long_lines() {
+        let dir = crate::tmp_path("stderr_reader_long_lines");
+        fs::create_dir_all(&dir).await.unwrap();
+        let path = conf::path_feed_log(&dir);
+        let log = Log {
+            file: fs::File::create(&path).await.unwrap(),
+            path: path.clone(),
+            rotated_path: conf::path_feed_log_rotated(&dir),
+            max_lines: None,
+            max_line_bytes: 4,
+        };
+        let line_count = Arc::new(AtomicU64::new(0));
+        let stderr =
+            format!("{}\nab\n{}", "x".repeat(1024 * 1024), "y".repeat(10));
+        stderr_reader(stderr.as_bytes(), log, line_count.clone())
+            .await
+            .unwrap();
+        assert_eq!(
+            "xxxx\nab\nyyyy\n",
+            fs::read_to_string(&path).await.unwrap()
+        );
+        assert_eq!(3, line_count.load(Ordering::Relaxed));
+        fs::remove_dir_all(&dir).await.unwrap();
+    }
+
+    async fn proc_status_field(cfg: &conf::Feed, name: &str) -> String {
+        let dir = crate::tmp_path(&format!("proc_status_field_{}", name));
+        let (tx, _rx) = tokio::sync::mpsc::unbounded_channel();
+        let mut feed =
+            Feed::start(cfg, &dir, 0, None, None, tx).await.unwrap();
+        let logged = tokio::time::timeout(Duration::from_secs(5), async {
+            while feed.get_stderr_lines() < 1 {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await;
+        assert!(logged.is_ok());
+        feed.stop();
+        feed.clean_up(cfg.get_stop_timeout()).await.unwrap();
+        let log = fs::read_to_string(feed.get_log_path()).await.unwrap();
+        fs::remove_dir_all(&dir).await.unwrap();
+        log.lines()
+            .find_map(|line| line.strip_prefix(&format!("{}:", name)))
+            .unwrap()
+            .trim()
+            .to_string()
+    }
+
+    #[tokio::test]
+    async fn no_new_privs() {
+        let mut cfg = conf::Conf::default().feeds[0].clone();
+        cfg.cmd = conf::FeedCmd::Cmd(
+            "grep NoNewPrivs /proc/self/status >&2; sleep 10".to_string(),
+        );
+        assert_eq!("0", proc_status_field(&cfg, "NoNewPrivs").await);
+        cfg.no_new_privs = Some(true);
+        assert_eq!("1", proc_status_field(&cfg, "NoNewPrivs").await);
+    }
+
+    #[tokio::test]
+    async fn seccomp_filter() {
+        // BPF_RET | BPF_K, SECCOMP_RET_ALLOW
+        let allow_all: Vec<u8> = [
+            0x06u16.to_ne_bytes().as_slice(),
+            &[0, 0],
+            0x7fff_0000u32.to_ne_bytes().as_slice(),
+        ]
+        .concat();
+        let path = crate::tmp_path("seccomp_filter.bpf");
+        fs::write(&path, &allow_all).await.unwrap();
+        let mut cfg = conf::Conf::default().feeds[0].clone();
+        cfg.cmd = conf::FeedCmd::Cmd(
+            "grep Seccomp: /proc/self/status >&2; sleep 10".to_string(),
+        );
+        cfg.no_new_privs = Some(true);
+        cfg.seccomp_filter = Some(path.clone());
+        // SECCOMP_MODE_FILTER
+        assert_eq!("2", proc_status_field(&cfg, "Seccomp").await);
+
+        fs::write(&path, &allow_all[..7]).await.unwrap();
+        let (tx, _rx) = tokio::sync::mpsc::unbounded_channel();
+        let dir = crate::tmp_path("seccomp_filter_invalid");
+        assert!(Feed::start(&cfg, &dir, 0, None, None, tx).await.is_err());
+        fs::remove_file(&path).await.unwrap();
+        fs::remove_dir_all(&dir).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn systemd_run() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = crate::tmp_path("systemd_run");
+        let mocks = crate::tmp_path("systemd_run_mocks");
+        fs::create_dir_all(&mocks).await.unwrap();
+        let run_args = mocks.join("run_args");
+        let ctl_args = mocks.join("ctl_args");
+        let systemd = Systemd {
+            run: mocks.join("systemd-run"),
+            ctl: mocks.join("systemctl"),
+        };
+        // Passes through to what comes after --, as a scope would.
+        let run = format!(
+            "#!/bin/sh\n\
+            echo \"$@\" > {:?}\n\
+            while [ \"$1\" != -- ]; do shift; done\n\
+            shift\n\
+            exec \"$@\"\n",
+            run_args
+        );
+        let ctl = format!("#!/bin/sh\necho \"$@\" > {:?}\n", ctl_args);
+        for (path, script) in [(&systemd.run, run), (&systemd.ctl, ctl)] {
+            fs::write(path, script).await.unwrap();
+            let mode = std::fs::Permissions::from_mode(0o755);
+            fs::set_permissions(path, mode).await.unwrap();
+        }
+
+        let (tx, _rx) = tokio::sync::mpsc::unbounded_channel();
+        let mut cfg = conf::Conf::default().feeds[0].clone();
+        cfg.cmd = conf::FeedCmd::Cmd("echo ran >&2; sleep 10".to_string());
+        cfg.use_systemd_run = Some(true);
+        let mut feed = Feed::start(&cfg, &dir, 0, None, Some(&systemd), tx)
+            .await
+            .unwrap();
+        let logged = tokio::time::timeout(Duration::from_secs(5), async {
+            while feed.get_stderr_lines() < 1 {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await;
+        assert!(logged.is_ok());
+        feed.stop();
+        feed.clean_up(cfg.get_stop_timeout()).await.unwrap();
+
+        let unit = systemd_unit_name(&dir);
+        let log = fs::read_to_string(feed.get_log_path()).await.unwrap();
+        assert_eq!("ran\n", log);
+        let run_args = fs::read_to_string(&run_args).await.unwrap();
+        assert!(run_args.starts_with(&format!(
+            "--user --scope --quiet --unit {} -- ",
+            unit
+        )));
+        let ctl_args = fs::read_to_string(&ctl_args).await.unwrap();
+        assert_eq!(format!("--user stop {}\n", unit), ctl_args);
+        fs::remove_dir_all(&dir).await.unwrap();
+        fs::remove_dir_all(&mocks).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn systemd_stop_timeout() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let mocks = crate::tmp_path("systemd_stop_timeout_mocks");
+        fs::create_dir_all(&mocks).await.unwrap();
+        let systemd = Systemd {
+            run: mocks.join("systemd-run"),
+            ctl: mocks.join("systemctl"),
+        };
+        fs::write(&systemd.ctl, "#!/bin/sh\nexec sleep 10\n")
+            .await
+            .unwrap();
+        let mode = std::fs::Permissions::from_mode(0o755);
+        fs::set_permissions(&systemd.ctl, mode).await.unwrap();
+
+        let started_at = std::time::Instant::now();
+        let result = systemd.stop("unit", Duration::from_millis(100)).await;
+        assert!(started_at.elapsed() < Duration::from_secs(5));
+        let error = result.unwrap_err().to_string();
+        assert!(error.contains("Timed out"), "{}", error);
+        fs::remove_dir_all(&mocks).await.unwrap();
+    }
+
+    #[test]
+    fn unit_name() {
+        let name = systemd_unit_name(Path::new("/feeds/01-my feed/"));
+        assert_eq!(
+            format!(
+                "{}-feed-01-my_feed-{}.scope",
+                crate::NAME!(),
+                std::process::id()
+            ),
+            name
+        );
+    }
+
+    #[tokio::test]
+    async fn start_info() {
+        let main_dir = crate::tmp_path("start_info");
+        let feeds_dir = conf::path_feeds_dir(&main_dir, None);
+        let dir = conf::path_feed_dir(&feeds_dir, 0, "sleeper");
+        let (tx, _rx) = tokio::sync::mpsc::unbounded_channel();
+        let mut cfg = conf::Conf::default().feeds[0].clone();
+        cfg.cmd = conf::FeedCmd::Cmd("sleep 10".to_string());
+        let before = SystemTime::now();
+        let feed = Feed::start(&cfg, &dir, 0, None, None, tx).await.unwrap();
+        let info = conf::FeedStartInfo::read(&dir).await.unwrap();
+        assert_eq!(feed.get_pid(), info.pid);
+        assert_eq!(feed.get_pgid(), info.pgid);
+        assert_eq!("sleep 10", info.cmd);
+        assert_eq!(conf::default_shell(), info.shell);
+        assert!(humantime::parse_rfc3339(&info.started_at).is_ok());
+        let spawned_at = feed.get_spawned_at();
+        let since = before.duration_since(spawned_at).unwrap_or_default();
+        let until = spawned_at.duration_since(before).unwrap_or_default();
+        assert!(since.max(until) < Duration::from_secs(1));
+
+        // As if the server crashed.
+        let report = try_kill_all(&feeds_dir).await.unwrap();
+        assert_eq!(vec![dir.clone()], report.killed);
+        assert!(!conf::path_feed_pid(&dir).exists());
+        assert!(!conf::path_feed_start_info(&dir).exists());
+        fs::remove_dir_all(&main_dir).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn log_level() {
+        use tracing_subscriber::layer::{Layer, SubscriberExt};
+
+        let logs = crate::tracing::LogCapture::default();
+        let subscriber = {
+            let logs = logs.clone();
+            tracing_subscriber::registry().with(
+                tracing_subscriber::fmt::layer()
+                    .with_ansi(false)
+                    .with_writer(move || logs.clone())
+                    .with_filter(crate::tracing::FeedLevelFilter),
+            )
+        };
+        // Current-thread runtime, so this covers the feeds' tasks too.
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        let main_dir = crate::tmp_path("log_level");
+        let feeds_dir = conf::path_feeds_dir(&main_dir, None);
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        let mut feeds = Vec::new();
+        for (pos, (name, log_level)) in
+            [("quiet", Some(tracing::Level::WARN)), ("loud", None)]
+                .into_iter()
+                .enumerate()
+        {
+            let dir = conf::path_feed_dir(&feeds_dir, pos, name);
+            let mut cfg = conf::Conf::default().feeds[0].clone();
+            cfg.cmd = conf::FeedCmd::Cmd(format!("echo {}; sleep 10", name));
+            cfg.log_level = log_level;
+            let feed = Feed::start(&cfg, &dir, pos, None, None, tx.clone())
+                .await
+                .unwrap();
+            feeds.push(feed);
+        }
+        for _ in 0..2 {
+            rx.recv().await.unwrap();
+        }
+        let logs = String::from_utf8(logs.0.lock().unwrap().clone()).unwrap();
+        assert!(logs.contains(r#"line="loud""#));
+        assert!(!logs.contains(r#"line="quiet""#));
+
+        try_kill_all(&feeds_dir).await.unwrap();
+        fs::remove_dir_all(&main_dir).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn kill_report() {
+        use std::os::unix::process::{CommandExt, ExitStatusExt};
+
+        let main_dir = crate::tmp_path("kill_report");
+        let feeds_dir = conf::path_feeds_dir(&main_dir, None);
+        let mut child = std::process::Command::new("sleep")
+            .arg("10")
+            .process_group(0)
+            .spawn()
+            .unwrap();
+        let pids = [
+            ("alive", child.id().to_string()),
+            ("garbage", "not a pid".to_string()),
+            ("wrapped", u32::MAX.to_string()),
+        ];
+        for (pos, (name, pid)) in pids.iter().enumerate() {
+            let dir = conf::path_feed_dir(&feeds_dir, pos, name);
+            fs::create_dir_all(&dir).await.unwrap();
+            fs::write(conf::path_feed_pid(&dir), pid).await.unwrap();
+        }
+        let no_pid = conf::path_feed_dir(&feeds_dir, pids.len(), "no_pid");
+        fs::create_dir_all(&no_pid).await.unwrap();
+
+        let report = try_kill_all(&feeds_dir).await.unwrap();
+        assert!(report.has_failures());
+        let alive = conf::path_feed_dir(&feeds_dir, 0, "alive");
+        assert_eq!(vec![alive], report.killed);
+        let mut failed: Vec<&PathBuf> =
+            report.failed.iter().map(|(path, _)| path).collect();
+        failed.sort();
+        assert_eq!(
+            vec![
+                &conf::path_feed_dir(&feeds_dir, 1, "garbage"),
+                &conf::path_feed_dir(&feeds_dir, 2, "wrapped"),
+                &no_pid,
+            ],
+            failed
+        );
+        assert!(report.display().ends_with("1 killed, 3 failed"));
+        let status = child.wait().unwrap();
+        assert_eq!(Some(nix::libc::SIGKILL), status.signal());
+        fs::remove_dir_all(&main_dir).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn clear_log() {
+        let log_file = crate::tmp_path("clear_log");
+        fs::write(&log_file, "foo\nbar\nbaz\n").await.unwrap();
+        assert_eq!(
+            12,
+            crate::fs::file_stats(&log_file).await.unwrap().size_bytes
+        );
+        super::clear_log(&log_file).await.unwrap();
+        assert_eq!(
+            0,
+            crate::fs::file_stats(&log_file).await.unwrap().size_bytes
+        );
+        assert_eq!("", fs::read_to_string(&log_file).await.unwrap());
+        fs::remove_file(&log_file).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn search_log() {
+        let log_file = crate::tmp_path("search_log");
+        fs::write(&log_file, "error: 1\nok\nerror: 2\nok\nerror: 3\n")
+            .await
+            .unwrap();
+        assert_eq!(
+            vec!["error: 3", "error: 2", "error: 1"],
+            super::search_log(&log_file, "^error".to_string(), 10)
+                .await
+                .unwrap()
+        );
+        assert_eq!(
+            vec!["error: 3", "error: 2"],
+            super::search_log(&log_file, "^error".to_string(), 2)
+                .await
+                .unwrap()
+        );
+        assert!(super::search_log(&log_file, "warn".to_string(), 10)
+            .await
+            .unwrap()
+            .is_empty());
+        assert!(super::search_log(&log_file, "(".to_string(), 10)
+            .await
+            .is_err());
+        fs::remove_file(&log_file).await.unwrap();
+    }
+
+    /// Fails every read, as with an abruptly closed pipe.
+    struct FailingReader(io::ErrorKind);
+
+    impl AsyncRead for FailingReader {
+        fn poll_read(
+            self: std::pin::Pin<&mut Self>,
+            _: &mut std::task::Context<'_>,
+            _: &mut tokio::io::ReadBuf<'_>,
+        ) -> std::task::Poll<io::Result<()>> {
+            std::task::Poll::Ready(Err(self.0.into()))
+        }
+    }
+
+    #[tokio::test]
+    async fn output_reader_closed() {
+        use tokio::io::AsyncReadExt;
+
+        for (kind, is_ok) in [
+            (io::ErrorKind::UnexpectedEof, true),
+            (io::ErrorKind::BrokenPipe, true),
+            (io::ErrorKind::PermissionDenied, false),
+        ] {
+            let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+            let stats = OutputStats::default();
+            let stdout = (&b"a\n"[..]).chain(FailingReader(kind));
+            let result = output_reader(
+                stdout,
+                0,
+                0,
+                conf::Conf::default().feeds[0].clone(),
+                stats.clone(),
+                tx,
+            )
+            .await;
+            assert_eq!(is_ok, result.is_ok(), "{:?}", kind);
+            assert_eq!(1, stats.lines.load(Ordering::Relaxed));
+            assert!(rx.try_recv().is_ok());
+        }
+    }
+}
@@ -2,8 +2,29 @@ pub mod feed;
 pub mod server;
 pub mod status;
 
+use std::{sync::Arc, time::SystemTime};
+
+use unicode_width::UnicodeWidthStr;
+
 use crate::conf::Conf;
 
+/// When and how many times a slot was set by its feed. Clearing and
+/// expiring are not updates.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SlotMeta {
+    pub updated_at: SystemTime,
+    pub version_count: u64,
+}
+
+impl SlotMeta {
+    fn new() -> Self {
+        Self {
+            updated_at: SystemTime::now(),
+            version_count: 0,
+        }
+    }
+}
+
 pub struct Bar {
     left_pad: String,
     separator: String,
@@ -11,8 +32,20 @@ pub struct Bar {
     clear_char: char,
     expire_char: char,
     shown: bool,
+    /// In terminal columns. See show_truncated.
+    max_len: Option<usize>,
+    overflow_suffix: String,
 
-    slots: Vec<String>,
+    // Shared with the feed input messages they came from, rather than
+    // copied out of them.
+    slots: Vec<Arc<String>>,
+    slots_meta: Vec<SlotMeta>,
+    /// Hidden slots are left out of the bar, along with their separators.
+    visible: Vec<bool>,
+
+    // Rendered bar, rebuilt lazily in show when stale:
+    cached_show: String,
+    cached: bool,
 }
 
 impl Bar {
@@ -24,10 +57,29 @@ impl Bar {
         clear_char: char,
         expire_char: char,
     ) -> Self {
-        let mut slots = Vec::with_capacity(n);
-        for _ in 0..n {
-            slots.push(String::new());
-        }
+        Self::new_with_initial(
+            (0..n).map(|_| String::new()),
+            left_pad,
+            separator,
+            right_pad,
+            clear_char,
+            expire_char,
+        )
+    }
+
+    /// One slot per initial value.
+    pub fn new_with_initial(
+        initial: impl IntoIterator<Item = String>,
+        left_pad: &str,
+        separator: &str,
+        right_pad: &str,
+        clear_char: char,
+        expire_char: char,
+    ) -> Self {
+        let slots: Vec<Arc<String>> =
+            initial.into_iter().map(Arc::new).collect();
+        let slots_meta = vec![SlotMeta::new(); slots.len()];
+        let visible = vec![true; slots.len()];
         Self {
             left_pad: left_pad.to_string(),
             separator: separator.to_string(),
@@ -35,24 +87,145 @@ impl Bar {
             clear_char,
             expire_char,
             shown: false,
+            max_len: None,
+            overflow_suffix: String::new(),
             slots,
+            slots_meta,
+            visible,
+            cached_show: String::new(),
+            cached: false,
         }
     }
+
     pub fn from_conf(conf: &Conf) -> Self {
         let n = conf.feeds.len();
-        Self::new(
+        let mut selph = Self::new(
             n,
             &conf.pad_left,
             &conf.sep,
             &conf.pad_right,
-            ' ',
+            conf.get_clear_character(),
             conf.expiry_character,
-        )
+        );
+        selph.set_max_len(conf.max_bar_length, conf.get_overflow_suffix());
+        selph
+    }
+
+    /// Slots beyond those in initial are empty and those in initial beyond
+    /// the number of feeds are dropped.
+    pub fn from_conf_with_initial(conf: &Conf, initial: Vec<String>) -> Self {
+        let n = conf.feeds.len();
+        let mut selph = Self::new_with_initial(
+            initial
+                .into_iter()
+                .chain(std::iter::repeat_with(String::new))
+                .take(n),
+            &conf.pad_left,
+            &conf.sep,
+            &conf.pad_right,
+            conf.get_clear_character(),
+            conf.expiry_character,
+        );
+        selph.set_max_len(conf.max_bar_length, conf.get_overflow_suffix());
+        selph
+    }
+
+    pub fn set_max_len(&mut self, max_len: Option<usize>, suffix: &str) {
+        self.max_len = max_len;
+        suffix.clone_into(&mut self.overflow_suffix);
+        self.shown = false;
+        self.cached = false;
+    }
+
+    pub fn snapshot(&self) -> Vec<String> {
+        self.slots.iter().map(|slot| String::clone(slot)).collect()
+    }
+
+    pub fn slot_meta(&self, i: usize) -> &SlotMeta {
+        &self.slots_meta[i]
     }
 
     pub fn set(&mut self, i: usize, data: &str) {
-        self.slots[i] = data.to_string();
+        self.set_shared(i, Arc::new(data.to_string()));
+    }
+
+    pub fn set_shared(&mut self, i: usize, data: Arc<String>) {
+        let meta = &mut self.slots_meta[i];
+        meta.updated_at = SystemTime::now();
+        meta.version_count += 1;
+        self.put(i, data);
+    }
+
+    fn put(&mut self, i: usize, data: Arc<String>) {
+        self.slots[i] = data;
+        // Changes to hidden slots make no visible difference.
+        if self.visible[i] {
+            self.shown = false;
+        }
+        self.cached = false;
+    }
+
+    pub fn len(&self) -> usize {
+        self.slots.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.slots.is_empty()
+    }
+
+    /// Extends with empty, visible slots, or drops the slots beyond n.
+    /// Content and visibility of the remaining slots are kept.
+    pub fn resize(&mut self, n: usize) {
+        if n == self.slots.len() {
+            return;
+        }
+        self.slots.resize_with(n, || Arc::new(String::new()));
+        self.slots_meta.resize_with(n, SlotMeta::new);
+        self.visible.resize(n, true);
         self.shown = false;
+        self.cached = false;
+    }
+
+    /// Inserts an empty, visible slot at pos, shifting the later ones.
+    pub fn insert_slot(&mut self, pos: usize) {
+        self.slots.insert(pos, Arc::new(String::new()));
+        self.slots_meta.insert(pos, SlotMeta::new());
+        self.visible.insert(pos, true);
+        self.shown = false;
+        self.cached = false;
+    }
+
+    /// Removes the slot at pos, shifting the later ones.
+    pub fn remove_slot(&mut self, pos: usize) {
+        self.slots.remove(pos);
+        self.slots_meta.remove(pos);
+        self.visible.remove(pos);
+        self.shown = false;
+        self.cached = false;
+    }
+
+    pub fn hide_slot(&mut self, i: usize) {
+        self.set_visible(i, false);
+    }
+
+    pub fn show_slot(&mut self, i: usize) {
+        self.set_visible(i, true);
+    }
+
+    fn set_visible(&mut self, i: usize, visible: bool) {
+        if self.visible[i] != visible {
+            self.visible[i] = visible;
+            self.shown = false;
+            self.cached = false;
+        }
+    }
+
+    pub fn set_separator(&mut self, sep: String) {
+        if self.separator != sep {
+            self.separator = sep;
+            self.shown = false;
+            self.cached = false;
+        }
     }
 
     pub fn clear_all(&mut self) {
@@ -71,62 +244,353 @@ impl Bar {
 
     fn overwrite(&mut self, i: usize, c: char) {
         let new: String = (0..self.slots[i].len()).map(|_| c).collect();
-        self.set(i, &new);
-        self.shown = false;
+        self.put(i, Arc::new(new));
     }
 
-    fn show(&self) -> String {
-        [
-            self.left_pad.to_string(),
-            self.slots.join(&self.separator),
-            self.right_pad.to_string(),
-        ]
-        .into_iter()
-        .collect()
+    fn show(&mut self) -> &str {
+        if !self.cached {
+            self.cached_show.clear();
+            self.cached_show.push_str(&self.left_pad);
+            let visible_slots = self
+                .slots
+                .iter()
+                .zip(&self.visible)
+                .filter_map(|(slot, visible)| visible.then_some(slot));
+            for (i, slot) in visible_slots.enumerate() {
+                if i > 0 {
+                    self.cached_show.push_str(&self.separator);
+                }
+                self.cached_show.push_str(slot);
+            }
+            self.cached_show.push_str(&self.right_pad);
+            if let Some(max_len) = self.max_len {
+                if self.cached_show.width() > max_len {
+                    self.cached_show =
+                        self.show_truncated(max_len, &self.overflow_suffix);
+                }
+            }
+            self.cached = true;
+        }
+        &self.cached_show
     }
 
-    pub fn show_unshown(&mut self) -> Option<String> {
-        (!self.shown).then(|| {
+    /// The bar with only as many of the visible slots, from the left, as
+    /// fit within max_len terminal columns, along with the pads and, if any
+    /// slots were left out, the suffix. If not even the pads and the suffix
+    /// fit, they are cut to max_len.
+    pub fn show_truncated(&self, max_len: usize, suffix: &str) -> String {
+        let visible_slots: Vec<&str> = self
+            .slots
+            .iter()
+            .zip(&self.visible)
+            .filter_map(|(slot, visible)| visible.then_some(slot.as_str()))
+            .collect();
+        let pads_len = self.left_pad.width() + self.right_pad.width();
+        let sep_len = self.separator.width();
+        let total_len = pads_len
+            + visible_slots.iter().map(|slot| slot.width()).sum::<usize>()
+            + sep_len * visible_slots.len().saturating_sub(1);
+        let (fitting, suffix) = if total_len <= max_len {
+            (visible_slots.len(), "")
+        } else {
+            let mut len = pads_len + suffix.width();
+            let mut fitting = 0;
+            for (i, slot) in visible_slots.iter().enumerate() {
+                let sep_len = if i > 0 { sep_len } else { 0 };
+                len += sep_len + slot.width();
+                if len > max_len {
+                    break;
+                }
+                fitting += 1;
+            }
+            (fitting, suffix)
+        };
+        let mut bar = self.left_pad.clone();
+        bar.push_str(&visible_slots[..fitting].join(&self.separator));
+        bar.push_str(suffix);
+        bar.push_str(&self.right_pad);
+        if bar.width() > max_len {
+            bar = truncate_to_width(&bar, max_len);
+        }
+        bar
+    }
+
+    /// Like show_unshown, but even if already shown.
+    pub fn show_anyway(&mut self) -> &str {
+        self.shown = true;
+        self.show()
+    }
+
+    pub fn show_unshown(&mut self) -> Option<&str> {
+        if self.shown {
+            None
+        } else {
             self.shown = true;
-            self.show()
-        })
+            Some(self.show())
+        }
     }
 }
 
+fn truncate_to_width(s: &str, max_len: usize) -> String {
+    let mut len = 0;
+    s.chars()
+        .take_while(|c| {
+            len += unicode_width::UnicodeWidthChar::width(*c).unwrap_or(0);
+            len <= max_len
+        })
+        .collect()
+}
+
 #[cfg(test)]
 mod tests {
+    use unicode_width::UnicodeWidthStr;
+
     use super::Bar;
 
     #[test]
     fn basic() {
         let mut b = Bar::new(3, "[", "|", "]", ' ', '_');
-        assert_eq!(["", "", ""], b.slots.as_slice());
+        assert_eq!(["", "", ""], b.snapshot().as_slice());
         assert_eq!("[||]", b.show());
 
         b.set(1, "abc");
-        assert_eq!(["", "abc", ""], b.slots.as_slice());
+        assert_eq!(["", "abc", ""], b.snapshot().as_slice());
         assert_eq!("[|abc|]", b.show());
 
         b.set(2, "def");
-        assert_eq!(["", "abc", "def"], b.slots.as_slice());
+        assert_eq!(["", "abc", "def"], b.snapshot().as_slice());
         assert_eq!("[|abc|def]", b.show());
 
         b.set(1, "");
-        assert_eq!(["", "", "def"], b.slots.as_slice());
+        assert_eq!(["", "", "def"], b.snapshot().as_slice());
         assert_eq!("[||def]", b.show());
 
         b.set(0, "abc");
         b.set(1, "def");
         b.set(2, "ghi");
-        assert_eq!(["abc", "def", "ghi"], b.slots.as_slice());
+        assert_eq!(["abc", "def", "ghi"], b.snapshot().as_slice());
         assert_eq!("[abc|def|ghi]", b.show());
 
         b.clear(0);
-        assert_eq!(["   ", "def", "ghi"], b.slots.as_slice());
+        assert_eq!(["   ", "def", "ghi"], b.snapshot().as_slice());
         assert_eq!("[   |def|ghi]", b.show());
 
         b.expire(1);
-        assert_eq!(["   ", "___", "ghi"], b.slots.as_slice());
+        assert_eq!(["   ", "___", "ghi"], b.snapshot().as_slice());
         assert_eq!("[   |___|ghi]", b.show());
     }
+
+    #[test]
+    fn initial() {
+        let initial = ["a", "", "c"].map(String::from);
+        let mut b = Bar::new_with_initial(initial, "[", "|", "]", ' ', '_');
+        assert_eq!("[a||c]", b.show());
+        assert_eq!(vec!["a", "", "c"], b.snapshot());
+
+        b.set(1, "b");
+        assert_eq!("[a|b|c]", b.show());
+        assert_eq!(vec!["a", "b", "c"], b.snapshot());
+
+        let b = Bar::new_with_initial([], "[", "|", "]", ' ', '_');
+        assert!(b.snapshot().is_empty());
+    }
+
+    #[test]
+    fn cache_invalidation() {
+        let mut b = Bar::new(2, "[", "|", "]", ' ', '_');
+        assert_eq!(Some("[|]"), b.show_unshown());
+        assert_eq!(None, b.show_unshown());
+        assert!(b.cached);
+
+        b.set(0, "ab");
+        assert!(!b.cached);
+        assert_eq!(Some("[ab|]"), b.show_unshown());
+        assert_eq!(None, b.show_unshown());
+
+        b.set(1, "cd");
+        assert_eq!(Some("[ab|cd]"), b.show_unshown());
+
+        b.clear(0);
+        assert!(!b.cached);
+        assert_eq!(Some("[  |cd]"), b.show_unshown());
+
+        b.expire(1);
+        assert!(!b.cached);
+        assert_eq!(Some("[  |__]"), b.show_unshown());
+
+        // Showing without marking as shown must not lose the update:
+        b.set(0, "x");
+        assert_eq!("[x|__]", b.show());
+        assert_eq!(Some("[x|__]"), b.show_unshown());
+        assert_eq!(None, b.show_unshown());
+    }
+
+    #[test]
+    fn slot_meta() {
+        let mut b = Bar::new(2, "[", "|", "]", ' ', '_');
+        assert_eq!(0, b.slot_meta(0).version_count);
+        assert_eq!(0, b.slot_meta(1).version_count);
+
+        b.set(0, "a");
+        let first = *b.slot_meta(0);
+        assert_eq!(1, first.version_count);
+        b.set(0, "b");
+        assert_eq!(2, b.slot_meta(0).version_count);
+        assert!(first.updated_at <= b.slot_meta(0).updated_at);
+        b.set(0, "b");
+        assert_eq!(3, b.slot_meta(0).version_count);
+        assert_eq!(0, b.slot_meta(1).version_count);
+
+        // Not updates by the feed:
+        let before = *b.slot_meta(0);
+        b.clear(0);
+        b.expire(0);
+        assert_eq!(before, *b.slot_meta(0));
+    }
+
+    #[test]
+    fn separator() {
+        let initial = ["a", "b", "c"].map(String::from);
+        let mut b = Bar::new_with_initial(initial, "[", "|", "]", ' ', '_');
+        assert_eq!(Some("[a|b|c]"), b.show_unshown());
+        b.set_separator(" :: ".to_string());
+        assert_eq!(Some("[a :: b :: c]"), b.show_unshown());
+        b.set_separator(" :: ".to_string());
+        assert_eq!(None, b.show_unshown());
+        b.set_separator(String::new());
+        assert_eq!(Some("[abc]"), b.show_unshown());
+    }
+
+    #[test]
+    fn visibility() {
+        let mut b = Bar::new_with_initial(
+            ["a", "b", "c"].map(String::from),
+            "[",
+            "|",
+            "]",
+            ' ',
+            '_',
+        );
+        let expected = [
+            // Hidden: none, 0, 1, 0+1, 2, 0+2, 1+2, all.
+            "[a|b|c]", "[b|c]", "[a|c]", "[c]", "[a|b]", "[b]", "[a]", "[]",
+        ];
+        for (hidden, expected) in expected.iter().enumerate() {
+            for i in 0..3 {
+                if hidden & (1 << i) == 0 {
+                    b.show_slot(i);
+                } else {
+                    b.hide_slot(i);
+                }
+            }
+            assert_eq!(*expected, b.show(), "hidden: {:03b}", hidden);
+        }
+
+        // Visibility changes are new output, but non-changes are not.
+        for i in 0..3 {
+            b.show_slot(i);
+        }
+        assert_eq!(Some("[a|b|c]"), b.show_unshown());
+        b.show_slot(1);
+        assert_eq!(None, b.show_unshown());
+        b.hide_slot(1);
+        assert_eq!(Some("[a|c]"), b.show_unshown());
+        b.hide_slot(1);
+        assert_eq!(None, b.show_unshown());
+
+        // Hidden slots are still updated.
+        b.set(1, "x");
+        assert_eq!(None, b.show_unshown());
+        b.show_slot(1);
+        assert_eq!(Some("[a|x|c]"), b.show_unshown());
+    }
+
+    #[test]
+    fn resize() {
+        let initial = ["a", "b", "c"].map(String::from);
+        let mut b = Bar::new_with_initial(initial, "[", "|", "]", ' ', '_');
+        b.set(1, "x");
+        b.hide_slot(2);
+        assert_eq!(Some("[a|x]"), b.show_unshown());
+
+        b.resize(3);
+        assert_eq!(None, b.show_unshown());
+
+        b.resize(5);
+        assert_eq!(5, b.len());
+        assert_eq!(["a", "x", "c", "", ""], b.snapshot().as_slice());
+        assert_eq!(Some("[a|x||]"), b.show_unshown());
+        assert_eq!(1, b.slot_meta(1).version_count);
+        assert_eq!(0, b.slot_meta(4).version_count);
+
+        b.resize(2);
+        assert_eq!(["a", "x"], b.snapshot().as_slice());
+        assert_eq!(Some("[a|x]"), b.show_unshown());
+        assert_eq!(1, b.slot_meta(1).version_count);
+
+        b.resize(3);
+        assert_eq!(["a", "x", ""], b.snapshot().as_slice());
+        assert_eq!(Some("[a|x|]"), b.show_unshown());
+
+        b.resize(0);
+        assert!(b.is_empty());
+        assert_eq!(Some("[]"), b.show_unshown());
+    }
+
+    #[test]
+    fn insert_and_remove_slot() {
+        let initial = ["a", "b", "c"].map(String::from);
+        let mut b = Bar::new_with_initial(initial, "[", "|", "]", ' ', '_');
+        b.hide_slot(1);
+        assert_eq!(Some("[a|c]"), b.show_unshown());
+
+        b.insert_slot(1);
+        assert_eq!(["a", "", "b", "c"], b.snapshot().as_slice());
+        assert_eq!(Some("[a||c]"), b.show_unshown());
+        b.set(1, "n");
+        assert_eq!(Some("[a|n|c]"), b.show_unshown());
+
+        b.insert_slot(4);
+        b.set(4, "z");
+        assert_eq!(Some("[a|n|c|z]"), b.show_unshown());
+
+        b.remove_slot(0);
+        assert_eq!(["n", "b", "c", "z"], b.snapshot().as_slice());
+        assert_eq!(Some("[n|c|z]"), b.show_unshown());
+        b.remove_slot(1);
+        assert_eq!(["n", "c", "z"], b.snapshot().as_slice());
+        assert_eq!(Some("[n|c|z]"), b.show_unshown());
+    }
+
+    #[test]
+    fn truncated() {
+        let initial = ["abc", "de", "fgh"].map(String::from);
+        let mut b = Bar::new_with_initial(initial, "[", "|", "]", ' ', '_');
+        assert_eq!("[abc|de|fgh]", b.show_truncated(12, "..."));
+        assert_eq!("[abc|de...]", b.show_truncated(11, "..."));
+        assert_eq!("[abc...]", b.show_truncated(10, "..."));
+        assert_eq!("[abc...]", b.show_truncated(8, "..."));
+        assert_eq!("[...]", b.show_truncated(7, "..."));
+        assert_eq!("[..", b.show_truncated(3, "..."));
+
+        // Hidden slots take no space.
+        b.hide_slot(0);
+        assert_eq!("[de|fgh]", b.show_truncated(8, "..."));
+
+        // Wide characters take 2 columns each.
+        let initial = ["日本", "語"].map(String::from);
+        let b = Bar::new_with_initial(initial, "", " ", "", ' ', '_');
+        assert_eq!(7, b.show_truncated(7, "").width());
+        assert_eq!("日本…", b.show_truncated(6, "…"));
+        assert_eq!("…", b.show_truncated(4, "…"));
+
+        // Applied by show, with the suffix from the config.
+        let initial = ["abc", "de", "fgh"].map(String::from);
+        let mut b = Bar::new_with_initial(initial, "[", "|", "]", ' ', '_');
+        assert_eq!(Some("[abc|de|fgh]"), b.show_unshown());
+        b.set_max_len(Some(11), "+");
+        assert_eq!(Some("[abc|de+]"), b.show_unshown());
+        b.set(1, "d");
+        assert_eq!(Some("[abc|d|fgh]"), b.show_unshown());
+    }
 }
@@ -1,6 +1,7 @@
 use std::{
-    collections::HashSet,
+    collections::{HashSet, VecDeque},
     fmt::Debug,
+    future::Future,
     io,
     path::{Path, PathBuf},
     result,
@@ -16,19 +17,33 @@ use tokio::{
         oneshot, Notify,
     },
     task::{JoinHandle, JoinSet},
+    time::Instant,
 };
 use tracing::Instrument;
 
 use crate::{
-    bar::{self, feed::Feed},
-    conf::{self, Conf},
+    bar::{
+        self,
+        feed::{self, Feed, Systemd},
+    },
+    circuit_breaker::{CircuitBreaker, State as CircuitBreakerState},
+    conf::{self, Conf, ConfDiff},
     ps,
     x11::X11,
 };
 
 use super::Bar;
 
+/// Consecutive X11 output failures after which to stop trying for a while.
+const X11_FAILURES_TO_OPEN: u32 = 5;
+const X11_RESET_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How long a panicking server waits for its feeds to be killed.
+const DROP_KILL_TIMEOUT: Duration = Duration::from_secs(2);
+
 pub type ApiSender = UnboundedSender<Api>;
+/// Receives the bar in place of conf's dst, every time it changes.
+pub type OnUpdate = Box<dyn Fn(&str) + Send + Sync>;
 pub type ApiReceiver = UnboundedReceiver<Api>;
 pub type ApiResult<T> = result::Result<T, ApiError>;
 
@@ -37,6 +52,15 @@ pub enum ApiError {
     #[error("Bar server operation failed: {0:?}")]
     OpFailed(#[from] anyhow::Error),
 
+    #[error("Bar server config failed: {0:?}")]
+    ConfFailed(anyhow::Error),
+
+    #[error("Feed not found at position: {pos}")]
+    FeedNotFound { pos: usize },
+
+    #[error("Bar server operation timed out: {op}")]
+    Timeout { op: String },
+
     #[error("Bar server is dead")]
     Dead(#[from] tokio::sync::mpsc::error::SendError<Api>),
 
@@ -55,21 +79,67 @@ enum Msg {
     On(oneshot::Sender<anyhow::Result<()>>),
     Off(oneshot::Sender<()>),
     Status(oneshot::Sender<anyhow::Result<bar::status::Status>>),
-    Reconf(oneshot::Sender<anyhow::Result<()>>),
+    /// Liveness check, without the I/O of status.
+    Ping(oneshot::Sender<()>),
+    GetConf(oneshot::Sender<Conf>),
+    GetFeedConf {
+        pos: usize,
+        reply_tx: oneshot::Sender<ApiResult<conf::Feed>>,
+    },
+    SetSeparator(String, oneshot::Sender<()>),
+    /// Current slot contents, without the I/O of status.
+    SnapshotBar(oneshot::Sender<Vec<String>>),
+    /// Write the bar now, even if it was already written as it is.
+    ForceOutput(oneshot::Sender<anyhow::Result<()>>),
+    /// Replies whether the config was applied to the running bar, rather
+    /// than just loaded for the next on.
+    Reconf(oneshot::Sender<anyhow::Result<bool>>),
+    ClearFeedLog {
+        pos: usize,
+        reply_tx: oneshot::Sender<ApiResult<()>>,
+    },
+    SearchFeedLog {
+        pos: usize,
+        pattern: String,
+        max_lines: usize,
+        reply_tx: oneshot::Sender<ApiResult<Vec<String>>>,
+    },
     FeedExit {
         pos: usize,
         result: io::Result<std::process::ExitStatus>,
+        /// Exited because we killed it.
+        expected: bool,
     },
     Expiration {
         pos: usize,
     },
+    /// Auto-restart of a feed which exited unexpectedly, after a backoff.
+    Restart {
+        pos: usize,
+    },
     Input {
         pos: usize,
-        data: String,
+        /// Of the feed which sent it, see Feed::get_id.
+        id: u64,
+        data: Arc<String>,
     },
     Output,
 }
 
+/// Give up waiting on the bar server after the given duration.
+pub async fn with_timeout<T, F>(
+    op: &str,
+    duration: Duration,
+    fut: F,
+) -> ApiResult<T>
+where
+    F: Future<Output = ApiResult<T>>,
+{
+    tokio::time::timeout(duration, fut)
+        .await
+        .map_err(|_| ApiError::Timeout { op: op.to_string() })?
+}
+
 pub async fn on(api_tx: &ApiSender) -> ApiResult<()> {
     let (reply_tx, reply_rx) = oneshot::channel();
     api_tx.send(Api {
@@ -97,29 +167,127 @@ pub async fn status(api_tx: &ApiSender) -> ApiResult<bar::status::Status> {
     Ok(status)
 }
 
+pub async fn ping(api_tx: &ApiSender) -> ApiResult<()> {
+    let (reply_tx, reply_rx) = oneshot::channel();
+    api_tx.send(Api {
+        msg: Msg::Ping(reply_tx),
+    })?;
+    reply_rx.await?;
+    Ok(())
+}
+
+/// The config currently in use, which may differ from the file, if it was
+/// changed since last (re)loaded.
+pub async fn get_conf(api_tx: &ApiSender) -> ApiResult<Conf> {
+    let (reply_tx, reply_rx) = oneshot::channel();
+    api_tx.send(Api {
+        msg: Msg::GetConf(reply_tx),
+    })?;
+    let conf = reply_rx.await?;
+    Ok(conf)
+}
+
+/// Config of the feed at the given position, as currently in use.
+pub async fn get_feed_conf(
+    api_tx: &ApiSender,
+    pos: usize,
+) -> ApiResult<conf::Feed> {
+    let (reply_tx, reply_rx) = oneshot::channel();
+    api_tx.send(Api {
+        msg: Msg::GetFeedConf { pos, reply_tx },
+    })?;
+    let feed = reply_rx.await??;
+    Ok(feed)
+}
+
+/// Lasts until the next reload, which reverts to the config file's.
+pub async fn set_separator(api_tx: &ApiSender, sep: String) -> ApiResult<()> {
+    let (reply_tx, reply_rx) = oneshot::channel();
+    api_tx.send(Api {
+        msg: Msg::SetSeparator(sep, reply_tx),
+    })?;
+    reply_rx.await?;
+    Ok(())
+}
+
+pub async fn snapshot_bar(api_tx: &ApiSender) -> ApiResult<Vec<String>> {
+    let (reply_tx, reply_rx) = oneshot::channel();
+    api_tx.send(Api {
+        msg: Msg::SnapshotBar(reply_tx),
+    })?;
+    let slots = reply_rx.await?;
+    Ok(slots)
+}
+
+pub async fn force_output(api_tx: &ApiSender) -> ApiResult<()> {
+    let (reply_tx, reply_rx) = oneshot::channel();
+    api_tx.send(Api {
+        msg: Msg::ForceOutput(reply_tx),
+    })?;
+    reply_rx.await??;
+    Ok(())
+}
+
+/// When on, restarts only the feeds whose config changed. When off, turns
+/// on with the new config.
 pub async fn reload(api_tx: &ApiSender) -> ApiResult<()> {
-    off(api_tx).await?;
-    reconf(api_tx).await?;
-    on(api_tx).await?;
+    if !reconf(api_tx).await? {
+        on(api_tx).await?;
+    }
     Ok(())
 }
 
-async fn reconf(api_tx: &ApiSender) -> ApiResult<()> {
+/// Re-reads the config. Applied to the running bar right away, otherwise
+/// only loaded for the next on, which is what the returned bool tells.
+pub async fn reconf(api_tx: &ApiSender) -> ApiResult<bool> {
     let (reply_tx, reply_rx) = oneshot::channel();
     api_tx.send(Api {
         msg: Msg::Reconf(reply_tx),
     })?;
+    let applied = reply_rx.await?.map_err(ApiError::ConfFailed)?;
+    Ok(applied)
+}
+
+pub async fn clear_feed_log(api_tx: &ApiSender, pos: usize) -> ApiResult<()> {
+    let (reply_tx, reply_rx) = oneshot::channel();
+    api_tx.send(Api {
+        msg: Msg::ClearFeedLog { pos, reply_tx },
+    })?;
     reply_rx.await??;
     Ok(())
 }
 
+pub async fn search_feed_log(
+    api_tx: &ApiSender,
+    pos: usize,
+    pattern: String,
+    max_lines: usize,
+) -> ApiResult<Vec<String>> {
+    let (reply_tx, reply_rx) = oneshot::channel();
+    api_tx.send(Api {
+        msg: Msg::SearchFeedLog {
+            pos,
+            pattern,
+            max_lines,
+            reply_tx,
+        },
+    })?;
+    let lines = reply_rx.await??;
+    Ok(lines)
+}
+
 pub fn feed_data(
     api_tx: &ApiSender,
     pos: usize,
+    id: u64,
     data: String,
 ) -> ApiResult<()> {
     api_tx.send(Api {
-        msg: Msg::Input { pos, data },
+        msg: Msg::Input {
+            pos,
+            id,
+            data: Arc::new(data),
+        },
     })?;
     Ok(())
 }
@@ -128,23 +296,40 @@ pub fn feed_exit(
     api_tx: &ApiSender,
     pos: usize,
     result: io::Result<std::process::ExitStatus>,
+    expected: bool,
 ) -> ApiResult<()> {
     api_tx.send(Api {
-        msg: Msg::FeedExit { pos, result },
+        msg: Msg::FeedExit {
+            pos,
+            result,
+            expected,
+        },
     })?;
     Ok(())
 }
 
-pub async fn start(
+pub fn start(
+    siblings: &mut JoinSet<anyhow::Result<()>>,
+    dir: &Path,
+    conf: Conf,
+) -> ApiSender {
+    start_with(siblings, dir, conf, None)
+}
+
+/// Same as start, but with the bar given to on_update, if any, instead of
+/// written to conf's dst.
+pub fn start_with(
     siblings: &mut JoinSet<anyhow::Result<()>>,
     dir: &Path,
-) -> anyhow::Result<ApiSender> {
-    let conf = Conf::load_or_init(dir).await?;
+    conf: Conf,
+    on_update: Option<OnUpdate>,
+) -> ApiSender {
     let (tx, rx) = mpsc::unbounded_channel();
     siblings.spawn(
-        run(tx.clone(), rx, dir.to_path_buf(), conf).in_current_span(),
+        run(tx.clone(), rx, dir.to_path_buf(), conf, on_update)
+            .in_current_span(),
     );
-    Ok(tx)
+    tx
 }
 
 #[tracing::instrument(name = "bar", skip_all)]
@@ -153,52 +338,251 @@ async fn run(
     mut rx: ApiReceiver,
     dir: PathBuf,
     conf: Conf,
+    on_update: Option<OnUpdate>,
 ) -> anyhow::Result<()> {
     tracing::info!("Starting");
     tracing::debug!("Initial conf: {:#?}", conf);
-    let mut server = Server::new(conf, dir, tx);
+    let snapshot = if conf.persist_state.unwrap_or(false) {
+        take_bar_snapshot(&dir).await.unwrap_or_else(|error| {
+            tracing::error!(?error, "Failed to restore bar snapshot.");
+            None
+        })
+    } else {
+        None
+    };
+    let batch_size = conf.get_batch_size();
+    let mut server = Server::new(conf, dir, tx, snapshot);
+    server.systemd = Systemd::detect().await;
+    server.on_update = on_update;
     while let Some(Api { msg }) = rx.recv().await {
-        server.handle(msg).await?;
+        let mut msgs = vec![msg];
+        while msgs.len() < batch_size {
+            match rx.try_recv() {
+                Ok(Api { msg }) => msgs.push(msg),
+                Err(_) => break,
+            }
+        }
+        server.handle_batch(msgs).await?;
+    }
+    Ok(())
+}
+
+/// Runs all futures concurrently, returning their results in the same
+/// order as the futures were given. Results are awaited in full rather than
+/// aborted on first error, since an aborted feed start could leave behind
+/// a process which was spawned but not yet tracked.
+async fn join_ordered<T, F>(futs: Vec<F>) -> Vec<anyhow::Result<T>>
+where
+    T: Send + 'static,
+    F: Future<Output = anyhow::Result<T>> + Send + 'static,
+{
+    let mut results: Vec<Option<anyhow::Result<T>>> =
+        futs.iter().map(|_| None).collect();
+    let mut tasks = JoinSet::new();
+    for (pos, fut) in futs.into_iter().enumerate() {
+        tasks.spawn(async move { (pos, fut.await) }.in_current_span());
+    }
+    while let Some(joined) = tasks.join_next().await {
+        match joined {
+            Ok((pos, result)) => results[pos] = Some(result),
+            Err(error) => tracing::error!(?error, "Task failed to finish."),
+        }
+    }
+    results
+        .into_iter()
+        .map(|result| {
+            result.unwrap_or_else(|| Err(anyhow!("Task failed to finish.")))
+        })
+        .collect()
+}
+
+async fn save_bar_snapshot(dir: &Path, bar: &Bar) -> anyhow::Result<()> {
+    let file = conf::path_bar_snapshot(dir);
+    if let Some(parent) = file.parent() {
+        fs::create_dir_all(parent).await?;
     }
+    let data = serde_json::to_string(&bar.snapshot())?;
+    fs::write(&file, data).await?;
+    tracing::debug!(?file, "Saved bar snapshot.");
     Ok(())
 }
 
+/// Reads and removes the snapshot file, so that a stale snapshot is never
+/// restored twice.
+async fn take_bar_snapshot(
+    dir: &Path,
+) -> anyhow::Result<Option<Vec<String>>> {
+    let file = conf::path_bar_snapshot(dir);
+    if !fs::try_exists(&file).await? {
+        return Ok(None);
+    }
+    let data = fs::read_to_string(&file).await?;
+    fs::remove_file(&file).await?;
+    let slots: Vec<String> = serde_json::from_str(&data)?;
+    tracing::debug!(?file, "Restored bar snapshot.");
+    Ok(Some(slots))
+}
+
 // TODO Move data fields from Server to appropriate State variants.
 #[derive(Debug)]
 enum State {
     On,
-    Offing { notify: Arc<Notify> },
+    Offing {
+        notify: Arc<Notify>,
+        /// Positions of the feeds which are yet to exit.
+        remaining: HashSet<usize>,
+    },
     Off,
 }
 
+/// Recent input times of each feed.
+struct FeedActivityTracker {
+    inputs: Vec<VecDeque<Instant>>,
+}
+
+impl FeedActivityTracker {
+    const WINDOW: usize = 10;
+
+    fn new(n: usize) -> Self {
+        Self {
+            inputs: vec![VecDeque::with_capacity(Self::WINDOW); n],
+        }
+    }
+
+    fn resize(&mut self, n: usize) {
+        self.inputs.resize(n, VecDeque::with_capacity(Self::WINDOW));
+    }
+
+    fn forget(&mut self, pos: usize) {
+        self.inputs[pos].clear();
+    }
+
+    fn record(&mut self, pos: usize, at: Instant) {
+        let inputs = &mut self.inputs[pos];
+        if inputs.len() == Self::WINDOW {
+            inputs.pop_front();
+        }
+        inputs.push_back(at);
+    }
+
+    /// Average time between recent inputs of the feed. None until it had
+    /// at least 2 inputs.
+    fn avg_interval(&self, pos: usize) -> Option<Duration> {
+        let inputs = &self.inputs[pos];
+        match (inputs.front(), inputs.back()) {
+            (Some(first), Some(last)) if inputs.len() > 1 => {
+                let intervals = u32::try_from(inputs.len() - 1).ok()?;
+                Some(last.duration_since(*first) / intervals)
+            }
+            _ => None,
+        }
+    }
+
+    /// Average interval of the most active feed.
+    fn min_avg_interval(&self) -> Option<Duration> {
+        (0..self.inputs.len())
+            .filter_map(|pos| self.avg_interval(pos))
+            .min()
+    }
+}
+
+/// Reload of a running bar, waiting for the feeds it stopped to exit.
+struct PendingReconf {
+    conf: Conf,
+    diff: ConfDiff,
+    /// Positions in the old config.
+    stopping: Vec<usize>,
+    /// Bar contents from before the feeds were stopped, if to be kept.
+    preserved: Option<Vec<String>>,
+    client: oneshot::Sender<anyhow::Result<bool>>,
+}
+
 struct Server {
     self_tx: ApiSender,
     dir: PathBuf,
+    /// Fixed at start, like the other locations.
+    feeds_dir: PathBuf,
     conf: Conf,
     state: State,
     bar: Bar,
     feeds: Vec<Option<Feed>>,
+    /// Of the feed last started at each position, which is the only one
+    /// whose inputs are still for it. Those of a feed which a reload or
+    /// restart replaced may still arrive after it.
+    feed_ids: Vec<Option<u64>>,
     expiration_timers: Vec<Option<JoinHandle<()>>>,
+    /// Auto-restarts of each feed since the bar was turned on, or the
+    /// feed's config changed.
+    restarts: Vec<u32>,
+    restart_timers: Vec<Option<JoinHandle<()>>>,
     output_timer: Option<JoinHandle<()>>,
     output_interval: Duration,
+    activity: FeedActivityTracker,
     x11: Option<X11>,
+    x11_breaker: CircuitBreaker,
+    on_update: Option<OnUpdate>,
+    /// Detected at start, for feeds with use_systemd_run.
+    systemd: Option<Systemd>,
+    /// Bar contents from right before the last off, before the feeds'
+    /// on_stop actions changed them.
+    snapshot_before_off: Option<Vec<String>>,
+    /// Reconfigured since last on, i.e. in the middle of a reload.
+    reconfed: bool,
+    pending_reconf: Option<PendingReconf>,
+    /// Reloads requested during the pending one, to be done after it, in
+    /// turn, rather than failing on it.
+    queued_reconfs: VecDeque<oneshot::Sender<anyhow::Result<bool>>>,
+    /// Consecutive failed writes to dst. Reset by a successful write.
+    output_error_count: u64,
+    last_output_error: Option<String>,
+    /// Timer tasks which panicked, since the server started.
+    total_timer_panics: u64,
 }
 
 impl Server {
-    fn new(conf: Conf, dir: PathBuf, self_tx: ApiSender) -> Self {
-        let bar = Bar::from_conf(&conf);
-        let output_interval = Duration::from_secs_f64(conf.output_interval);
+    fn new(
+        mut conf: Conf,
+        dir: PathBuf,
+        self_tx: ApiSender,
+        snapshot: Option<Vec<String>>,
+    ) -> Self {
+        conf.sort_feeds_by_priority();
+        let bar = match snapshot {
+            None => Bar::from_conf(&conf),
+            Some(slots) => Bar::from_conf_with_initial(&conf, slots),
+        };
+        let output_interval = conf.get_output_interval();
+        let activity = FeedActivityTracker::new(conf.feeds.len());
+        let feeds_dir = conf.get_feeds_dir(&dir);
         let mut selph = Self {
             self_tx,
             dir,
+            feeds_dir,
             conf,
             state: State::Off,
             bar,
             feeds: Vec::new(),
+            feed_ids: Vec::new(),
             expiration_timers: Vec::new(),
+            restarts: Vec::new(),
+            restart_timers: Vec::new(),
             output_interval,
+            activity,
             output_timer: None,
             x11: None,
+            x11_breaker: CircuitBreaker::new(
+                X11_FAILURES_TO_OPEN,
+                X11_RESET_TIMEOUT,
+            ),
+            output_error_count: 0,
+            last_output_error: None,
+            total_timer_panics: 0,
+            on_update: None,
+            systemd: None,
+            snapshot_before_off: None,
+            reconfed: false,
+            pending_reconf: None,
+            queued_reconfs: VecDeque::new(),
         };
         selph.ensure_output_scheduled();
         selph
@@ -206,70 +590,423 @@ impl Server {
 
     async fn output(&mut self) {
         if let Some(data) = self.bar.show_unshown() {
-            self.output_data(&data).await;
+            let result = Self::output_data(
+                &self.conf,
+                &mut self.x11,
+                &mut self.x11_breaker,
+                self.on_update.as_ref(),
+                data,
+            )
+            .await;
+            self.record_output_result(&result);
         }
     }
 
+    /// The scheduled output, if any, is left as is, since it will find
+    /// the bar already shown and skip the write.
+    async fn output_forced(&mut self) -> anyhow::Result<()> {
+        let result = Self::output_data(
+            &self.conf,
+            &mut self.x11,
+            &mut self.x11_breaker,
+            self.on_update.as_ref(),
+            self.bar.show_anyway(),
+        )
+        .await;
+        self.record_output_result(&result);
+        result.map(|_| ())
+    }
+
     async fn output_blank(&mut self) {
-        self.output_data("").await;
+        let result = Self::output_data(
+            &self.conf,
+            &mut self.x11,
+            &mut self.x11_breaker,
+            self.on_update.as_ref(),
+            "",
+        )
+        .await;
+        self.record_output_result(&result);
+    }
+
+    fn record_output_result(&mut self, result: &anyhow::Result<bool>) {
+        match result {
+            Ok(true) => self.output_error_count = 0,
+            Ok(false) => {}
+            Err(error) => {
+                self.output_error_count += 1;
+                self.last_output_error = Some(format!("{:#}", error));
+            }
+        }
     }
 
-    async fn output_data(&mut self, data: &str) {
-        let result: anyhow::Result<()> = async {
-            match self.conf.get_dst() {
+    /// Whether written, rather than skipped while x11_breaker is open.
+    // Takes the fields it needs, rather than &mut self, so that data can
+    // be borrowed from self.bar without a copy.
+    async fn output_data(
+        conf: &Conf,
+        x11_slot: &mut Option<X11>,
+        x11_breaker: &mut CircuitBreaker,
+        on_update: Option<&OnUpdate>,
+        data: &str,
+    ) -> anyhow::Result<bool> {
+        if let Some(on_update) = on_update {
+            on_update(data);
+            return Ok(true);
+        }
+        let result: anyhow::Result<bool> = async {
+            match conf.get_dst() {
                 conf::Dst::StdOut => println!("{}", &data),
                 conf::Dst::StdErr => eprintln!("{}", &data),
                 conf::Dst::File { path } => fs::write(path, data).await?,
                 conf::Dst::X11RootWindowName => {
-                    if self.x11.is_none() {
-                        self.x11 = Some(X11::init()?);
+                    let before = x11_breaker.state();
+                    if !x11_breaker.allow() {
+                        // Not a failure of its own, the one which opened
+                        // the breaker was already reported.
+                        tracing::debug!("X11 output skipped.");
+                        return Ok(false);
                     }
-                    let x11 = self.x11.take().unwrap_or_else(|| {
-                        unreachable!(
-                            "X11 failure should have caused a return above."
-                        );
-                    });
-                    x11.set_root_window_name(data)?;
-                    self.x11.replace(x11);
+                    let result = Self::output_x11(x11_slot, data);
+                    match result {
+                        Ok(()) => x11_breaker.record_success(),
+                        Err(_) => x11_breaker.record_failure(),
+                    }
+                    match (before, x11_breaker.state()) {
+                        (before, after) if before == after => {}
+                        (_, CircuitBreakerState::Open) => {
+                            tracing::warn!(
+                                timeout = ?X11_RESET_TIMEOUT,
+                                "X11 output failing. Pausing it."
+                            );
+                        }
+                        (_, CircuitBreakerState::Closed) => {
+                            tracing::info!("X11 output resumed.");
+                        }
+                        (_, CircuitBreakerState::HalfOpen) => {}
+                    }
+                    result?;
                 }
             }
-            Ok(())
+            Ok(true)
         }
         .await;
-        if let Err(error) = result {
+        if let Err(error) = &result {
             tracing::error!(?error, "Output failed");
             // TODO Post notification.
         }
+        result
+    }
+
+    fn output_x11(
+        x11_slot: &mut Option<X11>,
+        data: &str,
+    ) -> anyhow::Result<()> {
+        if x11_slot.is_none() {
+            *x11_slot = Some(X11::init()?);
+        }
+        let result = match x11_slot.as_mut() {
+            Some(x11) => x11.set_root_window_name(data),
+            None => {
+                unreachable!("X11 failure should have caused a return above.")
+            }
+        };
+        if let Err(error) = result {
+            // Such as after a protocol error, which closes the connection.
+            // A lost connection can't be recovered from this way, see
+            // x11::on_io_error.
+            tracing::warn!(?error, "X11 output failed. Reconnecting.");
+            // Not keeping the closed one, in case reconnecting fails too.
+            x11_slot.take();
+            let x11 = x11_slot.insert(X11::init()?);
+            x11.set_root_window_name(data)?;
+        }
+        Ok(())
     }
 
     async fn on(&mut self) -> anyhow::Result<()> {
-        self.bar = Bar::from_conf(&self.conf);
+        let reloading = std::mem::take(&mut self.reconfed);
+        let preserved = self
+            .snapshot_before_off
+            .take()
+            .filter(|_| reloading && self.conf.get_preserve_on_reload());
+        // Keeping what was shown so far, until the (re)started feeds
+        // produce something new.
+        match preserved {
+            Some(slots) => {
+                self.bar = Bar::from_conf_with_initial(&self.conf, slots);
+            }
+            None if self.conf.clear_on_start.unwrap_or(false) => {
+                self.bar = Bar::from_conf(&self.conf);
+            }
+            // Same config as the bar was made from, so only the number of
+            // slots may need adjusting, which also keeps their visibility.
+            None if !reloading => {
+                self.bar.resize(self.conf.feeds.len());
+            }
+            None => {
+                self.bar = Bar::from_conf_with_initial(
+                    &self.conf,
+                    self.bar.snapshot(),
+                );
+            }
+        }
         self.feeds = Vec::new();
+        self.activity = FeedActivityTracker::new(self.conf.feeds.len());
         self.expiration_timers = Vec::new();
-        let conf = self.conf.clone();
-        for (pos, feed_cfg) in conf.feeds.iter().enumerate() {
-            let feed_dir =
-                conf::path_feed_dir(&self.dir, pos, &feed_cfg.name);
-            let feed =
-                Feed::start(feed_cfg, &feed_dir, pos, self.self_tx.clone())
-                    .await?;
-            self.feeds.push(Some(feed));
+        self.restarts = vec![0; self.conf.feeds.len()];
+        self.restart_timers.clear();
+        self.restart_timers
+            .resize_with(self.conf.feeds.len(), || None);
+        let positions: Vec<usize> = (0..self.conf.feeds.len()).collect();
+        let mut first_error = None;
+        for (pos, result) in join_ordered(self.feed_starts(&positions))
+            .await
+            .into_iter()
+            .enumerate()
+        {
+            match result {
+                Ok(feed) => self.feeds.push(Some(feed)),
+                Err(error) => {
+                    tracing::error!(pos, ?error, "Feed start failed.");
+                    self.feeds.push(None);
+                    first_error.get_or_insert(error);
+                }
+            }
             self.expiration_timers.push(None);
+        }
+        self.feed_ids = self
+            .feeds
+            .iter()
+            .map(|feed| feed.as_ref().map(Feed::get_id))
+            .collect();
+        if let Some(error) = first_error {
+            // Turning off whatever did start, through the usual path, so
+            // that their exits are handled as expected ones.
+            if self.feeds.iter().any(Option::is_some) {
+                self.off_begin().await;
+            }
+            return Err(error);
+        }
+        for pos in 0..self.feeds.len() {
             self.reschedule_expiration(pos);
-            self.ensure_output_scheduled();
         }
+        self.ensure_output_scheduled();
         self.state = State::On;
+        if reloading && self.conf.get_output_on_reload() {
+            self.output().await;
+        }
         Ok(())
     }
 
-    fn off_begin(&mut self) -> Arc<Notify> {
-        tracing::info!("Shutdown begin.");
-        for feed in self.feeds.iter().filter_map(|x| x.as_ref()) {
+    /// Starts of the feeds at the given positions, to be joined together.
+    fn feed_starts(
+        &self,
+        positions: &[usize],
+    ) -> Vec<impl Future<Output = anyhow::Result<Feed>> + Send + 'static>
+    {
+        positions
+            .iter()
+            .map(|&pos| {
+                let feed_cfg = self.conf.feeds[pos].clone();
+                let feed_dir = conf::path_feed_dir(
+                    &self.feeds_dir,
+                    feed_cfg.config_pos,
+                    &feed_cfg.name,
+                );
+                let timezone = self.conf.timezone.clone();
+                let systemd = self.systemd.clone();
+                let self_tx = self.self_tx.clone();
+                async move {
+                    Feed::start(
+                        &feed_cfg,
+                        &feed_dir,
+                        pos,
+                        timezone.as_deref(),
+                        systemd.as_ref(),
+                        self_tx,
+                    )
+                    .await
+                }
+            })
+            .collect()
+    }
+
+    /// Applies the config file to the running bar. Only the feeds which
+    /// differ are stopped, the rest of the reload waits for their exits.
+    async fn reconf_begin(
+        &mut self,
+        client: oneshot::Sender<anyhow::Result<bool>>,
+    ) {
+        if self.pending_reconf.is_some() {
+            tracing::info!("Already reloading. Queueing reload.");
+            self.queued_reconfs.push_back(client);
+            return;
+        }
+        let mut conf = match Conf::load_or_init(&self.dir).await {
+            Ok(conf) => conf,
+            Err(error) => {
+                reply(client, Err(error));
+                return;
+            }
+        };
+        conf.sort_feeds_by_priority();
+        let diff = Conf::diff(&self.conf, &conf);
+        tracing::info!(?diff, "Reload begin.");
+        let stopping: Vec<usize> = (0..self.feeds.len())
+            .filter(|pos| !diff.unchanged.contains(pos))
+            .collect();
+        let preserved = self
+            .conf
+            .get_preserve_on_reload()
+            .then(|| self.bar.snapshot());
+        for feed in
+            stopping.iter().filter_map(|&pos| self.feeds[pos].as_ref())
+        {
             feed.stop();
         }
+        // Those already exited are to be started by the reload instead.
+        for &pos in &stopping {
+            if let Some(timer) = self.restart_timers[pos].take() {
+                timer.abort();
+            }
+        }
+        self.pending_reconf = Some(PendingReconf {
+            conf,
+            diff,
+            stopping,
+            preserved,
+            client,
+        });
+        self.reconf_continue().await;
+    }
+
+    /// Finishes the pending reload, once all the feeds it stopped exited.
+    async fn reconf_continue(&mut self) {
+        let stopped = self.pending_reconf.as_ref().is_some_and(|pending| {
+            pending
+                .stopping
+                .iter()
+                .all(|&pos| self.feeds[pos].is_none())
+        });
+        if !stopped {
+            return;
+        }
+        let Some(PendingReconf {
+            conf,
+            diff,
+            stopping,
+            preserved,
+            client,
+        }) = self.pending_reconf.take()
+        else {
+            unreachable!()
+        };
+        let old_slots = self.bar.snapshot();
+        for &pos in &stopping {
+            if let Some(timer) = self.expiration_timers[pos].take() {
+                timer.abort();
+            }
+            self.activity.forget(pos);
+        }
+        // By name, since the stopped feeds may have moved, or not be in
+        // the new config at all.
+        let slots = Conf::match_feeds(&self.conf, &conf)
+            .into_iter()
+            .enumerate()
+            .map(|(pos, old_pos)| match old_pos {
+                Some(old_pos) if diff.unchanged.contains(&pos) => {
+                    old_slots[old_pos].clone()
+                }
+                Some(old_pos) => preserved
+                    .as_ref()
+                    .unwrap_or(&old_slots)
+                    .get(old_pos)
+                    .cloned()
+                    .unwrap_or_default(),
+                None => String::new(),
+            })
+            .collect();
+        self.conf = conf;
+        self.bar = Bar::from_conf_with_initial(&self.conf, slots);
+        let n = self.conf.feeds.len();
+        self.feeds.resize_with(n, || None);
+        self.feed_ids.resize_with(n, || None);
+        self.expiration_timers.resize_with(n, || None);
+        self.restarts.resize(n, 0);
+        self.restart_timers.resize_with(n, || None);
+        self.activity.resize(n);
+        let mut starting: Vec<usize> =
+            diff.added.into_iter().chain(diff.changed).collect();
+        starting.sort_unstable();
+        for &pos in &starting {
+            self.feed_ids[pos] = None;
+            self.restarts[pos] = 0;
+        }
+        let mut first_error = None;
+        let results = join_ordered(self.feed_starts(&starting)).await;
+        for (pos, result) in starting.into_iter().zip(results) {
+            match result {
+                Ok(feed) => {
+                    self.feed_ids[pos] = Some(feed.get_id());
+                    self.feeds[pos] = Some(feed);
+                    self.reschedule_expiration(pos);
+                }
+                Err(error) => {
+                    tracing::error!(pos, ?error, "Feed start failed.");
+                    first_error.get_or_insert(error);
+                }
+            }
+        }
+        self.ensure_output_scheduled();
+        if self.conf.get_output_on_reload() {
+            self.output().await;
+        }
+        tracing::info!("Reload done.");
+        reply(client, first_error.map_or(Ok(true), Err));
+        // Through the mailbox, rather than right away, to not recurse.
+        if let Some(client) = self.queued_reconfs.pop_front() {
+            if let Err(SendError(Api { msg })) = self.self_tx.send(Api {
+                msg: Msg::Reconf(client),
+            }) {
+                tracing::warn!(?msg, "Bar server gone before reload.");
+            }
+        }
+    }
+
+    async fn off_begin(&mut self) -> Arc<Notify> {
+        tracing::info!("Shutdown begin.");
+        let clients = self
+            .pending_reconf
+            .take()
+            .map(|pending| pending.client)
+            .into_iter()
+            .chain(self.queued_reconfs.drain(..));
+        for client in clients {
+            let result = Err(anyhow!("Turned off before reload finished."));
+            reply(client, result);
+        }
+        for timer in self.restart_timers.iter_mut().filter_map(Option::take) {
+            timer.abort();
+        }
+        self.snapshot_before_off = Some(self.bar.snapshot());
+        if self.conf.persist_state.unwrap_or(false) {
+            if let Err(error) = save_bar_snapshot(&self.dir, &self.bar).await
+            {
+                tracing::error!(?error, "Failed to save bar snapshot.");
+            }
+        }
+        let mut remaining = HashSet::new();
+        for (pos, feed) in self.feeds.iter().enumerate() {
+            if let Some(feed) = feed {
+                feed.stop();
+                remaining.insert(pos);
+            }
+        }
         let notify = Arc::new(Notify::new());
         self.state = State::Offing {
             notify: notify.clone(),
+            remaining,
         };
         notify
     }
@@ -295,13 +1032,19 @@ impl Server {
                 tracing::info!(pos, name, ?exit_status, "Feed stop success.");
             }
         }
-        feed.clean_up().await?;
-        self.bar.expire(pos);
+        feed.clean_up(self.conf.feeds[pos].get_stop_timeout())
+            .await?;
+        match self.conf.feeds[pos].get_on_stop() {
+            conf::OnStop::Clear => self.bar.clear(pos),
+            conf::OnStop::Expire => self.bar.expire(pos),
+            conf::OnStop::Keep => {}
+        }
         self.output().await;
-        let num_feeds_still_running =
-            self.feeds.iter().filter(|x| x.is_some()).count();
+        if let State::Offing { remaining, .. } = &mut self.state {
+            remaining.remove(&pos);
+        }
         match &self.state {
-            State::Offing { notify } if num_feeds_still_running == 0 => {
+            State::Offing { notify, remaining } if remaining.is_empty() => {
                 for timer in self.expiration_timers.drain(0..).flatten() {
                     timer.abort();
                 }
@@ -318,37 +1061,158 @@ impl Server {
         Ok(())
     }
 
+    async fn on_unexpected_exit(
+        &mut self,
+        pos: usize,
+        result: io::Result<std::process::ExitStatus>,
+    ) -> anyhow::Result<()> {
+        let cfg = self.conf.feeds[pos].clone();
+        tracing::error!(
+            pos,
+            name = cfg.name,
+            ?result,
+            "Unsolicited feed exit."
+        );
+        if self.conf.notify_on_unexpected_exit.unwrap_or(false) {
+            post_notification(
+                &format!("{} feed exited", crate::NAME!()),
+                &format!(
+                    "Feed {} {:?} exited: {:?}",
+                    pos + 1,
+                    cfg.name,
+                    result
+                ),
+            );
+        }
+        // To be restarted by the reload, with its new config.
+        let reloading = self
+            .pending_reconf
+            .as_ref()
+            .is_some_and(|pending| pending.stopping.contains(&pos));
+        self.off_feed(pos, result).await?;
+        if matches!(self.state, State::On)
+            && !reloading
+            && cfg.auto_restart == Some(true)
+        {
+            let restarts = self.restarts[pos];
+            if restarts >= cfg.get_max_restarts() {
+                tracing::error!(
+                    pos,
+                    name = cfg.name,
+                    restarts,
+                    "Too many restarts. Giving up on feed."
+                );
+            } else {
+                let delay = cfg.get_restart_delay(restarts);
+                tracing::info!(
+                    pos,
+                    name = cfg.name,
+                    restarts,
+                    ?delay,
+                    "Scheduling feed restart."
+                );
+                self.restarts[pos] += 1;
+                self.restart_timers[pos] =
+                    Some(self.schedule(Msg::Restart { pos }, delay));
+            }
+        }
+        Ok(())
+    }
+
+    async fn restart(&mut self, pos: usize) {
+        let cfg = self.conf.feeds[pos].clone();
+        tracing::info!(pos, name = cfg.name, "Restarting feed.");
+        let feed_dir =
+            conf::path_feed_dir(&self.feeds_dir, cfg.config_pos, &cfg.name);
+        match Feed::start(
+            &cfg,
+            &feed_dir,
+            pos,
+            self.conf.timezone.as_deref(),
+            self.systemd.as_ref(),
+            self.self_tx.clone(),
+        )
+        .await
+        {
+            Ok(feed) => {
+                self.feed_ids[pos] = Some(feed.get_id());
+                self.feeds[pos] = Some(feed);
+                self.reschedule_expiration(pos);
+            }
+            Err(error) => {
+                tracing::error!(
+                    pos,
+                    name = cfg.name,
+                    ?error,
+                    "Failed to restart feed."
+                );
+            }
+        }
+    }
+
     async fn status(&mut self) -> anyhow::Result<bar::status::Status> {
         let status = match (&self.feeds[..], &self.expiration_timers[..]) {
-            ([], []) => bar::status::Status::UpOff,
+            ([], []) => bar::status::Status::UpOff {
+                connections: 0,
+                title: self.conf.bar_title.clone(),
+            },
             (procs, _) => {
                 let ps_list = ps::list().await?;
                 let mut pdescendants = ps::descendants(ps_list.as_slice());
                 let mut states = ps::states(ps_list.as_slice());
+                let mut usage = ps::usage(ps_list.as_slice());
                 let mut stati = Vec::new();
                 for (pos, cfg) in self.conf.feeds.iter().enumerate() {
                     let info = match &procs[pos] {
                         None => None,
                         Some(feed) => {
                             let log_file = feed.get_log_path();
-                            let log_mtime =
-                                crate::fs::mtime(&log_file).await?;
-                            let log_size_bytes =
-                                crate::fs::size_in_bytes(&log_file).await?;
+                            let crate::fs::FileStats {
+                                mtime: log_mtime,
+                                size_bytes: log_size_bytes,
+                                ..
+                            } = crate::fs::file_stats(&log_file)
+                                .await
+                                .unwrap_or_else(|error| {
+                                    tracing::error!(
+                                        ?error,
+                                        "Failed to stat log file",
+                                    );
+                                    // TODO Post notification.
+                                    crate::fs::FileStats {
+                                        mtime: SystemTime::UNIX_EPOCH,
+                                        size_bytes: 0,
+                                        exists: false,
+                                    }
+                                });
+                            let feed_dir_bytes =
+                                crate::fs::dir_size(feed.get_dir_path())
+                                    .await
+                                    .unwrap_or_else(|error| {
+                                        tracing::warn!(
+                                            ?error,
+                                            "Failed to size feed dir",
+                                        );
+                                        0
+                                    });
                             let now = SystemTime::now();
+                            let slot_meta = self.bar.slot_meta(pos);
+                            let update_count = slot_meta.version_count;
+                            // As of when the line was read, rather than
+                            // when it got through to here.
                             let age_of_output = feed
                                 .get_last_output_time()
-                                .and_then(|last| {
-                                    now.duration_since(last)
+                                .and_then(|last_output| {
+                                    now.duration_since(last_output)
                                         .map_err(|error| {
                                             tracing::warn!(
-                                        ?error,
-                                        "Last output is from the future. \
-                                         This far away: {}",
-                                        humantime::format_duration(
-                                            error.duration()
-                                        )
-                                    );
+                                                ?error,
+                                                "Last output is from the \
+                                                future. This far away: {}",
+                                                humantime::format_duration(
+                                                    error.duration()
+                                                )
+                                            );
                                             // TODO Post notification.
                                         })
                                         .ok()
@@ -368,19 +1232,31 @@ impl Server {
                                     .ok()
                                 })
                                 .flatten();
-                            let log_lines =
-                                match fs::read_to_string(&log_file).await {
-                                    Ok(log) => log.lines().count(),
-                                    Err(err) => {
+                            let log_lines = if self
+                                .conf
+                                .status_include_log_lines
+                                .unwrap_or(false)
+                            {
+                                feed::count_log_lines(log_file)
+                                    .await
+                                    .map_err(|error| {
                                         tracing::error!(
-                                            ?log_file,
-                                            ?err,
-                                            "Failed to read log file",
+                                            ?error,
+                                            "Failed to count log lines",
                                         );
                                         // TODO Post notification.
-                                        0
-                                    }
-                                };
+                                    })
+                                    .ok()
+                            } else {
+                                None
+                            };
+                            let log_lines_approx = feed.get_output_lines();
+                            let log_lines_live = feed.get_stderr_lines();
+                            let line_errors = feed.get_line_errors();
+                            let avg_output_interval_secs = self
+                                .activity
+                                .avg_interval(pos)
+                                .map(|interval| interval.as_secs_f64());
 
                             // Removing to reuse existing set allocation,
                             // since we'll never look it up more than once
@@ -391,16 +1267,36 @@ impl Server {
                                     .unwrap_or_default();
                             let state: Option<ps::State> =
                                 states.remove(&feed.get_pid());
+                            let pdepth = ps::tree_depth(
+                                ps_list.as_slice(),
+                                feed.get_pid(),
+                            );
+                            let (cpu_pct, mem_pct) = usage
+                                .remove(&feed.get_pid())
+                                .map_or((None, None), |(cpu, mem)| {
+                                    (Some(cpu), Some(mem))
+                                });
 
                             Some(bar::status::Info {
                                 name: cfg.name.to_string(),
                                 dir: feed.get_dir_path().to_owned(),
+                                started_at: Some(feed.get_spawned_at()),
                                 age_of_output,
+                                avg_output_interval_secs,
+                                update_count,
                                 age_of_log,
                                 log_size_bytes,
+                                feed_dir_bytes,
                                 log_lines,
+                                log_lines_approx,
+                                log_lines_live,
+                                line_errors,
                                 pid: feed.get_pid(),
+                                pgid: feed.get_pgid(),
                                 state,
+                                cpu_pct,
+                                mem_pct,
+                                pdepth,
                                 pdescendants,
                             })
                         }
@@ -410,56 +1306,156 @@ impl Server {
                         info,
                     });
                 }
-                bar::status::Status::UpOn { feeds: stati }
+                bar::status::Status::UpOn {
+                    feeds: stati,
+                    connections: 0,
+                    title: self.conf.bar_title.clone(),
+                    output_error_count: self.output_error_count,
+                    last_output_error: self.last_output_error.clone(),
+                    timer_panics: self.total_timer_panics,
+                    stopping: match &self.state {
+                        State::Offing { remaining, .. } => {
+                            Some(remaining.len())
+                        }
+                        State::On | State::Off => None,
+                    },
+                }
             }
         };
         Ok(status)
     }
 
+    fn feed_log_path(&self, pos: usize) -> ApiResult<PathBuf> {
+        let cfg = self
+            .conf
+            .feeds
+            .get(pos)
+            .ok_or(ApiError::FeedNotFound { pos })?;
+        let feed_dir =
+            conf::path_feed_dir(&self.feeds_dir, cfg.config_pos, &cfg.name);
+        Ok(conf::path_feed_log(&feed_dir))
+    }
+
+    async fn clear_feed_log(&mut self, pos: usize) -> ApiResult<()> {
+        let log_file = self.feed_log_path(pos)?;
+        bar::feed::clear_log(&log_file).await?;
+        tracing::info!(pos, ?log_file, "Feed log cleared.");
+        Ok(())
+    }
+
+    async fn search_feed_log(
+        &mut self,
+        pos: usize,
+        pattern: String,
+        max_lines: usize,
+    ) -> ApiResult<Vec<String>> {
+        let log_file = self.feed_log_path(pos)?;
+        let lines =
+            bar::feed::search_log(&log_file, pattern, max_lines).await?;
+        Ok(lines)
+    }
+
     async fn handle(&mut self, msg: Msg) -> anyhow::Result<()> {
         tracing::debug!(?msg, "Handling message.");
         match (&self.state, msg) {
-            (State::Offing { .. }, Msg::FeedExit { pos, result }) => {
-                self.off_feed(pos, result).await?;
+            (
+                _,
+                msg @ (Msg::Expiration { pos }
+                | Msg::Restart { pos }
+                | Msg::Input { pos, .. }
+                | Msg::FeedExit { pos, .. }),
+            ) if !self.is_valid_pos(pos) => {
+                tracing::warn!(?msg, "Ignoring for a position not in bar.");
             }
-            (_, Msg::FeedExit { pos, result }) => {
-                tracing::warn!(pos, ?result, "Unsolicited feed exit.");
-                self.off_feed(pos, result).await?;
+            (_, msg @ Msg::Input { pos, id, .. })
+                if !self.is_current_feed(pos, id) =>
+            {
+                tracing::warn!(?msg, "Ignoring from a replaced feed.");
             }
             (
-                State::Off,
-                msg @ (Msg::Expiration { pos: _ }
-                | Msg::Input { pos: _, data: _ }
-                | Msg::Output),
+                _,
+                Msg::FeedExit {
+                    pos,
+                    result,
+                    expected: true,
+                },
+            ) => {
+                self.off_feed(pos, result).await?;
+                self.reconf_continue().await;
+            }
+            (
+                _,
+                Msg::FeedExit {
+                    pos,
+                    result,
+                    expected: false,
+                },
+            ) => {
+                self.on_unexpected_exit(pos, result).await?;
+                self.reconf_continue().await;
+            }
+            (
+                State::Off,
+                msg @ (Msg::Expiration { pos: _ }
+                | Msg::Input { .. }
+                | Msg::Output),
             ) => {
                 tracing::warn!(?msg, "Ignoring in off state.");
             }
             (State::On | State::Offing { .. }, Msg::Expiration { pos }) => {
-                self.expiration_timers[pos]
-                    .take()
-                    .unwrap_or_else(|| unreachable!())
-                    .await?;
-                self.bar.expire(pos);
-                self.ensure_output_scheduled();
+                match self
+                    .expiration_timers
+                    .get_mut(pos)
+                    .and_then(Option::take)
+                {
+                    // Aborted by a reload, after it already fired.
+                    None => {
+                        tracing::warn!(pos, "Ignoring stale expiration.");
+                    }
+                    Some(timer) => {
+                        if self.join_timer(timer).await? {
+                            self.bar.expire(pos);
+                            self.ensure_output_scheduled();
+                        }
+                    }
+                }
             }
             (
-                State::On | State::Offing { notify: _ },
-                Msg::Input { pos, data },
+                State::Off | State::Offing { .. },
+                msg @ Msg::Restart { .. },
             ) => {
-                self.reschedule_expiration(pos);
-                self.bar.set(pos, &data);
-                self.ensure_output_scheduled();
-                if let Some(feed) = self.feeds[pos].as_mut() {
-                    feed.set_last_output_time();
+                tracing::warn!(?msg, "Ignoring restart when not on.");
+            }
+            (State::On, Msg::Restart { pos }) => {
+                match self.restart_timers.get_mut(pos).and_then(Option::take)
+                {
+                    // Aborted by a reload, after it already fired.
+                    None => {
+                        tracing::warn!(pos, "Ignoring stale restart.");
+                    }
+                    Some(timer) => {
+                        if self.join_timer(timer).await? {
+                            self.restart(pos).await;
+                        }
+                    }
                 }
             }
+            (
+                State::On | State::Offing { .. },
+                Msg::Input { pos, data, .. },
+            ) => {
+                self.input(pos, data);
+                self.ensure_output_scheduled();
+            }
             (State::On | State::Offing { .. }, Msg::Output) => {
-                self.output_timer.take().unwrap_or_else(|| {
+                let timer = self.output_timer.take().unwrap_or_else(|| {
                     unreachable!(
                         "Output msg arrived without being scheduled."
                     )
                 });
-                self.output().await;
+                if self.join_timer(timer).await? {
+                    self.output().await;
+                }
             }
             (State::On, Msg::On(client)) => {
                 tracing::warn!("Already on. Ignoring request to turn on.");
@@ -472,11 +1468,29 @@ impl Server {
                     Err(anyhow!("Still offing. Not ready to turn back on."));
                 reply(client, result);
             }
+            (State::Off, Msg::On(client))
+                if self.feeds.iter().any(Option::is_some) =>
+            {
+                // Left over from an on which didn't finish. Stopping them
+                // through the usual path, then trying again, rather than
+                // orphaning them with a fresh set.
+                tracing::warn!("Feeds still running. Stopping them first.");
+                let notify = self.off_begin().await;
+                let self_tx = self.self_tx.clone();
+                tokio::spawn(async move {
+                    notify.notified().await;
+                    if let Err(SendError(Api { msg })) = self_tx.send(Api {
+                        msg: Msg::On(client),
+                    }) {
+                        tracing::warn!(?msg, "Bar server gone before on.");
+                    }
+                });
+            }
             (State::Off, Msg::On(client)) => {
                 reply(client, self.on().await);
             }
             (State::On, Msg::Off(client)) => {
-                let notify = self.off_begin();
+                let notify = self.off_begin().await;
                 tokio::spawn(async move {
                     notify.notified().await;
                     reply(client, ());
@@ -492,30 +1506,166 @@ impl Server {
             (_, Msg::Status(client)) => {
                 reply(client, self.status().await);
             }
+            (_, Msg::Ping(client)) => {
+                reply(client, ());
+            }
+            (_, Msg::GetConf(client)) => {
+                reply(client, self.conf.clone());
+            }
+            (_, Msg::GetFeedConf { pos, reply_tx }) => {
+                let result = self
+                    .conf
+                    .feeds
+                    .get(pos)
+                    .cloned()
+                    .ok_or(ApiError::FeedNotFound { pos });
+                reply(reply_tx, result);
+            }
+            (_, Msg::SetSeparator(sep, client)) => {
+                // Also in conf, so that it survives a bar rebuild on (re)start.
+                self.conf.sep.clone_from(&sep);
+                self.bar.set_separator(sep);
+                self.ensure_output_scheduled();
+                reply(client, ());
+            }
+            (_, Msg::SnapshotBar(client)) => {
+                reply(client, self.bar.snapshot());
+            }
+            (State::On | State::Offing { .. }, Msg::ForceOutput(client)) => {
+                reply(client, self.output_forced().await);
+            }
+            (State::Off, Msg::ForceOutput(client)) => {
+                let result = Err(anyhow!("Nothing to output in off state."));
+                reply(client, result);
+            }
             (State::Off, Msg::Reconf(client)) => {
                 let result =
-                    Conf::load_or_init(&self.dir).await.map(|conf| {
+                    Conf::load_or_init(&self.dir).await.map(|mut conf| {
+                        conf.sort_feeds_by_priority();
                         self.conf = conf;
+                        self.reconfed = true;
+                        false
                     });
                 reply(client, result);
             }
-            (State::On | State::Offing { .. }, Msg::Reconf(client)) => {
-                let result = Err(anyhow!("Can only reconfig in off state."));
+            (State::On, Msg::Reconf(client)) => {
+                self.reconf_begin(client).await;
+            }
+            (State::Offing { .. }, Msg::Reconf(client)) => {
+                let result =
+                    Err(anyhow!("Still offing. Not ready to reconfig."));
                 reply(client, result);
             }
+            (_, Msg::ClearFeedLog { pos, reply_tx }) => {
+                reply(reply_tx, self.clear_feed_log(pos).await);
+            }
+            (
+                _,
+                Msg::SearchFeedLog {
+                    pos,
+                    pattern,
+                    max_lines,
+                    reply_tx,
+                },
+            ) => {
+                let result =
+                    self.search_feed_log(pos, pattern, max_lines).await;
+                reply(reply_tx, result);
+            }
+        }
+        Ok(())
+    }
+
+    /// Handles the messages in order, except that output waits for the
+    /// inputs right after it, so that it includes them, and for which
+    /// output is then scheduled only once. Any other message may change
+    /// what the pending output is for, e.g. by turning the bar off and
+    /// back on with a new output timer, so the held back output and
+    /// inputs are handled before it.
+    async fn handle_batch(&mut self, msgs: Vec<Msg>) -> anyhow::Result<()> {
+        tracing::debug!(n = msgs.len(), "Handling batch.");
+        let mut output = None;
+        let mut inputs = 0;
+        for msg in msgs {
+            match (&self.state, msg) {
+                (_, msg @ Msg::Output) => {
+                    output = Some(msg);
+                }
+                (
+                    State::On | State::Offing { .. },
+                    Msg::Input { pos, id, data },
+                ) if self.is_valid_pos(pos)
+                    && self.is_current_feed(pos, id) =>
+                {
+                    self.input(pos, data);
+                    inputs += 1;
+                }
+                (_, msg) => {
+                    self.handle_held_back(&mut inputs, &mut output).await?;
+                    self.handle(msg).await?;
+                }
+            }
+        }
+        self.handle_held_back(&mut inputs, &mut output).await
+    }
+
+    async fn handle_held_back(
+        &mut self,
+        inputs: &mut usize,
+        output: &mut Option<Msg>,
+    ) -> anyhow::Result<()> {
+        if std::mem::take(inputs) > 0 {
+            self.ensure_output_scheduled();
+        }
+        if let Some(msg) = output.take() {
+            self.handle(msg).await?;
         }
         Ok(())
     }
 
+    fn input(&mut self, pos: usize, data: Arc<String>) {
+        self.reschedule_expiration(pos);
+        self.bar.set_shared(pos, data);
+        self.activity.record(pos, Instant::now());
+    }
+
+    /// Whether pos is a slot of the current bar. Feeds which a reload
+    /// removed may still send messages with positions past its end.
+    fn is_valid_pos(&self, pos: usize) -> bool {
+        pos < self.bar.len() && pos < self.conf.feeds.len()
+    }
+
+    /// Whether the feed with the id is the one last started at pos.
+    fn is_current_feed(&self, pos: usize, id: u64) -> bool {
+        self.feed_ids.get(pos) == Some(&Some(id))
+    }
+
     fn ensure_output_scheduled(&mut self) {
         if self.output_timer.is_none() {
             let output_timer =
-                self.schedule(Msg::Output, self.output_interval);
+                self.schedule(Msg::Output, self.current_output_interval());
             self.output_timer = Some(output_timer);
         }
     }
 
+    fn current_output_interval(&self) -> Duration {
+        match (
+            self.conf.get_adaptive_output_interval(),
+            self.activity.min_avg_interval(),
+        ) {
+            (Some((min, max)), Some(input_interval)) => {
+                (input_interval / 2).clamp(min, max)
+            }
+            (Some((_, max)), None) => max,
+            (None, _) => self.output_interval,
+        }
+    }
+
     fn reschedule_expiration(&mut self, pos: usize) {
+        if !self.is_valid_pos(pos) || pos >= self.expiration_timers.len() {
+            tracing::warn!(pos, "Not scheduling expiration outside of bar.");
+            return;
+        }
         if let Some(ttl) = self.conf.feeds[pos].ttl {
             let ttl = Duration::from_secs_f64(ttl);
             let new = self.schedule(Msg::Expiration { pos }, ttl);
@@ -525,6 +1675,27 @@ impl Server {
         }
     }
 
+    /// Whether the timer finished cleanly. A panicked one is counted and
+    /// its message dropped, rather than taking the whole server down.
+    async fn join_timer(
+        &mut self,
+        timer: JoinHandle<()>,
+    ) -> anyhow::Result<bool> {
+        match timer.await {
+            Ok(()) => Ok(true),
+            Err(error) if error.is_panic() => {
+                self.total_timer_panics += 1;
+                tracing::error!(
+                    ?error,
+                    total = self.total_timer_panics,
+                    "Timer task panicked."
+                );
+                Ok(false)
+            }
+            Err(error) => Err(error.into()),
+        }
+    }
+
     fn schedule(&self, msg: Msg, delay: Duration) -> JoinHandle<()> {
         let tx = self.self_tx.clone();
         tokio::spawn(
@@ -542,8 +1713,1464 @@ impl Server {
     }
 }
 
+impl Drop for Server {
+    /// Last resort for when the server panicked, or was aborted, such as
+    /// with a dropped BarHandle: the usual shutdown path can no longer be
+    /// driven, so kill the feeds here, rather than leave behind orphans.
+    /// The server's files are only removed after a panic, since otherwise
+    /// whoever aborted the server is still around to do it.
+    fn drop(&mut self) {
+        let panicking = std::thread::panicking();
+        if panicking {
+            tracing::error!("Dropped while panicking. Cleaning up.");
+        }
+        let running = self.feeds.iter().flatten().count();
+        if running > 0 {
+            for feed in self.feeds.iter().flatten() {
+                feed.stop();
+            }
+            tracing::warn!(running, "Killing feeds.");
+            // The async runtime may be the one blocked by this drop, so
+            // the killing needs a thread and a runtime of its own.
+            let feeds_dir = self.feeds_dir.clone();
+            let (tx, rx) = std::sync::mpsc::channel();
+            std::thread::spawn(move || {
+                let result = tokio::runtime::Builder::new_current_thread()
+                    .enable_all()
+                    .build()
+                    .map_err(anyhow::Error::from)
+                    .and_then(|rt| {
+                        rt.block_on(feed::try_kill_all(&feeds_dir))
+                    });
+                let _ = tx.send(result);
+            });
+            match rx.recv_timeout(DROP_KILL_TIMEOUT) {
+                Ok(Ok(report)) => {
+                    tracing::info!(
+                        killed = report.killed.len(),
+                        failed = report.failed.len(),
+                        "Done killing feed process groups."
+                    );
+                }
+                Ok(Err(error)) => {
+                    tracing::error!(?error, "Failed to kill feeds.");
+                }
+                Err(_) => {
+                    tracing::error!(
+                        timeout = ?DROP_KILL_TIMEOUT,
+                        "Timed out killing feeds."
+                    );
+                }
+            }
+        }
+        if !panicking {
+            return;
+        }
+        let abstract_socket = self.conf.get_abstract_socket();
+        let mut files = vec![conf::path_server_pid(&self.dir)];
+        if !abstract_socket {
+            files.push(conf::path_server_sock(&self.dir, abstract_socket));
+        }
+        for file in files {
+            match std::fs::remove_file(&file) {
+                Ok(()) => {}
+                Err(error) if error.kind() == io::ErrorKind::NotFound => {}
+                Err(error) => {
+                    tracing::error!(?error, ?file, "Failed to remove file.");
+                }
+            }
+        }
+    }
+}
+
+fn post_notification(summary: &str, body: &str) {
+    let spawned = tokio::process::Command::new("notify-send")
+        .arg(summary)
+        .arg(body)
+        .spawn();
+    match spawned {
+        Ok(mut child) => {
+            tokio::spawn(
+                async move {
+                    if let Err(error) = child.wait().await {
+                        tracing::warn!(?error, "notify-send failed.");
+                    }
+                }
+                .in_current_span(),
+            );
+        }
+        Err(error) => {
+            tracing::warn!(?error, "Failed to spawn notify-send.");
+        }
+    }
+}
+
+/// The requester may be gone by now, such as when its client disconnected,
+/// which is not a problem here.
 fn reply<M: Debug>(tx: oneshot::Sender<M>, msg: M) {
-    if let Err(error) = tx.send(msg) {
-        tracing::error!(?error, "Failed to reply. Sender dropped.");
+    if let Err(msg) = tx.send(msg) {
+        tracing::debug!(?msg, "Requester gone. Dropping reply.");
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, SystemTime};
+
+    use tokio::sync::{mpsc, oneshot};
+
+    use super::{
+        join_ordered, save_bar_snapshot, take_bar_snapshot, with_timeout,
+        Api, ApiError, ApiReceiver, Msg, Server,
+    };
+    use crate::{
+        bar::{
+            self,
+            feed::{count_log_lines, Feed},
+            Bar,
+        },
+        circuit_breaker::State as CircuitBreakerState,
+        conf::{self, Conf},
+        tracing::LogCapture,
+        x11::X11,
     };
+
+    async fn next_feed_exit(rx: &mut ApiReceiver) -> bool {
+        while let Some(Api { msg }) = rx.recv().await {
+            if let Msg::FeedExit { expected, .. } = msg {
+                return expected;
+            }
+        }
+        unreachable!("Channel closed before feed exit.")
+    }
+
+    #[tokio::test]
+    async fn feed_exit_expected() {
+        let dir = crate::tmp_path("feed_exit");
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let mut cfg = Conf::default().feeds[0].clone();
+
+        cfg.cmd = conf::FeedCmd::Cmd("true".to_string());
+        let mut feed = Feed::start(&cfg, &dir, 0, None, None, tx.clone())
+            .await
+            .unwrap();
+        assert!(!next_feed_exit(&mut rx).await);
+        feed.clean_up(cfg.get_stop_timeout()).await.unwrap();
+
+        cfg.cmd = conf::FeedCmd::Cmd("sleep 10".to_string());
+        let mut feed = Feed::start(&cfg, &dir, 0, None, None, tx.clone())
+            .await
+            .unwrap();
+        feed.stop();
+        assert!(next_feed_exit(&mut rx).await);
+        feed.clean_up(cfg.get_stop_timeout()).await.unwrap();
+
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn output_lines_vs_log_lines() {
+        let dir = crate::tmp_path("output_lines");
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let mut cfg = Conf::default().feeds[0].clone();
+        cfg.cmd = conf::FeedCmd::Cmd(
+            "for i in 1 2 3; do echo $i >&2; echo $i; done; sleep 10"
+                .to_string(),
+        );
+        let mut feed =
+            Feed::start(&cfg, &dir, 0, None, None, tx).await.unwrap();
+        let mut inputs = 0;
+        while inputs < 3 {
+            if let Some(Api {
+                msg: Msg::Input { .. },
+            }) = rx.recv().await
+            {
+                inputs += 1;
+            }
+        }
+        assert_eq!(3, feed.get_output_lines());
+        // Log is written by its own task, which is done once cleaned up.
+        feed.stop();
+        feed.clean_up(cfg.get_stop_timeout()).await.unwrap();
+        assert_eq!(
+            feed.get_output_lines() as usize,
+            count_log_lines(feed.get_log_path()).await.unwrap()
+        );
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn last_output_time_set_by_reader() {
+        let dir = crate::tmp_path("last_output_time");
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let mut cfg = Conf::default().feeds[0].clone();
+        cfg.cmd = conf::FeedCmd::Cmd("echo 1; sleep 10".to_string());
+        let before = SystemTime::now();
+        let mut feed =
+            Feed::start(&cfg, &dir, 0, None, None, tx).await.unwrap();
+        let last_output = feed.last_output_shared();
+        loop {
+            if let Some(Api {
+                msg: Msg::Input { .. },
+            }) = rx.recv().await
+            {
+                break;
+            }
+        }
+        // The input message is received, but never handled by a server.
+        let received = SystemTime::now();
+        let set = last_output.lock().unwrap().unwrap();
+        assert!(before <= set && set <= received);
+        assert_eq!(Some(set), feed.get_last_output_time());
+        feed.stop();
+        feed.clean_up(cfg.get_stop_timeout()).await.unwrap();
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn line_errors() {
+        let dir = crate::tmp_path("line_errors");
+        let mut cfg = Conf::default().feeds[0].clone();
+        cfg.encoding = Some(conf::FeedEncoding::Utf8Strict);
+        cfg.max_line_errors = Some(2);
+
+        // Tolerated.
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        cfg.cmd = conf::FeedCmd::Cmd(
+            r"printf 'a\377\nok\nb\377\nstill ok\n'; sleep 10".to_string(),
+        );
+        let mut feed =
+            Feed::start(&cfg, &dir, 0, None, None, tx).await.unwrap();
+        let mut inputs = Vec::new();
+        while inputs.len() < 2 {
+            if let Some(Api {
+                msg: Msg::Input { data, .. },
+            }) = rx.recv().await
+            {
+                inputs.push(data.to_string());
+            }
+        }
+        assert_eq!(vec!["ok", "still ok"], inputs);
+        assert_eq!(2, feed.get_line_errors());
+        feed.stop();
+        feed.clean_up(cfg.get_stop_timeout()).await.unwrap();
+
+        // Not tolerated.
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        cfg.cmd = conf::FeedCmd::Cmd(
+            r"printf '\377\n\377\n\377\nlost\n'; sleep 10".to_string(),
+        );
+        let mut feed =
+            Feed::start(&cfg, &dir, 0, None, None, tx).await.unwrap();
+        while feed.get_line_errors() < 3 {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        feed.stop();
+        let result = feed.clean_up(cfg.get_stop_timeout()).await;
+        assert!(result.is_err());
+        assert_eq!(3, feed.get_line_errors());
+        while let Ok(Api { msg }) = rx.try_recv() {
+            assert!(!matches!(msg, Msg::Input { .. }));
+        }
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn timezone() {
+        let dir = crate::tmp_path("timezone");
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let mut cfg = Conf::default().feeds[0].clone();
+        cfg.cmd = conf::FeedCmd::Cmd("echo \"$TZ\"".to_string());
+        let mut feed =
+            Feed::start(&cfg, &dir, 0, Some("Asia/Tokyo"), None, tx)
+                .await
+                .unwrap();
+        let data = loop {
+            if let Some(Api {
+                msg: Msg::Input { data, .. },
+            }) = rx.recv().await
+            {
+                break data.to_string();
+            }
+        };
+        assert_eq!("Asia/Tokyo", data);
+        feed.clean_up(cfg.get_stop_timeout()).await.unwrap();
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn script() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = crate::tmp_path("script");
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let mut cfg = Conf::default().feeds[0].clone();
+        cfg.cmd = conf::FeedCmd::Script(
+            "x='quoted \"and\" $dollars'\necho \"$x\"\n".to_string(),
+        );
+        let mut feed =
+            Feed::start(&cfg, &dir, 0, None, None, tx).await.unwrap();
+        let data = loop {
+            if let Some(Api {
+                msg: Msg::Input { data, .. },
+            }) = rx.recv().await
+            {
+                break data.to_string();
+            }
+        };
+        assert_eq!("quoted \"and\" $dollars", data);
+        let script_file = conf::path_feed_script(&dir);
+        let mode = std::fs::metadata(&script_file)
+            .unwrap()
+            .permissions()
+            .mode();
+        assert_eq!(0o755, mode & 0o777);
+        feed.clean_up(cfg.get_stop_timeout()).await.unwrap();
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn bar_snapshot_restart() {
+        let dir = crate::tmp_path("bar_snapshot");
+        let conf = Conf::default();
+        let (tx, _rx) = mpsc::unbounded_channel();
+
+        let mut bar = Bar::from_conf(&conf);
+        bar.set(0, "up 1 day");
+        bar.set(1, "12:34");
+        save_bar_snapshot(&dir, &bar).await.unwrap();
+        // Where it isn't mistaken for a feed by kill-orphans.
+        let feeds_dir = conf::path_feeds_dir(&dir, None);
+        assert!(!conf::path_bar_snapshot(&dir).starts_with(feeds_dir));
+
+        let snapshot = take_bar_snapshot(&dir).await.unwrap();
+        assert!(snapshot.is_some());
+        let mut server = Server::new(conf.clone(), dir.clone(), tx, snapshot);
+        assert_eq!(bar.show(), server.bar.show());
+
+        // Consumed by the first boot.
+        assert!(take_bar_snapshot(&dir).await.unwrap().is_none());
+
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn priority_order() {
+        let dir = crate::tmp_path("priority_order");
+        tokio::fs::create_dir_all(&dir).await.unwrap();
+        let data = r#"
+            sep = "|"
+            pad_left = "["
+            pad_right = "]"
+            expiry_character = "_"
+            output_interval = 1.0
+
+            [[feeds]]
+            name = "a"
+            cmd = "echo a"
+            priority = 2
+
+            [[feeds]]
+            name = "b"
+            cmd = "echo b"
+
+            [[feeds]]
+            name = "c"
+            cmd = "echo c"
+            priority = 1
+
+            [[feeds]]
+            name = "d"
+            cmd = "echo d"
+            priority = 2
+        "#;
+        tokio::fs::write(conf::path_conf(&dir), data).await.unwrap();
+        let conf = Conf::load_or_init(&dir).await.unwrap();
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let mut server = Server::new(conf, dir.clone(), tx, None);
+        let order: Vec<(&str, usize)> = server
+            .conf
+            .feeds
+            .iter()
+            .map(|feed| (feed.name.as_str(), feed.config_pos))
+            .collect();
+        assert_eq!(vec![("c", 2), ("a", 0), ("d", 3), ("b", 1)], order);
+
+        server.on().await.unwrap();
+        let mut inputs = 0;
+        while inputs < 4 {
+            if let Some(Api {
+                msg: msg @ Msg::Input { .. },
+            }) = rx.recv().await
+            {
+                server.handle(msg).await.unwrap();
+                inputs += 1;
+            }
+        }
+        assert_eq!(Some("[c|a|d|b]"), server.bar.show_unshown());
+        let feeds_dir = conf::path_feeds_dir(&dir, None);
+        assert!(conf::path_feed_dir(&feeds_dir, 2, "c").exists());
+        server.off_begin().await;
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn custom_feeds_dir() {
+        let dir = crate::tmp_path("custom_feeds_dir");
+        let feeds_dir = dir.join("elsewhere");
+        let mut conf = Conf {
+            feeds_dir: Some(feeds_dir.clone()),
+            ..Conf::default()
+        };
+        conf.feeds.truncate(1);
+        conf.feeds[0].cmd = conf::FeedCmd::Cmd("sleep 10".to_string());
+        let (tx, _rx) = mpsc::unbounded_channel();
+        let mut server = Server::new(conf, dir.clone(), tx, None);
+        server.on().await.unwrap();
+        let feed_dir =
+            conf::path_feed_dir(&feeds_dir, 0, &server.conf.feeds[0].name);
+        assert!(conf::path_feed_pid(&feed_dir).exists());
+        assert!(!conf::path_feeds_dir(&dir, None).exists());
+        server.off_begin().await;
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn output_on_reload() {
+        let dir = crate::tmp_path("output_on_reload");
+        tokio::fs::create_dir_all(&dir).await.unwrap();
+        let bar_file = dir.join("bar");
+        let data = format!(
+            r#"
+            sep = "|"
+            pad_left = ""
+            pad_right = ""
+            expiry_character = "_"
+            output_interval = 60.0
+            output_on_reload = true
+            preserve_on_reload = true
+            dst = {{ File = {{ path = {:?} }} }}
+
+            [[feeds]]
+            name = "a"
+            cmd = "sleep 10"
+            "#,
+            bar_file
+        );
+        tokio::fs::write(conf::path_conf(&dir), data).await.unwrap();
+        let conf = Conf::load_or_init(&dir).await.unwrap();
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let mut server = Server::new(conf, dir.clone(), tx, None);
+        server.on().await.unwrap();
+        let msg = Msg::Input {
+            pos: 0,
+            id: server.feed_ids[0].unwrap(),
+            data: "abc".to_string().into(),
+        };
+        server.handle(msg).await.unwrap();
+
+        // Reload of an off bar, which is then turned on.
+        let (reply_tx, _reply_rx) = tokio::sync::oneshot::channel();
+        server.handle(Msg::Off(reply_tx)).await.unwrap();
+        while !matches!(server.state, super::State::Off) {
+            let Api { msg } = rx.recv().await.unwrap();
+            server.handle(msg).await.unwrap();
+        }
+        assert_eq!(vec!["___"], server.bar.snapshot());
+        let (reply_tx, _reply_rx) = tokio::sync::oneshot::channel();
+        server.handle(Msg::Reconf(reply_tx)).await.unwrap();
+        let (reply_tx, _reply_rx) = tokio::sync::oneshot::channel();
+        server.handle(Msg::On(reply_tx)).await.unwrap();
+
+        // Long before the output interval.
+        let output = tokio::fs::read_to_string(&bar_file).await.unwrap();
+        assert_eq!("abc", output);
+
+        server.off_begin().await;
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn reload_in_place() {
+        let dir = crate::tmp_path("reload_in_place");
+        tokio::fs::create_dir_all(&dir).await.unwrap();
+        let conf_toml = |feeds: &str| {
+            format!(
+                r#"
+                sep = "|"
+                pad_left = ""
+                pad_right = ""
+                expiry_character = "_"
+                output_interval = 60.0
+                {}
+                "#,
+                feeds
+            )
+        };
+        let feed = |name: &str, cmd: &str| {
+            format!("[[feeds]]\nname = {:?}\ncmd = {:?}\n", name, cmd)
+        };
+        let data = conf_toml(
+            &[feed("a", "sleep 10"), feed("b", "sleep 10")].concat(),
+        );
+        tokio::fs::write(conf::path_conf(&dir), data).await.unwrap();
+        let conf = Conf::load_or_init(&dir).await.unwrap();
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let mut server = Server::new(conf, dir.clone(), tx, None);
+        server.on().await.unwrap();
+        let pids: Vec<_> = server
+            .feeds
+            .iter()
+            .map(|feed| feed.as_ref().unwrap().get_pid())
+            .collect();
+
+        let data = conf_toml(
+            &[
+                feed("a", "sleep 10"),
+                feed("b", "sleep 11"),
+                feed("c", "sleep 10"),
+            ]
+            .concat(),
+        );
+        tokio::fs::write(conf::path_conf(&dir), data).await.unwrap();
+        let (reply_tx, mut reply_rx) = tokio::sync::oneshot::channel();
+        server.handle(Msg::Reconf(reply_tx)).await.unwrap();
+        let applied = loop {
+            if let Ok(result) = reply_rx.try_recv() {
+                break result.unwrap();
+            }
+            let Api { msg } = rx.recv().await.unwrap();
+            server.handle(msg).await.unwrap();
+        };
+        assert!(applied);
+        assert!(matches!(server.state, super::State::On));
+        assert_eq!(3, server.feeds.len());
+        let feed = |pos: usize| server.feeds[pos].as_ref().unwrap();
+        assert_eq!(pids[0], feed(0).get_pid());
+        assert_ne!(pids[1], feed(1).get_pid());
+        assert_eq!(
+            conf::FeedCmd::Cmd("sleep 11".to_string()),
+            server.conf.feeds[1].cmd
+        );
+        assert_eq!("c", feed(2).get_name());
+
+        server.off_begin().await;
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn reload_queued() {
+        let dir = crate::tmp_path("reload_queued");
+        tokio::fs::create_dir_all(&dir).await.unwrap();
+        let conf_toml = |cmd: &str| {
+            format!(
+                r#"
+                sep = "|"
+                pad_left = ""
+                pad_right = ""
+                expiry_character = "_"
+                output_interval = 60.0
+                [[feeds]]
+                name = "a"
+                cmd = {:?}
+                "#,
+                cmd
+            )
+        };
+        tokio::fs::write(conf::path_conf(&dir), conf_toml("sleep 10"))
+            .await
+            .unwrap();
+        let conf = Conf::load_or_init(&dir).await.unwrap();
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let mut server = Server::new(conf, dir.clone(), tx, None);
+        server.on().await.unwrap();
+
+        // Changed, so that the first waits for the feed to exit.
+        tokio::fs::write(conf::path_conf(&dir), conf_toml("sleep 11"))
+            .await
+            .unwrap();
+        let (first_tx, mut first_rx) = oneshot::channel();
+        let (second_tx, mut second_rx) = oneshot::channel();
+        server.handle(Msg::Reconf(first_tx)).await.unwrap();
+        server.handle(Msg::Reconf(second_tx)).await.unwrap();
+        assert!(server.pending_reconf.is_some());
+        assert!(second_rx.try_recv().is_err());
+        let mut first = None;
+        let second = loop {
+            if first.is_none() {
+                first = first_rx.try_recv().ok();
+            }
+            if let Ok(result) = second_rx.try_recv() {
+                break result;
+            }
+            let Api { msg } = rx.recv().await.unwrap();
+            server.handle(msg).await.unwrap();
+        };
+        assert!(first.unwrap().unwrap());
+        assert!(second.unwrap());
+        assert!(server.queued_reconfs.is_empty());
+
+        server.off_begin().await;
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn auto_restart_backoff() {
+        let dir = crate::tmp_path("auto_restart_backoff");
+        tokio::fs::create_dir_all(&dir).await.unwrap();
+        let starts = dir.join("starts");
+        let mut conf = Conf::default();
+        conf.feeds.truncate(1);
+        conf.feeds[0].cmd =
+            conf::FeedCmd::Cmd(format!("echo >> {:?}; exit 1", starts));
+        conf.feeds[0].auto_restart = Some(true);
+        conf.feeds[0].max_restarts = Some(2);
+        conf.feeds[0].restart_delay_secs = Some(0.1);
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let mut server = Server::new(conf, dir.clone(), tx, None);
+        server.on().await.unwrap();
+
+        let started_at = std::time::Instant::now();
+        while let Ok(Some(Api { msg })) =
+            tokio::time::timeout(Duration::from_secs(1), rx.recv()).await
+        {
+            server.handle(msg).await.unwrap();
+        }
+        // Initial start, then after 0.1 and 0.2 seconds.
+        assert!(started_at.elapsed() >= Duration::from_millis(300));
+        let starts = tokio::fs::read_to_string(&starts).await.unwrap();
+        assert_eq!(3, starts.lines().count());
+        assert_eq!(vec![2], server.restarts);
+        assert!(server.feeds[0].is_none());
+
+        server.off_begin().await;
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn reload_moved_feeds() {
+        let dir = crate::tmp_path("reload_moved_feeds");
+        tokio::fs::create_dir_all(&dir).await.unwrap();
+        let conf_toml = |names: &[&str]| {
+            let feeds: String = names
+                .iter()
+                .map(|name| {
+                    format!(
+                        "[[feeds]]\nname = {:?}\ncmd = \"sleep 10\"\n",
+                        name
+                    )
+                })
+                .collect();
+            format!(
+                r#"
+                sep = "|"
+                pad_left = ""
+                pad_right = ""
+                expiry_character = "_"
+                output_interval = 60.0
+                preserve_on_reload = true
+                {}
+                "#,
+                feeds
+            )
+        };
+        tokio::fs::write(conf::path_conf(&dir), conf_toml(&["a", "b"]))
+            .await
+            .unwrap();
+        let conf = Conf::load_or_init(&dir).await.unwrap();
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let mut server = Server::new(conf, dir.clone(), tx, None);
+        server.on().await.unwrap();
+        let old_ids = server.feed_ids.clone();
+        for (pos, data) in ["A", "B"].into_iter().enumerate() {
+            let msg = Msg::Input {
+                pos,
+                id: old_ids[pos].unwrap(),
+                data: data.to_string().into(),
+            };
+            server.handle(msg).await.unwrap();
+        }
+
+        // a moves to where b was, which is gone, and c takes its place.
+        tokio::fs::write(conf::path_conf(&dir), conf_toml(&["c", "a"]))
+            .await
+            .unwrap();
+        let (reply_tx, mut reply_rx) = tokio::sync::oneshot::channel();
+        server.handle(Msg::Reconf(reply_tx)).await.unwrap();
+        loop {
+            if let Ok(result) = reply_rx.try_recv() {
+                assert!(result.unwrap());
+                break;
+            }
+            let Api { msg } = rx.recv().await.unwrap();
+            server.handle(msg).await.unwrap();
+        }
+        assert_eq!(vec!["", "A"], server.bar.snapshot());
+
+        // Late inputs of the stopped feeds are not mistaken for those of
+        // the feeds now at their positions.
+        for (pos, id) in old_ids.into_iter().enumerate() {
+            let msg = Msg::Input {
+                pos,
+                id: id.unwrap(),
+                data: "stale".to_string().into(),
+            };
+            server.handle_batch(vec![msg]).await.unwrap();
+        }
+        assert_eq!(vec!["", "A"], server.bar.snapshot());
+        let msg = Msg::Input {
+            pos: 0,
+            id: server.feed_ids[0].unwrap(),
+            data: "C".to_string().into(),
+        };
+        server.handle(msg).await.unwrap();
+        assert_eq!(vec!["C", "A"], server.bar.snapshot());
+
+        server.off_begin().await;
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn on_with_feeds_left_running() {
+        let dir = crate::tmp_path("on_with_feeds_left_running");
+        let mut conf = Conf::default();
+        conf.feeds.truncate(1);
+        conf.feeds[0].cmd = conf::FeedCmd::Cmd("sleep 10".to_string());
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let mut server = Server::new(conf, dir.clone(), tx, None);
+        server.on().await.unwrap();
+        let pid = server.feeds[0].as_ref().unwrap().get_pid();
+        // As if the on was cut short before changing state.
+        server.state = super::State::Off;
+
+        let (reply_tx, mut reply_rx) = tokio::sync::oneshot::channel();
+        server.handle(Msg::On(reply_tx)).await.unwrap();
+        loop {
+            if let Ok(result) = reply_rx.try_recv() {
+                result.unwrap();
+                break;
+            }
+            let Api { msg } = rx.recv().await.unwrap();
+            server.handle(msg).await.unwrap();
+        }
+        assert!(matches!(server.state, super::State::On));
+        assert_ne!(pid, server.feeds[0].as_ref().unwrap().get_pid());
+        assert!(nix::sys::signal::kill(
+            nix::unistd::Pid::from_raw(pid as i32),
+            None
+        )
+        .is_err());
+
+        server.off_begin().await;
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn off_remaining() {
+        let dir = crate::tmp_path("off_remaining");
+        let mut conf = Conf::default();
+        for feed in &mut conf.feeds {
+            feed.cmd = conf::FeedCmd::Cmd("sleep 10".to_string());
+        }
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let mut server = Server::new(conf, dir.clone(), tx, None);
+        server.on().await.unwrap();
+        server.off_begin().await;
+        let remaining = |server: &Server| match &server.state {
+            super::State::Offing { remaining, .. } => Some(remaining.len()),
+            _ => None,
+        };
+        assert_eq!(Some(2), remaining(&server));
+        match server.status().await.unwrap() {
+            bar::status::Status::UpOn { stopping, .. } => {
+                assert_eq!(Some(2), stopping);
+            }
+            status => unreachable!("Unexpected status: {:?}", status),
+        }
+
+        while let Some(n) = remaining(&server) {
+            let running = server.feeds.iter().flatten().count();
+            assert_eq!(running, n);
+            let Api { msg } = rx.recv().await.unwrap();
+            server.handle(msg).await.unwrap();
+        }
+        assert!(matches!(server.state, super::State::Off));
+        assert!(server.feeds.iter().all(Option::is_none));
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn status_with_unreadable_log() {
+        let dir = crate::tmp_path("status_with_unreadable_log");
+        let mut conf = Conf::default();
+        for feed in &mut conf.feeds {
+            feed.cmd = conf::FeedCmd::Cmd("echo >&2 a; sleep 10".to_string());
+        }
+        let (tx, _rx) = mpsc::unbounded_channel();
+        let mut server = Server::new(conf, dir.clone(), tx, None);
+        server.on().await.unwrap();
+        let log_file = |pos: usize| {
+            server.feeds[pos]
+                .as_ref()
+                .unwrap()
+                .get_log_path()
+                .to_owned()
+        };
+        let mut retries = 50;
+        while tokio::fs::metadata(log_file(1)).await.unwrap().len() == 0 {
+            assert!(retries > 0, "Feed did not log.");
+            retries -= 1;
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        // Stat fails with ELOOP, rather than NotFound.
+        tokio::fs::remove_file(log_file(0)).await.unwrap();
+        tokio::fs::symlink(log_file(0), log_file(0)).await.unwrap();
+
+        match server.status().await.unwrap() {
+            bar::status::Status::UpOn { feeds, .. } => {
+                let info = |pos: usize| feeds[pos].info.as_ref().unwrap();
+                assert_eq!(0, info(0).log_size_bytes);
+                assert!(info(0).age_of_log.is_none());
+                assert!(info(1).log_size_bytes > 0);
+            }
+            status => unreachable!("Unexpected status: {:?}", status),
+        }
+
+        server.off_begin().await;
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn status_with_unreadable_feed_dir() {
+        let dir = crate::tmp_path("status_with_unreadable_feed_dir");
+        let mut conf = Conf::default();
+        for feed in &mut conf.feeds {
+            feed.cmd = conf::FeedCmd::Cmd("sleep 10".to_string());
+        }
+        let (tx, _rx) = mpsc::unbounded_channel();
+        let mut server = Server::new(conf, dir.clone(), tx, None);
+        server.on().await.unwrap();
+        // Walking it fails with ENOTDIR, rather than NotFound.
+        let feed_dir = server.feeds[0].as_ref().unwrap().get_dir_path();
+        tokio::fs::remove_dir_all(feed_dir).await.unwrap();
+        tokio::fs::write(feed_dir, "").await.unwrap();
+
+        match server.status().await.unwrap() {
+            bar::status::Status::UpOn { feeds, .. } => {
+                let info = |pos: usize| feeds[pos].info.as_ref().unwrap();
+                assert_eq!(0, info(0).feed_dir_bytes);
+                assert!(info(1).feed_dir_bytes > 0);
+            }
+            status => unreachable!("Unexpected status: {:?}", status),
+        }
+
+        server.off_begin().await;
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn output_sentinel() {
+        let dir = crate::tmp_path("output_sentinel");
+        let mut conf = Conf::default();
+        conf.feeds.truncate(1);
+        conf.feeds[0].cmd = conf::FeedCmd::Cmd(
+            r"printf 'a\nb\nc\n--\nd\n'; sleep 10".to_string(),
+        );
+        conf.feeds[0].output_sentinel = Some("^--$".to_string());
+        conf.feeds[0].output_joiner = Some(" | ".to_string());
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let mut server = Server::new(conf, dir.clone(), tx, None);
+        server.on().await.unwrap();
+        let Api { msg } = rx.recv().await.unwrap();
+        match msg {
+            Msg::Input { pos: 0, data, .. } => assert_eq!("a | b | c", *data),
+            msg => panic!("Unexpected message: {:?}", msg),
+        }
+
+        // d is held back until the next sentinel.
+        let next =
+            tokio::time::timeout(Duration::from_millis(200), rx.recv()).await;
+        assert!(next.is_err(), "Unexpected message: {:?}", next);
+
+        server.off_begin().await;
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn output_prefix_and_suffix() {
+        let dir = crate::tmp_path("output_prefix_and_suffix");
+        let mut conf = Conf::default();
+        conf.feeds.truncate(1);
+        conf.feeds[0].cmd = conf::FeedCmd::Cmd(
+            r"printf '50\na\nb\n--\n'; sleep 10".to_string(),
+        );
+        conf.feeds[0].output_prefix = Some("BAT ".to_string());
+        conf.feeds[0].output_suffix = Some("%".to_string());
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let mut server = Server::new(conf.clone(), dir.clone(), tx, None);
+        server.on().await.unwrap();
+        let Api { msg } = rx.recv().await.unwrap();
+        match msg {
+            Msg::Input { pos: 0, data, .. } => assert_eq!("BAT 50%", *data),
+            msg => panic!("Unexpected message: {:?}", msg),
+        }
+        server.off_begin().await;
+
+        // Around the whole block, not each of its lines.
+        conf.feeds[0].output_sentinel = Some("^--$".to_string());
+        conf.feeds[0].output_joiner = Some(" ".to_string());
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let mut server = Server::new(conf, dir.clone(), tx, None);
+        server.on().await.unwrap();
+        let Api { msg } = rx.recv().await.unwrap();
+        match msg {
+            Msg::Input { pos: 0, data, .. } => {
+                assert_eq!("BAT 50 a b%", *data)
+            }
+            msg => panic!("Unexpected message: {:?}", msg),
+        }
+        server.off_begin().await;
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn ping() {
+        let dir = crate::tmp_path("ping");
+        let mut siblings = tokio::task::JoinSet::new();
+        let tx = super::start(&mut siblings, &dir, Conf::default());
+        for _ in 0..1000 {
+            super::ping(&tx).await.unwrap();
+        }
+        siblings.abort_all();
+    }
+
+    #[tokio::test]
+    async fn set_separator() {
+        let dir = crate::tmp_path("set_separator");
+        let conf = Conf {
+            sep: "|".to_string(),
+            ..Conf::default()
+        };
+        let (tx, _rx) = mpsc::unbounded_channel();
+        let mut server = Server::new(conf, dir, tx, None);
+        server.bar.set(0, "a");
+        server.bar.set(1, "b");
+        assert_eq!(Some(" a|b "), server.bar.show_unshown());
+
+        let (reply_tx, reply_rx) = tokio::sync::oneshot::channel();
+        let msg = Msg::SetSeparator(" :: ".to_string(), reply_tx);
+        server.handle(msg).await.unwrap();
+        reply_rx.await.unwrap();
+        assert_eq!(Some(" a :: b "), server.bar.show_unshown());
+        // For the bar rebuilt on restart.
+        assert_eq!(" :: ", server.conf.sep);
+    }
+
+    #[tokio::test]
+    async fn snapshot_bar() {
+        let dir = crate::tmp_path("snapshot_bar");
+        let (tx, _rx) = mpsc::unbounded_channel();
+        let mut server = Server::new(Conf::default(), dir, tx, None);
+        let expected: Vec<String> =
+            (0..server.bar.len()).map(|i| format!("slot {i}")).collect();
+        for (i, data) in expected.iter().enumerate() {
+            server.bar.set(i, data);
+        }
+
+        let (reply_tx, reply_rx) = tokio::sync::oneshot::channel();
+        server.handle(Msg::SnapshotBar(reply_tx)).await.unwrap();
+        let snapshot = reply_rx.await.unwrap();
+        assert!(!snapshot.is_empty());
+        assert_eq!(expected, snapshot);
+        assert_eq!(server.bar.snapshot(), snapshot);
+    }
+
+    #[tokio::test]
+    async fn force_output() {
+        let dir = crate::tmp_path("force_output");
+        tokio::fs::create_dir_all(&dir).await.unwrap();
+        let bar_file = dir.join("bar");
+        let mut conf = Conf {
+            sep: "|".to_string(),
+            output_interval: 60.0,
+            dst: Some(conf::Dst::File {
+                path: bar_file.clone(),
+            }),
+            ..Conf::default()
+        };
+        conf.feeds[0].cmd = conf::FeedCmd::Cmd("sleep 10".to_string());
+        conf.feeds[1].cmd = conf::FeedCmd::Cmd("sleep 10".to_string());
+        let (tx, _rx) = mpsc::unbounded_channel();
+        let mut server = Server::new(conf, dir.clone(), tx, None);
+
+        let (reply_tx, reply_rx) = tokio::sync::oneshot::channel();
+        server.handle(Msg::ForceOutput(reply_tx)).await.unwrap();
+        assert!(reply_rx.await.unwrap().is_err());
+
+        server.on().await.unwrap();
+        server.bar.set(0, "a");
+        server.bar.set(1, "b");
+        assert_eq!(Some(" a|b "), server.bar.show_unshown());
+        assert!(!bar_file.exists());
+
+        let (reply_tx, reply_rx) = tokio::sync::oneshot::channel();
+        server.handle(Msg::ForceOutput(reply_tx)).await.unwrap();
+        reply_rx.await.unwrap().unwrap();
+        let output = tokio::fs::read_to_string(&bar_file).await.unwrap();
+        assert_eq!(" a|b ", output);
+
+        // Scheduled output has nothing new to write.
+        tokio::fs::remove_file(&bar_file).await.unwrap();
+        server.output().await;
+        assert!(!bar_file.exists());
+
+        server.off_begin().await;
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn handle_batch() {
+        let dir = crate::tmp_path("handle_batch");
+        let conf = Conf {
+            dst: Some(conf::Dst::StdOut),
+            ..Conf::default()
+        };
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let mut server = Server::new(conf, dir, tx, None);
+        server.state = super::State::On;
+        server.feed_ids = vec![Some(0), Some(0)];
+        server.expiration_timers = vec![None, None];
+        // Fire the output scheduled at start, to be in the batch.
+        tokio::time::advance(server.output_interval).await;
+        let Api { msg } = rx.recv().await.unwrap();
+        assert!(matches!(msg, Msg::Output));
+
+        let mut msgs = vec![msg];
+        for i in 0..10 {
+            msgs.push(Msg::Input {
+                pos: i % 2,
+                id: 0,
+                data: i.to_string().into(),
+            });
+        }
+        server.handle_batch(msgs).await.unwrap();
+        assert_eq!(vec!["8", "9"], server.bar.snapshot());
+        // Output came last, so nothing is left to show.
+        assert!(server.output_timer.is_none());
+        assert_eq!(None, server.bar.show_unshown());
+        assert_eq!(5, server.bar.slot_meta(0).version_count);
+        assert_eq!(5, server.bar.slot_meta(1).version_count);
+    }
+
+    #[tokio::test]
+    async fn handle_batch_state_change() {
+        let dir = crate::tmp_path("handle_batch_state_change");
+        let mut conf = Conf {
+            dst: Some(conf::Dst::File {
+                path: dir.join("bar"),
+            }),
+            ..Conf::default()
+        };
+        conf.feeds.truncate(1);
+        conf.feeds[0].cmd = conf::FeedCmd::Cmd("sleep 10".to_string());
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let mut server = Server::new(conf, dir.clone(), tx, None);
+        server.output_interval = Duration::from_millis(10);
+        server.on().await.unwrap();
+        server.ensure_output_scheduled();
+        let output = loop {
+            let Api { msg } = rx.recv().await.unwrap();
+            if matches!(msg, Msg::Output) {
+                break msg;
+            }
+        };
+
+        // The last feed's exit turns the bar off, before it is turned back
+        // on, with a new output timer, in the same batch as the output
+        // which fired before all that.
+        server.off_begin().await;
+        let exit = loop {
+            let Api { msg } = rx.recv().await.unwrap();
+            if matches!(msg, Msg::FeedExit { .. }) {
+                break msg;
+            }
+        };
+        let (on_tx, on_rx) = oneshot::channel();
+        tokio::time::timeout(
+            Duration::from_secs(5),
+            server.handle_batch(vec![output, exit, Msg::On(on_tx)]),
+        )
+        .await
+        .unwrap()
+        .unwrap();
+        on_rx.await.unwrap().unwrap();
+        assert!(matches!(server.state, super::State::On));
+        assert!(server.output_timer.is_some());
+
+        // The new timer's output finds itself scheduled.
+        let output = loop {
+            let Api { msg } = rx.recv().await.unwrap();
+            if matches!(msg, Msg::Output) {
+                break msg;
+            }
+        };
+        server.handle_batch(vec![output]).await.unwrap();
+
+        server.off_begin().await;
+        while !matches!(server.state, super::State::Off) {
+            let Api { msg } = rx.recv().await.unwrap();
+            server.handle(msg).await.unwrap();
+        }
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn out_of_range_pos() {
+        let logs = LogCapture::default();
+        let subscriber = {
+            let logs = logs.clone();
+            tracing_subscriber::fmt()
+                .with_max_level(tracing::Level::WARN)
+                .with_ansi(false)
+                .with_writer(move || logs.clone())
+                .finish()
+        };
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        let dir = crate::tmp_path("out_of_range_pos");
+        let (tx, _rx) = mpsc::unbounded_channel();
+        let mut server = Server::new(Conf::default(), dir, tx, None);
+        server.state = super::State::On;
+        server.feed_ids = vec![Some(0), Some(0)];
+        server.expiration_timers = vec![None, None];
+        assert_eq!(2, server.bar.len());
+        for msg in [
+            Msg::Input {
+                pos: 2,
+                id: 0,
+                data: "x".to_string().into(),
+            },
+            Msg::Expiration { pos: 2 },
+            Msg::FeedExit {
+                pos: 5,
+                result: Ok(std::process::ExitStatus::default()),
+                expected: true,
+            },
+        ] {
+            server.handle(msg).await.unwrap();
+        }
+        let logs = String::from_utf8(logs.0.lock().unwrap().clone()).unwrap();
+        assert_eq!(
+            3,
+            logs.matches("Ignoring for a position not in bar.").count()
+        );
+
+        // Still up.
+        let msg = Msg::Input {
+            pos: 1,
+            id: 0,
+            data: "b".to_string().into(),
+        };
+        server.handle(msg).await.unwrap();
+        assert_eq!(vec!["", "b"], server.bar.snapshot());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn adaptive_output_interval() {
+        let dir = crate::tmp_path("adaptive_output_interval");
+        let conf = Conf {
+            adaptive_output_interval: Some(true),
+            ..Conf::default()
+        };
+        let (tx, _rx) = mpsc::unbounded_channel();
+        let mut server = Server::new(conf, dir, tx, None);
+        server.state = super::State::On;
+        server.feed_ids = vec![Some(0), Some(0)];
+        server.expiration_timers = vec![None, None];
+        assert_eq!(Duration::from_secs(1), server.current_output_interval());
+
+        // 10 Hz.
+        for i in 0..20 {
+            let msg = Msg::Input {
+                pos: 0,
+                id: 0,
+                data: i.to_string().into(),
+            };
+            server.handle(msg).await.unwrap();
+            tokio::time::advance(Duration::from_millis(100)).await;
+        }
+        assert_eq!(
+            Duration::from_millis(50),
+            server.current_output_interval()
+        );
+
+        // Way too fast.
+        for _ in 0..10 {
+            let msg = Msg::Input {
+                pos: 1,
+                id: 0,
+                data: Default::default(),
+            };
+            server.handle(msg).await.unwrap();
+            tokio::time::advance(Duration::from_millis(1)).await;
+        }
+        assert_eq!(
+            Duration::from_millis(10),
+            server.current_output_interval()
+        );
+    }
+
+    #[tokio::test]
+    async fn x11_breaker() {
+        // Not to overwrite the root window name of an actual display.
+        if std::env::var_os("DISPLAY").is_some() {
+            return;
+        }
+        let logs = LogCapture::default();
+        let subscriber = {
+            let logs = logs.clone();
+            tracing_subscriber::fmt()
+                .with_max_level(tracing::Level::WARN)
+                .with_ansi(false)
+                .with_writer(move || logs.clone())
+                .finish()
+        };
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        let dir = crate::tmp_path("x11_breaker");
+        let conf = Conf {
+            dst: Some(conf::Dst::X11RootWindowName),
+            ..Conf::default()
+        };
+        let (tx, _rx) = mpsc::unbounded_channel();
+        let mut server = Server::new(conf, dir, tx, None);
+        let failures = u64::from(super::X11_FAILURES_TO_OPEN);
+        for _ in 0..failures {
+            server.output_blank().await;
+        }
+        assert_eq!(failures, server.output_error_count);
+        assert_eq!(CircuitBreakerState::Open, server.x11_breaker.state());
+
+        // Skipped, rather than failed again.
+        for _ in 0..10 {
+            server.output_blank().await;
+        }
+        assert_eq!(failures, server.output_error_count);
+        let logs = String::from_utf8(logs.0.lock().unwrap().clone()).unwrap();
+        assert_eq!(failures as usize, logs.matches("Output failed").count());
+        assert_eq!(1, logs.matches("Pausing it.").count());
+    }
+
+    #[test]
+    fn x11_reconnect() {
+        // Not to overwrite the root window name of an actual display.
+        if std::env::var_os("DISPLAY").is_some() {
+            return;
+        }
+        let mut x11 = Some(X11::disconnected());
+        assert!(Server::output_x11(&mut x11, "a").is_err());
+        // Tried to reconnect, rather than keep the closed connection.
+        assert!(x11.is_none());
+    }
+
+    #[tokio::test]
+    async fn timer_panics() {
+        let dir = crate::tmp_path("timer_panics");
+        let mut conf = Conf::default();
+        conf.feeds.truncate(1);
+        let (tx, _rx) = mpsc::unbounded_channel();
+        let mut server = Server::new(conf, dir, tx, None);
+        server.state = super::State::On;
+        server.feeds = vec![None];
+        server.feed_ids = vec![Some(0)];
+        server.bar.set(0, "a");
+        let panicking =
+            || tokio::spawn(async { std::panic::panic_any("boom") });
+
+        server.expiration_timers = vec![Some(panicking())];
+        server.handle(Msg::Expiration { pos: 0 }).await.unwrap();
+        assert_eq!(vec!["a"], server.bar.snapshot());
+        assert_eq!(1, server.total_timer_panics);
+
+        server.output_timer = Some(panicking());
+        server.handle(Msg::Output).await.unwrap();
+        assert_eq!(2, server.total_timer_panics);
+        match server.status().await.unwrap() {
+            bar::status::Status::UpOn { timer_panics, .. } => {
+                assert_eq!(2, timer_panics);
+            }
+            status => unreachable!("Unexpected status: {:?}", status),
+        }
+
+        // Still going.
+        server
+            .handle(Msg::Input {
+                pos: 0,
+                id: 0,
+                data: "b".to_string().into(),
+            })
+            .await
+            .unwrap();
+        assert_eq!(vec!["b"], server.bar.snapshot());
+        assert!(server.output_timer.is_some());
+    }
+
+    #[tokio::test]
+    async fn output_errors() {
+        let dir = crate::tmp_path("output_errors");
+        tokio::fs::create_dir_all(&dir).await.unwrap();
+        let conf = Conf {
+            dst: Some(conf::Dst::File {
+                path: dir.join("nonexistent").join("bar"),
+            }),
+            ..Conf::default()
+        };
+        let (tx, _rx) = mpsc::unbounded_channel();
+        let mut server = Server::new(conf, dir.clone(), tx, None);
+        server.feeds = vec![None, None];
+        let output_errors = |status| match status {
+            bar::status::Status::UpOn {
+                output_error_count,
+                last_output_error,
+                ..
+            } => (output_error_count, last_output_error),
+            status => unreachable!("Unexpected status: {:?}", status),
+        };
+
+        let (count, last) = output_errors(server.status().await.unwrap());
+        assert_eq!(0, count);
+        assert_eq!(None, last);
+
+        for i in 1..=3 {
+            server.bar.set(0, &i.to_string());
+            server.output().await;
+            let (count, last) = output_errors(server.status().await.unwrap());
+            assert_eq!(i, count);
+            assert!(last.is_some());
+        }
+
+        server.conf.dst = Some(conf::Dst::File {
+            path: dir.join("bar"),
+        });
+        server.bar.set(0, "ok");
+        server.output().await;
+        let (count, last) = output_errors(server.status().await.unwrap());
+        assert_eq!(0, count);
+        assert!(last.is_some());
+
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn avg_output_interval() {
+        let mut activity = super::FeedActivityTracker::new(2);
+        assert_eq!(None, activity.avg_interval(0));
+        activity.record(0, tokio::time::Instant::now());
+        assert_eq!(None, activity.avg_interval(0));
+
+        // 1 Hz, with jitter, for longer than the window.
+        for i in 0..30u64 {
+            let jitter = Duration::from_millis(i % 3 * 10);
+            tokio::time::advance(Duration::from_secs(1) - jitter).await;
+            activity.record(0, tokio::time::Instant::now());
+            tokio::time::advance(jitter).await;
+        }
+        let avg = activity.avg_interval(0).unwrap().as_secs_f64();
+        assert!((avg - 1.0).abs() < 0.01, "{}", avg);
+        assert_eq!(None, activity.avg_interval(1));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn join_ordered_concurrently() {
+        let started = tokio::time::Instant::now();
+        let futs: Vec<_> = (0..10u64)
+            .map(|i| async move {
+                // Later ones finish first.
+                tokio::time::sleep(Duration::from_secs(10 - i)).await;
+                if i == 3 {
+                    Err(anyhow::anyhow!("3 failed"))
+                } else {
+                    Ok(i)
+                }
+            })
+            .collect();
+        let results = join_ordered(futs).await;
+        // Concurrent, so as long as the slowest, not the sum.
+        assert_eq!(Duration::from_secs(10), started.elapsed());
+        assert_eq!(10, results.len());
+        for (i, result) in results.into_iter().enumerate() {
+            match result {
+                Ok(n) => assert_eq!(i as u64, n),
+                Err(error) => {
+                    assert_eq!(3, i);
+                    assert_eq!("3 failed", error.to_string());
+                }
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn timeout() {
+        let slow = async {
+            tokio::time::sleep(Duration::from_secs(5)).await;
+            Ok(())
+        };
+        let result = with_timeout("slow", Duration::from_millis(10), slow);
+        assert!(matches!(
+            result.await,
+            Err(ApiError::Timeout { op }) if op == "slow"
+        ));
+
+        let fast = async { Ok(42) };
+        let result = with_timeout("fast", Duration::from_secs(5), fast);
+        assert!(matches!(result.await, Ok(42)));
+    }
+
+    #[tokio::test]
+    async fn drop_while_panicking() {
+        let dir = crate::tmp_path("drop_while_panicking");
+        tokio::fs::create_dir_all(&dir).await.unwrap();
+        let data = r#"
+            sep = "|"
+            pad_left = ""
+            pad_right = ""
+            expiry_character = "_"
+            output_interval = 0.05
+            dst = "StdOut"
+            [[feeds]]
+            name = "a"
+            cmd = "sleep 10"
+        "#;
+        tokio::fs::write(conf::path_conf(&dir), data).await.unwrap();
+        let conf = Conf::load_or_init(&dir).await.unwrap();
+        let pid_file = conf::path_server_pid(&dir);
+        tokio::fs::write(&pid_file, "1").await.unwrap();
+        let mut siblings = tokio::task::JoinSet::new();
+        let tx = super::start(&mut siblings, &dir, conf);
+        super::on(&tx).await.unwrap();
+        let feed_dir =
+            conf::path_feed_dir(&conf::path_feeds_dir(&dir, None), 0, "a");
+        let feed_pid: i32 =
+            tokio::fs::read_to_string(conf::path_feed_pid(&feed_dir))
+                .await
+                .unwrap()
+                .parse()
+                .unwrap();
+        let feed_pid = nix::unistd::Pid::from_raw(feed_pid);
+
+        // One more than scheduled, so one of them finds no timer and
+        // panics.
+        tx.send(Api { msg: Msg::Output }).unwrap();
+        let joined = siblings.join_next().await.unwrap();
+        assert!(joined.unwrap_err().is_panic());
+        assert!(!pid_file.exists());
+        let mut retries = 50;
+        while nix::sys::signal::kill(feed_pid, None).is_ok() {
+            assert!(retries > 0, "Feed still alive: {}", feed_pid);
+            retries -= 1;
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
 }
@@ -1,25 +1,61 @@
 // Status of the status bar :)
 
-use std::{collections::HashSet, path::PathBuf, time::Duration};
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
 
-use crate::ps;
+use anyhow::Context;
+
+use crate::{conf, ps};
 
 #[derive(Debug, serde::Serialize, serde::Deserialize)]
 pub struct Info {
     pub name: String,
     pub dir: PathBuf,
+    /// When the feed's process started.
+    #[serde(default)]
+    pub started_at: Option<SystemTime>,
+    #[serde(default)]
     pub age_of_output: Option<Duration>,
+    /// Average time between recent outputs of the feed.
+    #[serde(default)]
+    pub avg_output_interval_secs: Option<f64>,
+    /// Times the feed's slot was set since the bar was turned on.
+    pub update_count: u64,
+    #[serde(default)]
     pub age_of_log: Option<Duration>,
     pub log_size_bytes: u64,
-    pub log_lines: usize,
+    /// Everything in the feed's directory, including the log.
+    #[serde(default)]
+    pub feed_dir_bytes: u64,
+    /// Only counted on request, see Conf::status_include_log_lines.
+    #[serde(default)]
+    pub log_lines: Option<usize>,
+    /// Lines output by the feed, see Feed::get_output_lines.
+    pub log_lines_approx: u64,
+    /// Lines written to the log since the feed started, counted as they
+    /// are written, see Feed::get_stderr_lines.
+    pub log_lines_live: u64,
+    /// Unreadable output lines skipped, see Feed::get_line_errors.
+    pub line_errors: u32,
     pub pid: u32,
+    pub pgid: u32,
+    #[serde(default)]
     pub state: Option<ps::State>,
+    #[serde(default)]
+    pub cpu_pct: Option<f32>,
+    #[serde(default)]
+    pub mem_pct: Option<f32>,
+    pub pdepth: usize,
     pub pdescendants: HashSet<ps::Proc>,
 }
 
 #[derive(Debug, serde::Serialize, serde::Deserialize)]
 pub struct Feed {
     pub position: usize,
+    #[serde(default)]
     pub info: Option<Info>,
 }
 
@@ -29,116 +65,581 @@ pub enum Audience {
     Machine,
 }
 
+#[derive(Debug, Clone, Copy, clap::ValueEnum)]
+pub enum Format {
+    /// Aligned columns, for humans.
+    Table,
+    /// Tab-separated values, for scripts.
+    Tsv,
+    /// The whole status, such as to save as a baseline for `diff`.
+    Json,
+}
+
 #[derive(Debug, serde::Serialize, serde::Deserialize)]
 pub enum Status {
-    Down,
-    UpOff,
-    UpOn { feeds: Vec<Feed> },
+    /// Known only when the server got to record its shutdown.
+    Down {
+        #[serde(default)]
+        since: Option<SystemTime>,
+        #[serde(default)]
+        last_error: Option<String>,
+    },
+    /// Server seems to be running, but can't be connected to.
+    Unreachable { reason: String },
+    UpOff {
+        connections: usize,
+        /// Conf::bar_title, to tell apart multiple bar instances.
+        #[serde(default)]
+        title: Option<String>,
+    },
+    UpOn {
+        feeds: Vec<Feed>,
+        connections: usize,
+        #[serde(default)]
+        title: Option<String>,
+        /// Consecutive failed writes of the bar to its destination.
+        output_error_count: u64,
+        #[serde(default)]
+        last_output_error: Option<String>,
+        /// Internal timer tasks which panicked, since the server started.
+        #[serde(default)]
+        timer_panics: u64,
+        /// Feeds yet to exit, while turning off.
+        #[serde(default)]
+        stopping: Option<usize>,
+    },
+    /// Server answered, but with an error instead of the status, such as
+    /// when its bar server is gone. Last, to not renumber the variants
+    /// which go over the wire.
+    Failed { error: String },
+}
+
+impl Status {
+    /// Active control connections are only known to the control server,
+    /// which fills them in after getting the rest of the status from the
+    /// bar server.
+    pub fn set_connections(&mut self, n: usize) {
+        match self {
+            Self::Down { .. }
+            | Self::Unreachable { .. }
+            | Self::Failed { .. } => {}
+            Self::UpOff { connections, .. }
+            | Self::UpOn { connections, .. } => {
+                *connections = n;
+            }
+        }
+    }
 }
 
 impl Default for Status {
     fn default() -> Self {
-        Self::Down
+        Self::Down {
+            since: None,
+            last_error: None,
+        }
     }
 }
 
 impl Status {
+    /// Can be loaded back, such as to be the baseline of a diff.
+    pub fn to_json(&self) -> anyhow::Result<String> {
+        let json = serde_json::to_string_pretty(self)?;
+        Ok(json)
+    }
+
     pub fn to_str(&self, audience: Audience) -> String {
-        match self {
-            Self::Down => "down".to_string(),
-            Self::UpOff => "up off".to_string(),
-            Self::UpOn { feeds } => {
+        self.to_str_colored(audience, false)
+    }
+
+    /// Color only applies to the human audience's feed table, where it
+    /// marks the process state of each feed.
+    pub fn to_str_colored(&self, audience: Audience, color: bool) -> String {
+        match (self, audience) {
+            (Self::Down { .. }, Audience::Machine) => "down".to_string(),
+            (Self::Down { since, last_error }, Audience::Human) => {
+                let mut lines = vec!["down".to_string()];
+                if let Some(since) = since {
+                    lines.push(format!(
+                        "since: {}",
+                        humantime::format_rfc3339_seconds(*since)
+                    ));
+                }
+                if let Some(last_error) = last_error {
+                    lines.push(format!("last error: {}", last_error));
+                }
+                lines.join("\n")
+            }
+            (Self::Unreachable { .. }, Audience::Machine) => {
+                "unreachable".to_string()
+            }
+            (Self::Unreachable { reason }, Audience::Human) => {
+                format!("unreachable\nreason: {}", reason)
+            }
+            (Self::Failed { .. }, Audience::Machine) => "failed".to_string(),
+            (Self::Failed { error }, Audience::Human) => {
+                format!("failed\nerror: {}", error)
+            }
+            (Self::UpOff { .. }, Audience::Machine) => "up off".to_string(),
+            (Self::UpOff { connections, title }, Audience::Human) => {
+                let state = match title {
+                    None => "up off".to_string(),
+                    Some(title) => format!("up off ({})", title),
+                };
+                format!("{}\n\nconnections: {}", state, connections)
+            }
+            (Self::UpOn { .. }, Audience::Machine) => self.to_tsv(),
+            (
+                Self::UpOn {
+                    feeds,
+                    connections,
+                    title,
+                    output_error_count,
+                    last_output_error,
+                    timer_panics,
+                    stopping,
+                },
+                Audience::Human,
+            ) => {
                 let mut table = comfy_table::Table::new();
                 table.load_preset(comfy_table::presets::NOTHING); // No borders or dividers.
-                table.set_header([
-                    "POSITION",
-                    "NAME",
-                    "DIR",
-                    "LAST_OUTPUTTED",
-                    "LAST_LOGGED",
-                    "LOG_SIZE",
-                    "LOG_LINES",
-                    "PID",
-                    "PROC_STATE",
-                    "PROC_DESCENDANTS",
-                ]);
-                for Feed { position, info } in feeds {
-                    match info {
-                        Some(Info {
-                            name,
-                            dir,
-                            age_of_output,
-                            age_of_log,
-                            log_size_bytes,
-                            log_lines,
-                            pid,
-                            state,
-                            pdescendants,
-                        }) => {
-                            let pdescendants = if pdescendants.is_empty() {
-                                "-".to_string()
-                            } else {
-                                let mut pdescendants: Vec<&ps::Proc> =
-                                    pdescendants.iter().collect();
-                                pdescendants.sort_by_key(|p| p.pid);
-                                pdescendants
-                                    .iter()
-                                    .map(|p| {
-                                        format!(
-                                            "{}:{}",
-                                            p.pid,
-                                            p.state.to_str()
-                                        )
-                                    })
-                                    .collect::<Vec<String>>()
-                                    .join(",")
-                            };
-                            let log_size = match audience {
-                                Audience::Human => {
-                                    bytesize::ByteSize(*log_size_bytes)
-                                        .to_string()
-                                }
-                                Audience::Machine => {
-                                    log_size_bytes.to_string()
-                                }
-                            };
-                            table.add_row(vec![
-                                &position.to_string(),
-                                name,
-                                dir.to_string_lossy().as_ref(),
-                                &duration_fmt(*age_of_output, audience),
-                                &duration_fmt(*age_of_log, audience),
-                                &log_size,
-                                &log_lines.to_string(),
-                                &pid.to_string(),
-                                &state
-                                    .map(|s| s.to_str().to_string())
-                                    .unwrap_or("-".to_string()),
-                                &pdescendants,
-                            ]);
-                        }
-                        None => {
-                            table.add_row(vec![
-                                &position.to_string(),
-                                "-",
-                                "-",
-                                "-",
-                                "-",
-                                "-",
-                                "-",
-                                "-",
-                                "-",
-                                "-",
-                            ]);
+                table.set_header(HEADER);
+                if color {
+                    table.enforce_styling();
+                    table.style_text_only();
+                } else {
+                    table.force_no_tty();
+                }
+                for (row, feed) in
+                    rows(feeds, audience).into_iter().zip(feeds)
+                {
+                    let state_color = state_color(
+                        feed.info.as_ref().map(|info| info.state),
+                    );
+                    let row = row.into_iter().enumerate().map(|(i, cell)| {
+                        let cell = comfy_table::Cell::new(cell);
+                        match state_color {
+                            Some(c) if i == PROC_STATE_COLUMN => cell.fg(c),
+                            _ => cell,
                         }
-                    }
+                    });
+                    table.add_row(row);
+                }
+                let mut output_table = comfy_table::Table::new();
+                output_table.load_preset(comfy_table::presets::NOTHING);
+                output_table.set_header([
+                    "OUT_ERRORS",
+                    "LAST_OUT_ERR",
+                    "TIMER_PANICS",
+                ]);
+                output_table.add_row([
+                    output_error_count.to_string(),
+                    last_output_error.clone().unwrap_or("-".to_string()),
+                    timer_panics.to_string(),
+                ]);
+                let title = title
+                    .as_ref()
+                    .map(|title| format!("{}\n\n", title))
+                    .unwrap_or_default();
+                let stopping = stopping
+                    .map(|n| format!("\nstopping: {}", n))
+                    .unwrap_or_default();
+                format!(
+                    "{}{}\n\n{}\n\nconnections: {}{}",
+                    title, table, output_table, connections, stopping
+                )
+            }
+        }
+    }
+
+    /// Tab-separated values, with a header line and no padding.
+    pub fn to_tsv(&self) -> String {
+        match self {
+            Self::Down { .. }
+            | Self::Unreachable { .. }
+            | Self::Failed { .. }
+            | Self::UpOff { .. } => self.to_str(Audience::Machine),
+            Self::UpOn { feeds, .. } => {
+                let mut lines = vec![HEADER.join("\t")];
+                for row in rows(feeds, Audience::Machine) {
+                    lines.push(row.join("\t"));
                 }
-                format!("{}", table)
+                lines.join("\n")
             }
         }
     }
 }
 
+/// A feed's field which changed by more than the threshold.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StatusDiff {
+    pub position: usize,
+    pub name: String,
+    pub field: &'static str,
+    pub old: f64,
+    pub new: f64,
+}
+
+impl StatusDiff {
+    /// Relative to the old value. Infinite if the old value is 0.
+    pub fn change(&self) -> f64 {
+        (self.new - self.old) / self.old.abs()
+    }
+}
+
+/// Compares the feeds found in both, by position and name, in
+/// age_of_output (seconds), log_size_bytes and output_rate_per_min (from the
+/// average output interval). Threshold is a fraction of the old value, i.e.
+/// 0.1 for 10%. Fields missing from either side are skipped, as are
+/// statuses other than UpOn.
+pub fn diff(old: &Status, new: &Status, threshold: f64) -> Vec<StatusDiff> {
+    let (
+        Status::UpOn {
+            feeds: old_feeds, ..
+        },
+        Status::UpOn {
+            feeds: new_feeds, ..
+        },
+    ) = (old, new)
+    else {
+        return Vec::new();
+    };
+    let mut diffs = Vec::new();
+    for new_feed in new_feeds {
+        let Some(new_info) = &new_feed.info else {
+            continue;
+        };
+        let old_info = old_feeds
+            .iter()
+            .filter(|old_feed| old_feed.position == new_feed.position)
+            .find_map(|old_feed| old_feed.info.as_ref())
+            .filter(|old_info| old_info.name == new_info.name);
+        let Some(old_info) = old_info else {
+            continue;
+        };
+        let fields = [
+            (
+                "age_of_output",
+                old_info.age_of_output.map(|age| age.as_secs_f64()),
+                new_info.age_of_output.map(|age| age.as_secs_f64()),
+            ),
+            (
+                "log_size_bytes",
+                Some(old_info.log_size_bytes as f64),
+                Some(new_info.log_size_bytes as f64),
+            ),
+            (
+                "output_rate_per_min",
+                old_info.output_rate_per_min(),
+                new_info.output_rate_per_min(),
+            ),
+        ];
+        for (field, old, new) in fields {
+            let (Some(old), Some(new)) = (old, new) else {
+                continue;
+            };
+            let diff = StatusDiff {
+                position: new_feed.position,
+                name: new_info.name.clone(),
+                field,
+                old,
+                new,
+            };
+            if old != new && diff.change().abs() > threshold {
+                diffs.push(diff);
+            }
+        }
+    }
+    diffs
+}
+
+pub fn diffs_to_str(diffs: &[StatusDiff]) -> String {
+    let mut table = comfy_table::Table::new();
+    table.load_preset(comfy_table::presets::NOTHING);
+    table.set_header(["POSITION", "NAME", "FIELD", "OLD", "NEW", "CHANGE"]);
+    for diff in diffs {
+        let change = diff.change();
+        let change = if change.is_finite() {
+            format!("{:+.1}%", change * 100.0)
+        } else {
+            "new".to_string()
+        };
+        table.add_row([
+            diff.position.to_string(),
+            diff.name.clone(),
+            diff.field.to_string(),
+            format!("{:.2}", diff.old),
+            format!("{:.2}", diff.new),
+            change,
+        ]);
+    }
+    table.to_string()
+}
+
+impl Info {
+    pub fn output_rate_per_min(&self) -> Option<f64> {
+        self.avg_output_interval_secs
+            .filter(|secs| *secs > 0.0)
+            .map(|secs| 60.0 / secs)
+    }
+}
+
+/// About the last server run, left in place after it stops, so that
+/// clients can tell when the server last ran even when it's down.
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+pub struct ServerInfo {
+    /// RFC 3339, like all the timestamps here.
+    pub started_at: String,
+    /// Only set when the server got to record its shutdown.
+    pub stopped_at: Option<String>,
+    /// What the server stopped on, if it wasn't a clean shutdown.
+    #[serde(default)]
+    pub last_error: Option<String>,
+    pub pid: u32,
+    /// SHA-256 of the config file the server started with.
+    pub conf_hash: String,
+    pub version: String,
+    /// Where the server kept its feeds, possibly given with --feeds-dir,
+    /// for finding them once it's gone.
+    #[serde(default)]
+    pub feeds_dir: Option<PathBuf>,
+}
+
+impl ServerInfo {
+    pub async fn write_started(
+        dir: &Path,
+        feeds_dir: &Path,
+    ) -> anyhow::Result<()> {
+        let conf_file = conf::path_conf(dir);
+        let conf_data = tokio::fs::read(&conf_file).await.context(
+            format!("Failed to read config file: {:?}", &conf_file),
+        )?;
+        let info = Self {
+            started_at: conf::timestamp_now(),
+            stopped_at: None,
+            last_error: None,
+            pid: std::process::id(),
+            conf_hash: sha256_hex(&conf_data),
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            feeds_dir: Some(feeds_dir.to_path_buf()),
+        };
+        info.write(dir).await
+    }
+
+    pub async fn write_stopped(
+        dir: &Path,
+        last_error: Option<String>,
+    ) -> anyhow::Result<()> {
+        let mut info = Self::read(dir).await?;
+        info.stopped_at = Some(conf::timestamp_now());
+        info.last_error = last_error;
+        info.write(dir).await
+    }
+
+    pub async fn read(dir: &Path) -> anyhow::Result<Self> {
+        let file = conf::path_server_info(dir);
+        let data = tokio::fs::read_to_string(&file)
+            .await
+            .context(format!("Failed to read server info: {:?}", &file))?;
+        let info = serde_json::from_str(&data)
+            .context(format!("Invalid server info: {:?}", &file))?;
+        Ok(info)
+    }
+
+    async fn write(&self, dir: &Path) -> anyhow::Result<()> {
+        let file = conf::path_server_info(dir);
+        tokio::fs::write(&file, serde_json::to_string_pretty(self)?)
+            .await
+            .context(format!("Failed to write server info: {:?}", &file))?;
+        Ok(())
+    }
+}
+
+fn sha256_hex(data: &[u8]) -> String {
+    use sha2::Digest;
+
+    sha2::Sha256::digest(data)
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+/// Status when the server can't be asked, from whatever it left behind.
+/// No recorded stop means the server did not shut down cleanly, or never
+/// ran.
+pub async fn status_from_server_info(dir: &Path) -> Status {
+    let info = match ServerInfo::read(dir).await {
+        Ok(info) => Some(info),
+        Err(error) => {
+            if conf::path_server_info(dir).exists() {
+                tracing::warn!(?error, "Invalid server info.");
+            }
+            None
+        }
+    };
+    match info.and_then(|info| Some((info.stopped_at?, info.last_error))) {
+        None => Status::default(),
+        Some((stopped_at, last_error)) => Status::Down {
+            since: humantime::parse_rfc3339(&stopped_at)
+                .map_err(|error| {
+                    tracing::warn!(
+                        ?stopped_at,
+                        ?error,
+                        "Invalid server stop time."
+                    );
+                })
+                .ok(),
+            last_error,
+        },
+    }
+}
+
+const HEADER: [&str; 21] = [
+    "POSITION",
+    "NAME",
+    "DIR",
+    "STARTED",
+    "LAST_OUTPUTTED",
+    "AVG_INTERVAL",
+    "UPDATE_COUNT",
+    "LAST_LOGGED",
+    "LOG_SIZE",
+    "FEED_DIR_SIZE",
+    "LOG_LINES",
+    "LOG_LINES_APPROX",
+    "LOG_LINES_LIVE",
+    "LINE_ERRORS",
+    "PID",
+    "PGID",
+    "PROC_STATE",
+    "%CPU",
+    "%MEM",
+    "PROC_DEPTH",
+    "PROC_DESCENDANTS",
+];
+
+const PROC_STATE_COLUMN: usize = 16;
+
+/// None is for feeds which aren't running, and Some(None) for those whose
+/// process state is unknown.
+fn state_color(
+    state: Option<Option<ps::State>>,
+) -> Option<comfy_table::Color> {
+    use comfy_table::Color;
+
+    match state {
+        None => Some(Color::DarkGrey),
+        Some(None) => None,
+        Some(Some(state)) => match state {
+            ps::State::RunQueue | ps::State::SleepInterruptible => {
+                Some(Color::Green)
+            }
+            ps::State::SleepUninterruptible => Some(Color::Yellow),
+            ps::State::Zombie | ps::State::Dead => Some(Color::Red),
+            ps::State::StoppedByJobControl | ps::State::StoppedByDebugger => {
+                Some(Color::DarkGrey)
+            }
+            ps::State::Idle | ps::State::Paging => None,
+        },
+    }
+}
+
+fn rows(feeds: &[Feed], audience: Audience) -> Vec<Vec<String>> {
+    let mut rows = Vec::with_capacity(feeds.len());
+    for Feed { position, info } in feeds {
+        match info {
+            Some(Info {
+                name,
+                dir,
+                started_at,
+                age_of_output,
+                avg_output_interval_secs,
+                update_count,
+                age_of_log,
+                log_size_bytes,
+                feed_dir_bytes,
+                log_lines,
+                log_lines_approx,
+                log_lines_live,
+                line_errors,
+                pid,
+                pgid,
+                state,
+                cpu_pct,
+                mem_pct,
+                pdepth,
+                pdescendants,
+            }) => {
+                let pdescendants = if pdescendants.is_empty() {
+                    "-".to_string()
+                } else {
+                    let mut procs: Vec<ps::Proc> =
+                        pdescendants.iter().cloned().collect();
+                    procs.sort();
+                    let mut pgids: Vec<u32> =
+                        procs.iter().map(|p| p.pgrp).collect();
+                    pgids.sort();
+                    pgids.dedup();
+                    pgids
+                        .iter()
+                        .map(|pgid| ps::group_display(&procs, *pgid))
+                        .collect::<Vec<String>>()
+                        .join("; ")
+                };
+                let age = started_at
+                    .and_then(|t| SystemTime::now().duration_since(t).ok());
+                let log_size = bytes_fmt(*log_size_bytes, audience);
+                let feed_dir_size = bytes_fmt(*feed_dir_bytes, audience);
+                rows.push(vec![
+                    position.to_string(),
+                    name.to_string(),
+                    dir.to_string_lossy().to_string(),
+                    duration_fmt(age, audience),
+                    duration_fmt(*age_of_output, audience),
+                    avg_output_interval_secs
+                        .map(|secs| format!("{:.2}", secs))
+                        .unwrap_or("-".to_string()),
+                    update_count.to_string(),
+                    duration_fmt(*age_of_log, audience),
+                    log_size,
+                    feed_dir_size,
+                    log_lines
+                        .map(|n| n.to_string())
+                        .unwrap_or("-".to_string()),
+                    log_lines_approx.to_string(),
+                    log_lines_live.to_string(),
+                    line_errors.to_string(),
+                    pid.to_string(),
+                    pgid.to_string(),
+                    state
+                        .map(|s| s.to_str().to_string())
+                        .unwrap_or("-".to_string()),
+                    pct_fmt(*cpu_pct),
+                    pct_fmt(*mem_pct),
+                    pdepth.to_string(),
+                    pdescendants,
+                ]);
+            }
+            None => {
+                let mut row = vec!["-".to_string(); HEADER.len()];
+                row[0] = position.to_string();
+                rows.push(row);
+            }
+        }
+    }
+    rows
+}
+
+fn bytes_fmt(bytes: u64, audience: Audience) -> String {
+    match audience {
+        Audience::Human => bytesize::ByteSize(bytes).to_string(),
+        Audience::Machine => bytes.to_string(),
+    }
+}
+
+fn pct_fmt(pct: Option<f32>) -> String {
+    pct.map_or_else(|| "-".to_string(), |pct| format!("{:.1}", pct))
+}
+
 fn duration_fmt(duration: Option<Duration>, audience: Audience) -> String {
     match (duration, audience) {
         (None, Audience::Human) => "never".to_string(),
@@ -159,15 +660,568 @@ fn duration_fmt(duration: Option<Duration>, audience: Audience) -> String {
 
 #[cfg(test)]
 mod tests {
+    use std::{
+        collections::HashSet,
+        path::PathBuf,
+        time::{Duration, SystemTime},
+    };
+
+    use super::{
+        diff, status_from_server_info, Feed, Info, ServerInfo, Status,
+        StatusDiff, HEADER,
+    };
+    use crate::{conf::Conf, ps};
+
     #[test]
     fn test() {
         assert_eq!(
             "down",
-            super::Status::Down.to_str(super::Audience::Machine)
+            super::Status::default().to_str(super::Audience::Machine)
+        );
+        assert_eq!(
+            "down\nlast error: oops",
+            super::Status::Down {
+                since: None,
+                last_error: Some("oops".to_string())
+            }
+            .to_str(super::Audience::Human)
+        );
+        assert_eq!(
+            "unreachable",
+            super::Status::Unreachable {
+                reason: "refused".to_string()
+            }
+            .to_str(super::Audience::Machine)
+        );
+        assert_eq!(
+            "failed\nerror: Bar server is not running",
+            super::Status::Failed {
+                error: "Bar server is not running".to_string()
+            }
+            .to_str(super::Audience::Human)
         );
         assert_eq!(
             "up off",
-            super::Status::UpOff.to_str(super::Audience::Machine)
+            super::Status::UpOff {
+                connections: 1,
+                title: Some("laptop".to_string())
+            }
+            .to_str(super::Audience::Machine)
         );
+        assert_eq!(
+            "up off (laptop)\n\nconnections: 1",
+            super::Status::UpOff {
+                connections: 1,
+                title: Some("laptop".to_string())
+            }
+            .to_str(super::Audience::Human)
+        );
+        assert_eq!(
+            "up off\n\nconnections: 1",
+            super::Status::UpOff {
+                connections: 1,
+                title: None
+            }
+            .to_str(super::Audience::Human)
+        );
+    }
+
+    #[test]
+    fn tsv() {
+        let status = Status::UpOn {
+            feeds: vec![
+                Feed {
+                    position: 1,
+                    info: Some(Info {
+                        name: "uptime".to_string(),
+                        dir: PathBuf::from("/tmp/feeds/00-uptime"),
+                        started_at: None,
+                        age_of_output: Some(Duration::from_millis(1500)),
+                        avg_output_interval_secs: Some(1.004),
+                        update_count: 7,
+                        age_of_log: None,
+                        log_size_bytes: 2048,
+                        feed_dir_bytes: 2053,
+                        log_lines: Some(3),
+                        log_lines_approx: 4,
+                        log_lines_live: 5,
+                        line_errors: 6,
+                        pid: 42,
+                        pgid: 42,
+                        state: None,
+                        cpu_pct: Some(1.5),
+                        mem_pct: None,
+                        pdepth: 0,
+                        pdescendants: HashSet::new(),
+                    }),
+                },
+                Feed {
+                    position: 2,
+                    info: None,
+                },
+            ],
+            connections: 1,
+            title: Some("laptop".to_string()),
+            output_error_count: 0,
+            last_output_error: None,
+            timer_panics: 0,
+            stopping: None,
+        };
+        let tsv = status.to_tsv();
+        let lines: Vec<Vec<&str>> =
+            tsv.lines().map(|line| line.split('\t').collect()).collect();
+        assert_eq!(3, lines.len());
+        for fields in &lines {
+            assert_eq!(HEADER.len(), fields.len());
+        }
+        assert_eq!(HEADER.as_slice(), lines[0].as_slice());
+        assert_eq!(
+            [
+                "1",
+                "uptime",
+                "/tmp/feeds/00-uptime",
+                "-1.00",
+                "1.50",
+                "1.00",
+                "7",
+                "-1.00",
+                "2048",
+                "2053",
+                "3",
+                "4",
+                "5",
+                "6",
+                "42",
+                "42",
+                "-",
+                "1.5",
+                "-",
+                "0",
+                "-"
+            ]
+            .as_slice(),
+            lines[1].as_slice()
+        );
+        assert_eq!("2", lines[2][0]);
+        assert!(lines[2][1..].iter().all(|field| *field == "-"));
+        assert_eq!(tsv, status.to_str(super::Audience::Machine));
+        assert!(status
+            .to_str(super::Audience::Human)
+            .starts_with("laptop\n\n"));
+    }
+
+    fn fixture_status() -> Status {
+        let info = Info {
+            name: "a".to_string(),
+            dir: PathBuf::from("/tmp/feeds/a"),
+            started_at: Some(
+                SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000),
+            ),
+            age_of_output: Some(Duration::from_millis(1500)),
+            avg_output_interval_secs: Some(2.5),
+            update_count: 3,
+            age_of_log: None,
+            log_size_bytes: 100,
+            feed_dir_bytes: 200,
+            log_lines: Some(4),
+            log_lines_approx: 5,
+            log_lines_live: 6,
+            line_errors: 0,
+            pid: 123,
+            pgid: 120,
+            state: Some(ps::State::SleepInterruptible),
+            cpu_pct: Some(1.5),
+            mem_pct: None,
+            pdepth: 1,
+            pdescendants: HashSet::new(),
+        };
+        Status::UpOn {
+            feeds: vec![
+                Feed {
+                    position: 1,
+                    info: Some(info),
+                },
+                Feed {
+                    position: 2,
+                    info: None,
+                },
+            ],
+            connections: 1,
+            title: Some("laptop".to_string()),
+            output_error_count: 0,
+            last_output_error: None,
+            timer_panics: 0,
+            stopping: Some(2),
+        }
+    }
+
+    /// Status as sent by the control server. Any change is incompatible
+    /// with clients built before it, since Bincode has no field names to
+    /// skip unknown or fill in missing fields by. Update only together
+    /// with such a deliberate change.
+    const STATUS_BINCODE: &[u8] = &[
+        3, 2, 1, 1, 1, 97, 12, 47, 116, 109, 112, 47, 102, 101, 101, 100,
+        115, 47, 97, 1, 252, 0, 241, 83, 101, 0, 1, 1, 252, 0, 101, 205, 29,
+        1, 0, 0, 0, 0, 0, 0, 4, 64, 3, 0, 100, 200, 1, 4, 5, 6, 0, 123, 120,
+        1, 1, 1, 0, 0, 192, 63, 0, 1, 0, 2, 0, 1, 1, 6, 108, 97, 112, 116,
+        111, 112, 0, 0, 0, 1, 2,
+    ];
+
+    #[test]
+    fn bincode_snapshot() {
+        use std::pin::Pin;
+
+        use tarpc::tokio_serde::{
+            formats::Bincode, Deserializer, Serializer,
+        };
+        use tokio_util::bytes::BytesMut;
+
+        let mut codec = Bincode::<Status, Status>::default();
+        let bytes =
+            Pin::new(&mut codec).serialize(&fixture_status()).unwrap();
+        assert_eq!(STATUS_BINCODE, &bytes[..]);
+
+        let decoded = Pin::new(&mut codec)
+            .deserialize(&BytesMut::from(STATUS_BINCODE))
+            .unwrap();
+        let reencoded = Pin::new(&mut codec).serialize(&decoded).unwrap();
+        assert_eq!(STATUS_BINCODE, &reencoded[..]);
+    }
+
+    const STATUS_JSON: &str = r#"{
+  "UpOn": {
+    "feeds": [
+      {
+        "position": 1,
+        "info": {
+          "name": "a",
+          "dir": "/tmp/feeds/a",
+          "started_at": {
+            "secs_since_epoch": 1700000000,
+            "nanos_since_epoch": 0
+          },
+          "age_of_output": {
+            "secs": 1,
+            "nanos": 500000000
+          },
+          "avg_output_interval_secs": 2.5,
+          "update_count": 3,
+          "age_of_log": null,
+          "log_size_bytes": 100,
+          "feed_dir_bytes": 200,
+          "log_lines": 4,
+          "log_lines_approx": 5,
+          "log_lines_live": 6,
+          "line_errors": 0,
+          "pid": 123,
+          "pgid": 120,
+          "state": "SleepInterruptible",
+          "cpu_pct": 1.5,
+          "mem_pct": null,
+          "pdepth": 1,
+          "pdescendants": []
+        }
+      },
+      {
+        "position": 2,
+        "info": null
+      }
+    ],
+    "connections": 1,
+    "title": "laptop",
+    "output_error_count": 0,
+    "last_output_error": null,
+    "timer_panics": 0,
+    "stopping": 2
+  }
+}"#;
+
+    #[test]
+    fn json_snapshot() {
+        let json = fixture_status().to_json().unwrap();
+        assert_eq!(STATUS_JSON, json);
+        assert!(fixture_status()
+            .to_str(super::Audience::Human)
+            .ends_with("\nstopping: 2"));
+        let decoded: Status = serde_json::from_str(STATUS_JSON).unwrap();
+        assert_eq!(STATUS_JSON, decoded.to_json().unwrap());
+
+        // As saved before the fields with defaults were added.
+        let mut old: serde_json::Value =
+            serde_json::from_str(STATUS_JSON).unwrap();
+        let up_on = old["UpOn"].as_object_mut().unwrap();
+        for field in
+            ["title", "last_output_error", "timer_panics", "stopping"]
+        {
+            up_on.remove(field);
+        }
+        let info = up_on["feeds"][0]["info"].as_object_mut().unwrap();
+        for field in ["started_at", "feed_dir_bytes", "mem_pct"] {
+            info.remove(field);
+        }
+        match serde_json::from_value(old).unwrap() {
+            Status::UpOn {
+                feeds,
+                title: None,
+                timer_panics: 0,
+                stopping: None,
+                ..
+            } => {
+                let info = feeds[0].info.as_ref().unwrap();
+                assert_eq!(None, info.started_at);
+                assert_eq!(0, info.feed_dir_bytes);
+                assert_eq!(5, info.log_lines_approx);
+            }
+            status => panic!("Unexpected status: {:?}", status),
+        }
+    }
+
+    /// Position, name, age of output, log size and avg output interval.
+    type FixtureFeed<'a> = (usize, &'a str, Option<f64>, u64, Option<f64>);
+
+    fn fixture_json(feeds: &[FixtureFeed]) -> String {
+        let feeds: Vec<String> = feeds
+            .iter()
+            .map(|(position, name, age, log_size, interval)| {
+                let age = age
+                    .map(|secs| {
+                        serde_json::to_string(&Duration::from_secs_f64(secs))
+                            .unwrap()
+                    })
+                    .unwrap_or("null".to_string());
+                let interval = interval
+                    .map(|secs| secs.to_string())
+                    .unwrap_or("null".to_string());
+                format!(
+                    r#"{{
+                        "position": {position},
+                        "info": {{
+                            "name": "{name}",
+                            "dir": "/tmp/feeds/{name}",
+                            "age_of_output": {age},
+                            "avg_output_interval_secs": {interval},
+                            "update_count": 1,
+                            "age_of_log": null,
+                            "log_size_bytes": {log_size},
+                            "log_lines": null,
+                            "log_lines_approx": 0,
+                            "log_lines_live": 0,
+                            "line_errors": 0,
+                            "pid": 1,
+                            "pgid": 1,
+                            "state": "SleepInterruptible",
+                            "cpu_pct": null,
+                            "mem_pct": null,
+                            "pdepth": 0,
+                            "pdescendants": []
+                        }}
+                    }}"#
+                )
+            })
+            .collect();
+        format!(
+            r#"{{
+                "UpOn": {{
+                    "feeds": [{}],
+                    "connections": 1,
+                    "title": null,
+                    "output_error_count": 0,
+                    "last_output_error": null
+                }}
+            }}"#,
+            feeds.join(", ")
+        )
+    }
+
+    #[test]
+    fn status_diff() {
+        let old: Status = serde_json::from_str(&fixture_json(&[
+            (1, "uptime", Some(10.0), 1000, Some(1.0)),
+            (2, "time", Some(1.0), 500, Some(2.0)),
+            (3, "gone", Some(1.0), 100, None),
+        ]))
+        .unwrap();
+        let new: Status = serde_json::from_str(&fixture_json(&[
+            // Age +5%, size +20%, rate -50%:
+            (1, "uptime", Some(10.5), 1200, Some(2.0)),
+            // No interval in baseline, so no rate to compare:
+            (2, "time", Some(1.0), 500, None),
+            // Same position, but a different feed:
+            (3, "new", Some(100.0), 0, None),
+            // Not in baseline:
+            (4, "newer", Some(100.0), 0, None),
+        ]))
+        .unwrap();
+        let expected = vec![
+            StatusDiff {
+                position: 1,
+                name: "uptime".to_string(),
+                field: "log_size_bytes",
+                old: 1000.0,
+                new: 1200.0,
+            },
+            StatusDiff {
+                position: 1,
+                name: "uptime".to_string(),
+                field: "output_rate_per_min",
+                old: 60.0,
+                new: 30.0,
+            },
+        ];
+        assert_eq!(expected, diff(&old, &new, 0.1));
+        assert_eq!(3, diff(&old, &new, 0.0).len());
+        assert!(diff(&old, &new, 0.5).is_empty());
+        assert!(diff(&old, &old, 0.0).is_empty());
+        assert!(diff(&Status::default(), &new, 0.0).is_empty());
+
+        // From nothing to something is always a change.
+        let grown: Status = serde_json::from_str(&fixture_json(&[(
+            2,
+            "time",
+            Some(1.0),
+            500,
+            None,
+        )]))
+        .unwrap();
+        let empty: Status = serde_json::from_str(&fixture_json(&[(
+            2,
+            "time",
+            Some(0.0),
+            500,
+            None,
+        )]))
+        .unwrap();
+        let diffs = diff(&empty, &grown, 1000.0);
+        assert_eq!(1, diffs.len());
+        assert!(diffs[0].change().is_infinite());
+    }
+
+    #[test]
+    fn color() {
+        assert_eq!("PROC_STATE", HEADER[super::PROC_STATE_COLUMN]);
+        let info = |state| Info {
+            name: "uptime".to_string(),
+            dir: PathBuf::from("/tmp/feeds/00-uptime"),
+            started_at: None,
+            age_of_output: None,
+            avg_output_interval_secs: None,
+            update_count: 0,
+            age_of_log: None,
+            log_size_bytes: 0,
+            feed_dir_bytes: 0,
+            log_lines: None,
+            log_lines_approx: 0,
+            log_lines_live: 0,
+            line_errors: 0,
+            pid: 42,
+            pgid: 42,
+            state,
+            cpu_pct: None,
+            mem_pct: None,
+            pdepth: 0,
+            pdescendants: HashSet::new(),
+        };
+        let status = Status::UpOn {
+            feeds: vec![
+                Feed {
+                    position: 1,
+                    info: Some(info(Some(ps::State::SleepInterruptible))),
+                },
+                Feed {
+                    position: 2,
+                    info: Some(info(Some(ps::State::Zombie))),
+                },
+                Feed {
+                    position: 3,
+                    info: Some(info(None)),
+                },
+                Feed {
+                    position: 4,
+                    info: None,
+                },
+            ],
+            connections: 1,
+            title: None,
+            output_error_count: 0,
+            last_output_error: None,
+            timer_panics: 0,
+            stopping: None,
+        };
+        let plain = status.to_str(super::Audience::Human);
+        assert!(!plain.contains('\u{1b}'));
+        let colored = status.to_str_colored(super::Audience::Human, true);
+        let lines: Vec<&str> = colored.lines().collect();
+        let column = lines[0].find("PROC_STATE").unwrap();
+        let expected = [
+            Some("\u{1b}[38;5;10mS\u{1b}[39m"), // Green
+            Some("\u{1b}[38;5;9mZ\u{1b}[39m"),  // Red
+            None,                               // Unknown state
+            Some("\u{1b}[38;5;8m-\u{1b}[39m"),  // Gray, not running
+        ];
+        for (line, expected) in lines[1..].iter().zip(expected) {
+            match expected {
+                Some(expected) => {
+                    assert_eq!(Some(column), line.find(expected), "{}", line);
+                    assert_eq!(1, line.matches('\u{1b}').count() / 2);
+                }
+                None => assert!(!line.contains('\u{1b}'), "{}", line),
+            }
+        }
+        // Same layout, apart from the escape codes.
+        let stripped = regex::Regex::new("\u{1b}\\[[0-9;]*m")
+            .unwrap()
+            .replace_all(&colored, "");
+        assert_eq!(plain, stripped);
+    }
+
+    #[tokio::test]
+    async fn server_info() {
+        let dir = crate::tmp_path("server_info");
+        tokio::fs::create_dir_all(&dir).await.unwrap();
+        Conf::load_or_init(&dir).await.unwrap();
+
+        ServerInfo::write_started(&dir, &dir.join("feeds"))
+            .await
+            .unwrap();
+        let started = ServerInfo::read(&dir).await.unwrap();
+        assert!(humantime::parse_rfc3339(&started.started_at).is_ok());
+        assert!(started.stopped_at.is_none());
+        assert_eq!(std::process::id(), started.pid);
+        assert_eq!(64, started.conf_hash.len());
+        assert_eq!(Some(dir.join("feeds")), started.feeds_dir);
+        // Running, or crashed.
+        assert!(matches!(
+            status_from_server_info(&dir).await,
+            Status::Down {
+                since: None,
+                last_error: None
+            }
+        ));
+
+        ServerInfo::write_stopped(&dir, Some("Premature exit".to_string()))
+            .await
+            .unwrap();
+        let stopped = ServerInfo::read(&dir).await.unwrap();
+        assert_eq!(started.started_at, stopped.started_at);
+        assert!(humantime::parse_rfc3339(
+            stopped.stopped_at.as_ref().unwrap()
+        )
+        .is_ok());
+        assert_eq!(started.conf_hash, stopped.conf_hash);
+        assert!(matches!(
+            status_from_server_info(&dir).await,
+            Status::Down {
+                since: Some(_),
+                last_error: Some(e)
+            } if e == "Premature exit"
+        ));
+
+        // Started again, which forgets the last stop.
+        ServerInfo::write_started(&dir, &dir.join("feeds"))
+            .await
+            .unwrap();
+        assert!(ServerInfo::read(&dir).await.unwrap().last_error.is_none());
+
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
     }
 }
@@ -0,0 +1,127 @@
+use std::time::Duration;
+
+use tokio::time::Instant;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum State {
+    /// Working normally.
+    Closed,
+    /// Failing. Attempts are skipped until the reset timeout.
+    Open,
+    /// Reset timeout passed. The next attempt decides between closed and
+    /// open again.
+    HalfOpen,
+}
+
+/// Stops attempts at something which keeps failing, and tries again only
+/// once in a while.
+#[derive(Debug)]
+pub struct CircuitBreaker {
+    state: State,
+    /// Consecutive.
+    failure_count: u32,
+    last_failure: Instant,
+    open_threshold: u32,
+    reset_timeout: Duration,
+}
+
+impl CircuitBreaker {
+    pub fn new(open_threshold: u32, reset_timeout: Duration) -> Self {
+        Self {
+            state: State::Closed,
+            failure_count: 0,
+            last_failure: Instant::now(),
+            open_threshold,
+            reset_timeout,
+        }
+    }
+
+    pub fn state(&self) -> State {
+        self.state
+    }
+
+    /// Whether to attempt now. An open breaker half-opens once the reset
+    /// timeout has passed since the last failure.
+    pub fn allow(&mut self) -> bool {
+        match self.state {
+            State::Closed | State::HalfOpen => true,
+            State::Open => {
+                if self.last_failure.elapsed() >= self.reset_timeout {
+                    self.state = State::HalfOpen;
+                    true
+                } else {
+                    false
+                }
+            }
+        }
+    }
+
+    pub fn record_success(&mut self) {
+        self.state = State::Closed;
+        self.failure_count = 0;
+    }
+
+    pub fn record_failure(&mut self) {
+        self.failure_count = self.failure_count.saturating_add(1);
+        self.last_failure = Instant::now();
+        if self.state == State::HalfOpen
+            || self.failure_count >= self.open_threshold
+        {
+            self.state = State::Open;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{CircuitBreaker, State};
+
+    #[tokio::test(start_paused = true)]
+    async fn opens_after_threshold() {
+        let mut breaker = CircuitBreaker::new(3, Duration::from_secs(10));
+        for _ in 0..2 {
+            assert!(breaker.allow());
+            breaker.record_failure();
+            assert_eq!(State::Closed, breaker.state());
+        }
+
+        // Success resets the count.
+        breaker.record_success();
+        for _ in 0..2 {
+            breaker.record_failure();
+        }
+        assert_eq!(State::Closed, breaker.state());
+
+        breaker.record_failure();
+        assert_eq!(State::Open, breaker.state());
+        assert!(!breaker.allow());
+        tokio::time::advance(Duration::from_secs(9)).await;
+        assert!(!breaker.allow());
+        assert_eq!(State::Open, breaker.state());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn half_open_probe() {
+        let mut breaker = CircuitBreaker::new(1, Duration::from_secs(10));
+        breaker.record_failure();
+        assert_eq!(State::Open, breaker.state());
+
+        // Failed probe re-opens right away, for another full timeout.
+        tokio::time::advance(Duration::from_secs(10)).await;
+        assert!(breaker.allow());
+        assert_eq!(State::HalfOpen, breaker.state());
+        breaker.record_failure();
+        assert_eq!(State::Open, breaker.state());
+        tokio::time::advance(Duration::from_secs(5)).await;
+        assert!(!breaker.allow());
+
+        // Successful probe closes.
+        tokio::time::advance(Duration::from_secs(5)).await;
+        assert!(breaker.allow());
+        breaker.record_success();
+        assert_eq!(State::Closed, breaker.state());
+        assert!(breaker.allow());
+    }
+}
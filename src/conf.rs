@@ -1,16 +1,107 @@
-use std::path::{Path, PathBuf};
+use std::{
+    collections::HashSet,
+    ffi::OsString,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    process::Stdio,
+    time::{Duration, SystemTime},
+};
 
-use anyhow::Context;
-use tokio::fs;
+use anyhow::{bail, Context};
+use futures::future::BoxFuture;
+use regex::Regex;
+use tokio::{
+    fs,
+    io::{AsyncBufReadExt, BufReader},
+    process,
+    sync::mpsc::{self, error::TrySendError},
+};
+use unicode_width::UnicodeWidthChar;
 
 const DIR_NAME_FEEDS: &str = "feeds";
 const FILE_NAME_FEED_LOG: &str = "log";
+const FILE_NAME_FEED_LOG_ROTATED: &str = "log.1";
 const FILE_NAME_FEED_PID: &str = "pid";
+const FILE_NAME_FEED_SCRIPT: &str = "script.sh";
+const FILE_NAME_FEED_START_INFO: &str = "start_info.json";
 const FILE_NAME_SERVER_PID: &str = "pid";
 const FILE_NAME_SERVER_SOCK: &str = "socket";
 const FILE_NAME_CONF: &str = "conf.toml";
+const FILE_NAME_BAR_SNAPSHOT: &str = "bar_snapshot.json";
+const FILE_NAME_SERVER_INFO: &str = "server_info.json";
+
+const ENV_OVERRIDE_PREFIX: &str = "BARISTA_CONF_";
+/// Working directory, in place of the default, when --dir is not given.
+pub const BARISTA_DIR_ENV_VAR: &str = "BARISTA_DIR";
 
 const DEFAULT_DST: Dst = Dst::X11RootWindowName;
+const DEFAULT_MAX_STDOUT_BYTES_PER_LINE: usize = 4096;
+const DEFAULT_MAX_STDERR_BYTES_PER_LINE: usize = 64 * 1024;
+const DEFAULT_SOCKET_BACKLOG: u32 = 1024;
+pub const DEFAULT_MAX_RPC_MESSAGE_BYTES: usize = 1024 * 1024;
+const DEFAULT_STOP_TIMEOUT_SECS: f64 = 5.0;
+const DEFAULT_MAX_RESTARTS: u32 = 5;
+const DEFAULT_RESTART_DELAY_SECS: f64 = 1.0;
+const MAX_RESTART_DELAY_SECS: f64 = 60.0;
+const DEFAULT_MAX_LINE_ERRORS: u32 = 10;
+const DEFAULT_MIN_OUTPUT_INTERVAL_MS: u64 = 10;
+const DEFAULT_BATCH_SIZE: usize = 16;
+const DEFAULT_HOOK_TIMEOUT_SECS: f64 = 30.0;
+
+const MAX_FEED_NAME_LEN: usize = 64;
+/// "NN-<name>"
+const MAX_FEED_DIR_NAME_LEN: usize = 2 + 1 + MAX_FEED_NAME_LEN;
+
+#[derive(Debug, thiserror::Error)]
+#[error("Config error at {}:{line}:{column}: {message}", file.display())]
+pub struct TomlParseError {
+    pub file: PathBuf,
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+/// Feed names become parts of paths, so must be safe as such.
+#[derive(Debug, thiserror::Error, PartialEq, Eq)]
+pub enum ValidationError {
+    #[error("Feed name contains a path separator: {0:?}")]
+    PathSeparator(String),
+
+    #[error("Feed name contains a parent directory reference: {0:?}")]
+    ParentDir(String),
+
+    #[error("Feed name contains a null byte: {0:?}")]
+    NullByte(String),
+
+    #[error("Feed name is longer than {max} bytes: {name:?}")]
+    NameTooLong { name: String, max: usize },
+
+    #[error("Feed directory name is longer than {max} bytes: {name:?}")]
+    DirNameTooLong { name: String, max: usize },
+}
+
+impl TomlParseError {
+    fn new(file: &Path, data: &str, error: &toml::de::Error) -> Self {
+        // Lines and columns are 1-based, like in editors.
+        let (line, column) = error.span().map_or((0, 0), |span| {
+            let before = &data[..span.start.min(data.len())];
+            let line = before.matches('\n').count() + 1;
+            let column = before
+                .rfind('\n')
+                .map_or(before, |i| &before[i + 1..])
+                .chars()
+                .count()
+                + 1;
+            (line, column)
+        });
+        Self {
+            file: file.to_path_buf(),
+            line,
+            column,
+            message: error.message().trim_end().to_string(),
+        }
+    }
+}
 
 #[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
 pub struct Conf {
@@ -21,6 +112,122 @@ pub struct Conf {
     pub pad_right: String,
     pub expiry_character: char,
     pub output_interval: f64,
+
+    /// Fills the slot of a feed which was cleared. Defaults to a space.
+    pub clear_character: Option<char>,
+
+    /// Server socket backlog. Overridden by the --backlog CLI argument.
+    pub socket_backlog: Option<u32>,
+
+    /// Largest control RPC frame accepted, either way. Connections sending
+    /// bigger ones are dropped.
+    pub max_rpc_message_bytes: Option<usize>,
+
+    /// Also accept control connections over TCP on this address, in
+    /// addition to the Unix socket. There is no authentication, so
+    /// anyone who can reach the address can control the bar.
+    pub control_tcp_bind: Option<SocketAddr>,
+
+    /// Bind the control socket in the Linux abstract namespace, instead of
+    /// as a file in the server directory. Nothing is left behind in the
+    /// filesystem, even after a crash.
+    pub abstract_socket: Option<bool>,
+
+    /// Where the feed directories, with their logs, are kept, e.g. on a
+    /// tmpfs to spare the disk. Absolute, relative to the server directory,
+    /// or starting with ~. Overridden by the --feeds-dir CLI argument.
+    pub feeds_dir: Option<PathBuf>,
+
+    /// Values for optional feed fields which were not set per-feed.
+    pub defaults: Option<FeedDefaults>,
+
+    /// Post a desktop notification (via notify-send) when a feed exits
+    /// without being asked to.
+    pub notify_on_unexpected_exit: Option<bool>,
+
+    /// Soft limit of open file descriptors (RLIMIT_NOFILE) which the
+    /// server sets on itself at startup.
+    pub max_open_files: Option<u32>,
+
+    /// Other config files, whose feeds are appended after the feeds of
+    /// this one. All other fields of included files are ignored. Relative
+    /// paths are relative to the directory of the including file.
+    pub includes: Option<Vec<PathBuf>>,
+
+    /// Blank-out all slots when feeds are (re)started, instead of keeping
+    /// the previous contents until each feed outputs something new.
+    pub clear_on_start: Option<bool>,
+
+    /// Save bar contents when turning off and show them right away on the
+    /// next server start, rather than a blank bar while feeds warm up.
+    pub persist_state: Option<bool>,
+
+    /// Write the bar right after a reload turns the feeds back on, rather
+    /// than at the next output interval.
+    pub output_on_reload: Option<bool>,
+
+    /// How long clients wait for a reload, which can take much longer than
+    /// other requests. Overridden by the --reload-timeout CLI argument.
+    pub reload_timeout_secs: Option<f64>,
+
+    /// Show what was in the bar right before a reload, until the feeds
+    /// output something new, rather than what their on_stop left behind.
+    pub preserve_on_reload: Option<bool>,
+
+    /// Count the exact number of lines in each feed's log for status, by
+    /// reading the whole log. Otherwise only the approximation is shown.
+    pub status_include_log_lines: Option<bool>,
+
+    /// TZ environment variable for all feed processes, e.g. "UTC" or
+    /// "Europe/Paris". System timezone if not set.
+    pub timezone: Option<String>,
+
+    /// Shown in status, to tell apart multiple bar instances.
+    pub bar_title: Option<String>,
+
+    /// Output at half the input interval of the most active feed, instead
+    /// of at output_interval, within the min and max bounds below.
+    pub adaptive_output_interval: Option<bool>,
+    /// Defaults to 10.
+    pub min_output_interval_ms: Option<u64>,
+    /// Defaults to output_interval.
+    pub max_output_interval_ms: Option<u64>,
+
+    /// Longest bar to output, in terminal columns. Longer ones are cut
+    /// after the last whole slot which fits along with overflow_suffix.
+    pub max_bar_length: Option<usize>,
+
+    /// Appended to a bar cut to max_bar_length. Defaults to "...".
+    pub overflow_suffix: Option<String>,
+
+    /// Most messages, such as feed outputs, which the bar server takes in
+    /// at once, before writing the bar. Only read at server start.
+    pub batch_size: Option<usize>,
+
+    /// Command, run in the server directory, whose every line of output
+    /// triggers a reload, e.g. `inotifywait -m -e close_write conf.toml`.
+    /// A bar which is off stays off, with the config kept for the next on.
+    /// Only read at server start.
+    pub watch_command: Option<String>,
+
+    /// Command run once at server start, before any feeds, e.g. to set up
+    /// something they all need. Failure is logged, but not fatal.
+    pub on_startup_cmd: Option<String>,
+
+    /// Longest on_startup_cmd may run before it is killed. Also bounds
+    /// on_shutdown_cmd, so it can't hold up the exit. Defaults to 30.
+    pub on_startup_timeout_secs: Option<f64>,
+
+    /// Command run once at server shutdown, after the feeds are stopped.
+    pub on_shutdown_cmd: Option<String>,
+}
+
+/// The part of an included config file that is actually used.
+#[derive(Debug, serde::Deserialize)]
+struct Included {
+    #[serde(default)]
+    feeds: Vec<Feed>,
+    includes: Option<Vec<PathBuf>>,
 }
 
 #[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
@@ -31,34 +238,588 @@ pub enum Dst {
     X11RootWindowName,
 }
 
-#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
 pub struct Feed {
     pub name: String,
-    pub cmd: String,
+    #[serde(flatten)]
+    pub cmd: FeedCmd,
+
+    pub ttl: Option<f64>,
+    pub shell: Option<PathBuf>,
+
+    /// Longer lines are truncated, with the rest of the line discarded.
+    pub max_stdout_bytes_per_line: Option<usize>,
+
+    /// Same as max_stdout_bytes_per_line, but for the lines of the log.
+    pub max_stderr_bytes_per_line: Option<usize>,
+
+    /// File to connect to the feed process's stdin. Relative paths are
+    /// relative to the feed's directory.
+    pub stdin_file: Option<PathBuf>,
+
+    /// Blank-out the feed's slot after it stops.
+    pub clear_on_stop: Option<bool>,
+
+    /// Fill the feed's slot with the expiry character after it stops.
+    /// This is what happens when neither this nor clear_on_stop is set.
+    /// Set to false, without clear_on_stop, the slot is kept as it was.
+    pub expire_on_stop: Option<bool>,
+
+    /// Start the feed again after it exits without being asked to.
+    pub auto_restart: Option<bool>,
+
+    /// How many times to auto-restart the feed before giving up. Counted
+    /// from when the bar was turned on or the feed's config last changed.
+    pub max_restarts: Option<u32>,
+
+    /// How long to wait before the first auto-restart. Doubled with each
+    /// restart, up to a minute.
+    pub restart_delay_secs: Option<f64>,
+
+    /// How long to wait for the feed's process and output to be done with
+    /// after it stops, before giving up on them.
+    pub stop_timeout_secs: Option<f64>,
+
+    /// How to decode the feed's output. Utf8Lossy if not set.
+    pub encoding: Option<FeedEncoding>,
+
+    /// Unreadable output lines to skip before giving up on the feed's
+    /// output altogether.
+    pub max_line_errors: Option<u32>,
+
+    /// Lines in the log, after which it is moved to log.1, replacing the
+    /// previous one, and started over. Unlimited if not set.
+    pub max_log_lines: Option<usize>,
+
+    /// Feeds with lower values are shown earlier in the bar. Feeds without
+    /// a priority come after those with one. Ties keep the config order.
+    pub priority: Option<u32>,
+
+    /// Regex of a line ending a block of output lines, which are then
+    /// joined into a single update of the feed's slot. The sentinel line
+    /// itself is not shown. Each line is an update if not set.
+    pub output_sentinel: Option<String>,
+
+    /// What to join a block of output lines with. Newline if not set.
+    pub output_joiner: Option<String>,
+
+    /// Put before every update of the feed's slot, such as an icon.
+    pub output_prefix: Option<String>,
+
+    /// Put after every update of the feed's slot.
+    pub output_suffix: Option<String>,
+
+    /// Run the feed in its own transient systemd scope, with systemd-run,
+    /// and stop it with systemctl. Falls back to running it directly if
+    /// systemd is not available. Cannot be combined with no_new_privs or
+    /// seccomp_filter, which would only apply to systemd-run.
+    pub use_systemd_run: Option<bool>,
+
+    /// Prevent the feed's process and its descendants from gaining
+    /// privileges, such as via setuid binaries.
+    pub no_new_privs: Option<bool>,
+
+    /// File with a compiled seccomp BPF program, as a raw array of
+    /// `struct sock_filter`, such as exported by libseccomp's
+    /// `seccomp_export_bpf`, to load into the feed's process. Relative paths
+    /// are relative to the feed's directory. Unless the server has
+    /// CAP_SYS_ADMIN, loading requires no_new_privs.
+    pub seccomp_filter: Option<PathBuf>,
+
+    /// Most verbose level of the feed's own log events, such as of its
+    /// output lines at debug. Can only quiet a feed below the server's
+    /// level, not make it louder.
+    #[serde(default, with = "level")]
+    pub log_level: Option<tracing::Level>,
 
+    /// Position in the config, which, unlike the position in the bar,
+    /// does not change with priorities, so it is used to name the feed's
+    /// directory.
+    #[serde(skip)]
+    pub config_pos: usize,
+}
+
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize,
+)]
+pub enum FeedEncoding {
+    /// Skip invalid UTF-8 lines, as errors, see max_line_errors.
+    Utf8Strict,
+    /// Replace invalid UTF-8 sequences with U+FFFD.
+    Utf8Lossy,
+    Latin1,
+}
+
+/// Either `cmd = "..."` or `script = """..."""` in a feed's config.
+#[derive(
+    Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize,
+)]
+pub enum FeedCmd {
+    /// Passed to the shell as the argument of -c.
+    #[serde(rename = "cmd")]
+    Cmd(String),
+
+    /// Written to a file in the feed's directory, which is then passed to
+    /// the shell as the script to run. Spares escaping in longer scripts.
+    #[serde(rename = "script")]
+    Script(String),
+}
+
+/// Same as the optional fields of Feed.
+#[derive(Clone, Debug, Default, serde::Serialize, serde::Deserialize)]
+pub struct FeedDefaults {
     pub ttl: Option<f64>,
     pub shell: Option<PathBuf>,
+    pub max_stdout_bytes_per_line: Option<usize>,
+    pub max_stderr_bytes_per_line: Option<usize>,
+    pub stdin_file: Option<PathBuf>,
+    pub clear_on_stop: Option<bool>,
+    pub expire_on_stop: Option<bool>,
+    pub auto_restart: Option<bool>,
+    pub max_restarts: Option<u32>,
+    pub restart_delay_secs: Option<f64>,
+    pub stop_timeout_secs: Option<f64>,
+    pub encoding: Option<FeedEncoding>,
+    pub max_line_errors: Option<u32>,
+    pub max_log_lines: Option<usize>,
+    pub no_new_privs: Option<bool>,
+    pub seccomp_filter: Option<PathBuf>,
+}
+
+/// What to do with the feed's slot after the feed stops.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OnStop {
+    Clear,
+    Expire,
+    Keep,
+}
+
+/// How the feeds of two configs differ. Feeds are matched by name.
+/// Positions are in the bar of the new config, except the removed ones,
+/// which are in the old. Since a running feed is bound to its position,
+/// one which moved counts as changed.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct ConfDiff {
+    pub added: Vec<usize>,
+    pub removed: Vec<usize>,
+    pub changed: Vec<usize>,
+    pub unchanged: Vec<usize>,
+}
+
+impl Feed {
+    /// Whether a running feed could be kept as is, if its config was
+    /// replaced by other. Priority is not compared, since it only decides
+    /// the position, which is compared separately.
+    pub fn is_equivalent(&self, other: &Feed) -> bool {
+        self == &Feed {
+            priority: self.priority,
+            ..other.clone()
+        }
+    }
+
+    /// Fill-in the unset fields from defaults. Explicitly set fields are
+    /// left as they are.
+    pub fn apply_defaults(&self, defaults: &FeedDefaults) -> Self {
+        let FeedDefaults {
+            ttl,
+            shell,
+            max_stdout_bytes_per_line,
+            max_stderr_bytes_per_line,
+            stdin_file,
+            clear_on_stop,
+            expire_on_stop,
+            auto_restart,
+            max_restarts,
+            restart_delay_secs,
+            stop_timeout_secs,
+            encoding,
+            max_line_errors,
+            max_log_lines,
+            no_new_privs,
+            seccomp_filter,
+        } = defaults;
+        Self {
+            name: self.name.clone(),
+            cmd: self.cmd.clone(),
+            ttl: self.ttl.or(*ttl),
+            shell: self.shell.clone().or_else(|| shell.clone()),
+            max_stdout_bytes_per_line: self
+                .max_stdout_bytes_per_line
+                .or(*max_stdout_bytes_per_line),
+            max_stderr_bytes_per_line: self
+                .max_stderr_bytes_per_line
+                .or(*max_stderr_bytes_per_line),
+            stdin_file: self
+                .stdin_file
+                .clone()
+                .or_else(|| stdin_file.clone()),
+            clear_on_stop: self.clear_on_stop.or(*clear_on_stop),
+            expire_on_stop: self.expire_on_stop.or(*expire_on_stop),
+            auto_restart: self.auto_restart.or(*auto_restart),
+            max_restarts: self.max_restarts.or(*max_restarts),
+            restart_delay_secs: self
+                .restart_delay_secs
+                .or(*restart_delay_secs),
+            stop_timeout_secs: self.stop_timeout_secs.or(*stop_timeout_secs),
+            encoding: self.encoding.or(*encoding),
+            max_line_errors: self.max_line_errors.or(*max_line_errors),
+            max_log_lines: self.max_log_lines.or(*max_log_lines),
+            priority: self.priority,
+            output_sentinel: self.output_sentinel.clone(),
+            output_joiner: self.output_joiner.clone(),
+            output_prefix: self.output_prefix.clone(),
+            output_suffix: self.output_suffix.clone(),
+            use_systemd_run: self.use_systemd_run,
+            no_new_privs: self.no_new_privs.or(*no_new_privs),
+            seccomp_filter: self
+                .seccomp_filter
+                .clone()
+                .or_else(|| seccomp_filter.clone()),
+            log_level: self.log_level,
+            config_pos: self.config_pos,
+        }
+    }
+
+    pub fn get_on_stop(&self) -> OnStop {
+        match (self.clear_on_stop, self.expire_on_stop) {
+            (_, Some(true)) => OnStop::Expire,
+            (Some(true), _) => OnStop::Clear,
+            (Some(false), _) | (None, Some(false)) => OnStop::Keep,
+            (None, None) => OnStop::Expire,
+        }
+    }
+
+    pub fn validate_name(name: &str) -> Result<(), ValidationError> {
+        if name.contains(['/', '\\']) {
+            return Err(ValidationError::PathSeparator(name.to_string()));
+        }
+        if name.contains("..") {
+            return Err(ValidationError::ParentDir(name.to_string()));
+        }
+        if name.contains('\0') {
+            return Err(ValidationError::NullByte(name.to_string()));
+        }
+        if name.len() > MAX_FEED_NAME_LEN {
+            return Err(ValidationError::NameTooLong {
+                name: name.to_string(),
+                max: MAX_FEED_NAME_LEN,
+            });
+        }
+        Ok(())
+    }
+
+    pub fn validate(&self) -> anyhow::Result<()> {
+        Self::validate_name(&self.name)?;
+        let dir = path_feed_dir(Path::new(""), self.config_pos, &self.name);
+        let dir_name = dir.file_name().unwrap_or_default();
+        if dir_name.len() > MAX_FEED_DIR_NAME_LEN {
+            bail!(ValidationError::DirNameTooLong {
+                name: dir_name.to_string_lossy().to_string(),
+                max: MAX_FEED_DIR_NAME_LEN,
+            });
+        }
+        if let (Some(true), Some(true)) =
+            (self.clear_on_stop, self.expire_on_stop)
+        {
+            bail!(
+                "Feed {:?}: clear_on_stop and expire_on_stop \
+                are mutually exclusive",
+                self.name
+            );
+        }
+        if let Some(secs) = self.stop_timeout_secs {
+            if !(secs.is_finite() && secs > 0.0) {
+                bail!(
+                    "Feed {:?}: stop_timeout_secs must be a positive number",
+                    self.name
+                );
+            }
+        }
+        // A scope does not exec the command itself, so the unit's own
+        // sandboxing properties do not apply to it, while ours would only
+        // apply to systemd-run.
+        if self.get_use_systemd_run()
+            && (self.no_new_privs == Some(true)
+                || self.seccomp_filter.is_some())
+        {
+            bail!(
+                "Feed {:?}: use_systemd_run cannot be combined with \
+                no_new_privs or seccomp_filter",
+                self.name
+            );
+        }
+        if let Some(secs) = self.restart_delay_secs {
+            if !(secs.is_finite() && secs > 0.0) {
+                bail!(
+                    "Feed {:?}: restart_delay_secs must be a positive number",
+                    self.name
+                );
+            }
+        }
+        if self.max_log_lines == Some(0) {
+            bail!(
+                "Feed {:?}: max_log_lines must be greater than 0",
+                self.name
+            );
+        }
+        self.get_output_sentinel()?;
+        self.validate_cmd()?;
+        Ok(())
+    }
+
+    /// Only catches the obvious case of the command starting with an
+    /// absolute path to nothing. Anything else may be a builtin, keyword
+    /// or function of the shell, or be found on whatever PATH the command
+    /// sets up for itself, so is left to fail when the feed starts.
+    pub fn validate_cmd(&self) -> anyhow::Result<()> {
+        const SHELL_METACHARACTERS: &[char] =
+            &[';', '&', '|', '<', '>', '(', ')', '$', '`', '"', '\'', '\\'];
+
+        let FeedCmd::Cmd(cmd) = &self.cmd else {
+            return Ok(());
+        };
+        let bin = cmd
+            .trim_start()
+            .split(|c: char| {
+                c.is_whitespace() || SHELL_METACHARACTERS.contains(&c)
+            })
+            .next()
+            .unwrap_or_default();
+        if bin.starts_with('/') && !Path::new(bin).exists() {
+            bail!(
+                "Feed {:?}: Binary not found: {}, PATH={}",
+                self.name,
+                bin,
+                std::env::var("PATH").unwrap_or_default()
+            );
+        }
+        Ok(())
+    }
+
+    pub fn get_stop_timeout(&self) -> Duration {
+        Duration::from_secs_f64(
+            self.stop_timeout_secs.unwrap_or(DEFAULT_STOP_TIMEOUT_SECS),
+        )
+    }
+
+    pub fn get_max_restarts(&self) -> u32 {
+        self.max_restarts.unwrap_or(DEFAULT_MAX_RESTARTS)
+    }
+
+    /// Delay before the given restart, counting from 0.
+    pub fn get_restart_delay(&self, restarts: u32) -> Duration {
+        let base = self
+            .restart_delay_secs
+            .unwrap_or(DEFAULT_RESTART_DELAY_SECS);
+        let secs = base * 2f64.powi(restarts.min(31) as i32);
+        Duration::from_secs_f64(secs.min(MAX_RESTART_DELAY_SECS.max(base)))
+    }
+
+    pub fn get_encoding(&self) -> FeedEncoding {
+        self.encoding.unwrap_or(FeedEncoding::Utf8Lossy)
+    }
+
+    pub fn get_max_line_errors(&self) -> u32 {
+        self.max_line_errors.unwrap_or(DEFAULT_MAX_LINE_ERRORS)
+    }
+
+    pub fn get_output_sentinel(&self) -> anyhow::Result<Option<Regex>> {
+        self.output_sentinel
+            .as_deref()
+            .map(Regex::new)
+            .transpose()
+            .context(format!("Feed {:?}: invalid output_sentinel", self.name))
+    }
+
+    pub fn get_use_systemd_run(&self) -> bool {
+        self.use_systemd_run.unwrap_or(false)
+    }
+
+    pub fn get_output_joiner(&self) -> &str {
+        self.output_joiner.as_deref().unwrap_or("\n")
+    }
+
+    pub fn get_output_prefix(&self) -> &str {
+        self.output_prefix.as_deref().unwrap_or("")
+    }
+
+    pub fn get_output_suffix(&self) -> &str {
+        self.output_suffix.as_deref().unwrap_or("")
+    }
+
+    pub fn get_max_stdout_bytes_per_line(&self) -> usize {
+        self.max_stdout_bytes_per_line
+            .unwrap_or(DEFAULT_MAX_STDOUT_BYTES_PER_LINE)
+    }
+
+    pub fn get_max_stderr_bytes_per_line(&self) -> usize {
+        self.max_stderr_bytes_per_line
+            .unwrap_or(DEFAULT_MAX_STDERR_BYTES_PER_LINE)
+    }
 }
 
 pub fn default_shell() -> PathBuf {
     PathBuf::from("/bin/bash")
 }
 
+const DEFAULT_TOML: &str = r#"# Where to output the bar. One of:
+#   "StdOut"
+#   "StdErr"
+#   { File = { path = "/path/to/file" } }
+#   "X11RootWindowName"
+dst = "X11RootWindowName"
+
+# Separator between feed outputs.
+sep = "   "
+
+# Padding before the first and after the last feed output.
+pad_left = " "
+pad_right = " "
+
+# Fills the slot of a feed whose output has expired (or has stopped).
+expiry_character = "_"
+
+# Seconds between outputs of the bar, when something has changed.
+output_interval = 1.0
+
+# Optional settings, shown with example values:
+#
+# clear_character = " "
+# socket_backlog = 1024
+# max_rpc_message_bytes = 1048576
+# control_tcp_bind = "127.0.0.1:7777"  # No authentication!
+# abstract_socket = false
+# feeds_dir = "/run/user/1000/barista-feeds"
+# notify_on_unexpected_exit = false
+# max_open_files = 4096
+# includes = ["~/.config/barista/common.toml"]
+# clear_on_start = false
+# persist_state = false
+# output_on_reload = false
+# reload_timeout_secs = 30.0
+# preserve_on_reload = false
+# status_include_log_lines = false
+# timezone = "UTC"
+# bar_title = "laptop"
+# adaptive_output_interval = false
+# min_output_interval_ms = 10
+# max_output_interval_ms = 1000
+# max_bar_length = 200
+# overflow_suffix = "..."
+# batch_size = 16
+# watch_command = "inotifywait -m -e close_write conf.toml"
+# on_startup_cmd = "ssh-add -l"
+# on_startup_timeout_secs = 30.0
+# on_shutdown_cmd = "notify-send barista stopped"
+#
+# Values for optional feed fields not set per-feed:
+# [defaults]
+# ttl = 5.0
+
+# Feeds are shown in the order they're listed, unless given a priority.
+# Each feed runs either a `cmd` (passed to the shell with -c) or a
+# `script` (written to a file).
+# Every line the feed prints to stdout replaces its slot in the bar.
+# Optional feed fields, shown with example values:
+#
+# ttl = 5.0                          # Seconds until output expires.
+# shell = "/bin/bash"
+# max_stdout_bytes_per_line = 4096
+# max_stderr_bytes_per_line = 65536
+# stdin_file = "input.txt"           # Relative to the feed's directory.
+# clear_on_stop = false
+# expire_on_stop = true
+# auto_restart = false
+# max_restarts = 5                   # Before giving up on the feed.
+# restart_delay_secs = 1.0           # Doubled with each restart.
+# stop_timeout_secs = 5.0
+# encoding = "Utf8Lossy"             # Or "Utf8Strict" or "Latin1".
+# max_line_errors = 10
+# max_log_lines = 10000
+# priority = 1                       # Lower is shown earlier.
+# output_sentinel = "^--$"           # Ends a block of lines shown as one.
+# output_joiner = " | "              # Between lines of a block.
+# output_prefix = "BAT "
+# output_suffix = "%"
+# use_systemd_run = false
+# no_new_privs = true
+# seccomp_filter = "filter.bpf"      # Relative to the feed's directory.
+# log_level = "warn"
+
+[[feeds]]
+name = "uptime"
+cmd = "while :; do uptime; sleep 1; done"
+ttl = 1.0
+
+[[feeds]]
+name = "time"
+cmd = "while :; do date; sleep 1; done"
+ttl = 1.0
+"#;
+
 impl Default for Conf {
     fn default() -> Self {
         Self {
             feeds: vec![
                 Feed {
                     name: "uptime".to_string(),
-                    cmd: "while :; do uptime; sleep 1; done".to_string(),
+                    cmd: FeedCmd::Cmd(
+                        "while :; do uptime; sleep 1; done".to_string(),
+                    ),
                     ttl: Some(1.0),
                     shell: None,
+                    max_stdout_bytes_per_line: None,
+                    max_stderr_bytes_per_line: None,
+                    stdin_file: None,
+                    clear_on_stop: None,
+                    expire_on_stop: None,
+                    auto_restart: None,
+                    max_restarts: None,
+                    restart_delay_secs: None,
+                    stop_timeout_secs: None,
+                    encoding: None,
+                    max_line_errors: None,
+                    max_log_lines: None,
+                    priority: None,
+                    output_sentinel: None,
+                    output_joiner: None,
+                    output_prefix: None,
+                    output_suffix: None,
+                    use_systemd_run: None,
+                    no_new_privs: None,
+                    seccomp_filter: None,
+                    log_level: None,
+                    config_pos: 0,
                 },
                 Feed {
                     name: "time".to_string(),
-                    cmd: "while :; do date; sleep 1; done".to_string(),
+                    cmd: FeedCmd::Cmd(
+                        "while :; do date; sleep 1; done".to_string(),
+                    ),
                     ttl: Some(1.0),
                     shell: None,
+                    max_stdout_bytes_per_line: None,
+                    max_stderr_bytes_per_line: None,
+                    stdin_file: None,
+                    clear_on_stop: None,
+                    expire_on_stop: None,
+                    auto_restart: None,
+                    max_restarts: None,
+                    restart_delay_secs: None,
+                    stop_timeout_secs: None,
+                    encoding: None,
+                    max_line_errors: None,
+                    max_log_lines: None,
+                    priority: None,
+                    output_sentinel: None,
+                    output_joiner: None,
+                    output_prefix: None,
+                    output_suffix: None,
+                    use_systemd_run: None,
+                    no_new_privs: None,
+                    seccomp_filter: None,
+                    log_level: None,
+                    config_pos: 1,
                 },
             ],
             dst: Some(DEFAULT_DST),
@@ -67,17 +828,208 @@ impl Default for Conf {
             pad_right: " ".to_string(),
             expiry_character: '_',
             output_interval: 1.0,
+            clear_character: None,
+            socket_backlog: None,
+            max_rpc_message_bytes: None,
+            control_tcp_bind: None,
+            abstract_socket: None,
+            feeds_dir: None,
+            defaults: None,
+            notify_on_unexpected_exit: None,
+            max_open_files: None,
+            includes: None,
+            clear_on_start: None,
+            persist_state: None,
+            output_on_reload: None,
+            reload_timeout_secs: None,
+            preserve_on_reload: None,
+            status_include_log_lines: None,
+            timezone: None,
+            bar_title: None,
+            adaptive_output_interval: None,
+            min_output_interval_ms: None,
+            max_output_interval_ms: None,
+            max_bar_length: None,
+            overflow_suffix: None,
+            batch_size: None,
+            watch_command: None,
+            on_startup_cmd: None,
+            on_startup_timeout_secs: None,
+            on_shutdown_cmd: None,
         }
     }
 }
 
 impl Conf {
     pub async fn from_file(file: &Path) -> anyhow::Result<Self> {
+        Self::from_file_with_vars(file, std::env::vars().collect()).await
+    }
+
+    /// Same as from_file, but with the overrides taken from vars, rather
+    /// than from the environment.
+    async fn from_file_with_vars(
+        file: &Path,
+        vars: Vec<(String, String)>,
+    ) -> anyhow::Result<Self> {
         let data: String = fs::read_to_string(file)
             .await
             .context(format!("Failed to read file: {:?}", file))?;
-        let selph: Self = toml::from_str(&data)
-            .context(format!("Failed to parse TOML from: {:?}", file))?;
+        let mut selph = Self::parse(file, &data)?;
+        if let Some(includes) = &selph.includes {
+            let canonical = fs::canonicalize(file).await.context(format!(
+                "Failed to canonicalize path: {:?}",
+                file
+            ))?;
+            let mut chain = HashSet::from([canonical]);
+            let feeds =
+                load_included_feeds(file, includes, &mut chain).await?;
+            let feeds = feeds.into_iter().map(|feed| match &selph.defaults {
+                Some(defaults) => feed.apply_defaults(defaults),
+                None => feed,
+            });
+            selph.feeds.extend(feeds);
+        }
+        selph.apply_overrides(vars)?;
+        if let Some(feeds_dir) = &selph.feeds_dir {
+            selph.feeds_dir = Some(expand_tilde(feeds_dir)?);
+        }
+        for (pos, feed) in selph.feeds.iter_mut().enumerate() {
+            feed.config_pos = pos;
+        }
+        selph
+            .validate()
+            .context(format!("Invalid config in file: {:?}", file))?;
+        Ok(selph)
+    }
+
+    /// Overrides top-level scalar fields with the values of environment
+    /// variables named BARISTA_CONF_<FIELD>, e.g. BARISTA_CONF_SEP. Values
+    /// are TOML, except that strings don't need to be quoted.
+    pub fn apply_env_overrides(&mut self) -> anyhow::Result<()> {
+        self.apply_overrides(std::env::vars())
+    }
+
+    fn apply_overrides(
+        &mut self,
+        vars: impl IntoIterator<Item = (String, String)>,
+    ) -> anyhow::Result<()> {
+        for (var, raw) in vars {
+            let Some(field) = var.strip_prefix(ENV_OVERRIDE_PREFIX) else {
+                continue;
+            };
+            let field = field.to_lowercase();
+            let conf = override_field(self, &field, &raw)
+                .context(format!("Invalid config override: {}", var))?;
+            tracing::info!(var, field, "Config field overridden.");
+            *self = conf;
+        }
+        Ok(())
+    }
+
+    /// Compares the feeds of both configs, which are expected to be sorted
+    /// by priority already. A changed timezone changes all of them.
+    /// For each feed of new, the position of the same feed in old, if it
+    /// is there. Feeds are matched by name, in order.
+    pub fn match_feeds(old: &Conf, new: &Conf) -> Vec<Option<usize>> {
+        let mut matched = vec![false; old.feeds.len()];
+        new.feeds
+            .iter()
+            .map(|feed| {
+                let old_pos = (0..old.feeds.len()).find(|&i| {
+                    !matched[i] && old.feeds[i].name == feed.name
+                })?;
+                matched[old_pos] = true;
+                Some(old_pos)
+            })
+            .collect()
+    }
+
+    pub fn diff(old: &Conf, new: &Conf) -> ConfDiff {
+        let mut diff = ConfDiff::default();
+        let mut matched = vec![false; old.feeds.len()];
+        for (pos, (feed, old_pos)) in new
+            .feeds
+            .iter()
+            .zip(Self::match_feeds(old, new))
+            .enumerate()
+        {
+            match old_pos {
+                None => diff.added.push(pos),
+                Some(old_pos) => {
+                    matched[old_pos] = true;
+                    if old_pos == pos
+                        && old.timezone == new.timezone
+                        && old.feeds[old_pos].is_equivalent(feed)
+                    {
+                        diff.unchanged.push(pos);
+                    } else {
+                        diff.changed.push(pos);
+                    }
+                }
+            }
+        }
+        diff.removed =
+            (0..old.feeds.len()).filter(|&i| !matched[i]).collect();
+        diff
+    }
+
+    /// Reorders feeds into the order in which they're shown in the bar.
+    /// Idempotent, since ties are broken by the unchanging config_pos.
+    pub fn sort_feeds_by_priority(&mut self) {
+        self.feeds.sort_by_key(|feed| {
+            (feed.priority.unwrap_or(u32::MAX), feed.config_pos)
+        });
+    }
+
+    pub fn validate(&self) -> anyhow::Result<()> {
+        validate_fill_character("expiry_character", self.expiry_character)?;
+        validate_fill_character(
+            "clear_character",
+            self.get_clear_character(),
+        )?;
+        if self.socket_backlog == Some(0) {
+            bail!("socket_backlog must be greater than 0");
+        }
+        if self.max_rpc_message_bytes == Some(0) {
+            bail!("max_rpc_message_bytes must be greater than 0");
+        }
+        if self.batch_size == Some(0) {
+            bail!("batch_size must be greater than 0");
+        }
+        if self.max_open_files == Some(0) {
+            bail!("max_open_files must be greater than 0");
+        }
+        if let Some(secs) = self.reload_timeout_secs {
+            if !(secs.is_finite() && secs > 0.0) {
+                bail!("reload_timeout_secs must be a positive number");
+            }
+        }
+        if let Some(secs) = self.on_startup_timeout_secs {
+            if !(secs.is_finite() && secs > 0.0) {
+                bail!("on_startup_timeout_secs must be a positive number");
+            }
+        }
+        if let Some(timezone) = &self.timezone {
+            if timezone.parse::<chrono_tz::Tz>().is_err() {
+                bail!("Unknown timezone: {:?}", timezone);
+            }
+        }
+        for feed in &self.feeds {
+            feed.validate()?;
+        }
+        Ok(())
+    }
+
+    fn parse(file: &Path, data: &str) -> Result<Self, TomlParseError> {
+        let mut selph: Self = toml::from_str(data)
+            .map_err(|error| TomlParseError::new(file, data, &error))?;
+        if let Some(defaults) = &selph.defaults {
+            selph.feeds = selph
+                .feeds
+                .iter()
+                .map(|feed| feed.apply_defaults(defaults))
+                .collect();
+        }
         Ok(selph)
     }
 
@@ -90,45 +1042,1049 @@ impl Conf {
             Self::from_file(&file).await
         } else {
             let default = Self::default();
-            fs::write(&file, toml::to_string_pretty(&default)?).await?;
+            default.validate()?;
+            fs::write(&file, Self::default_toml_str()).await?;
             Ok(default)
         }
     }
 
+    /// Same as Conf::default, but commented, for new users to start from.
+    pub fn default_toml_str() -> &'static str {
+        DEFAULT_TOML
+    }
+
     pub fn get_dst(&self) -> Dst {
         self.dst.as_ref().unwrap_or(&DEFAULT_DST).to_owned()
     }
+
+    pub fn get_clear_character(&self) -> char {
+        self.clear_character.unwrap_or(' ')
+    }
+
+    pub fn get_socket_backlog(&self) -> u32 {
+        self.socket_backlog.unwrap_or(DEFAULT_SOCKET_BACKLOG)
+    }
+
+    pub fn get_max_rpc_message_bytes(&self) -> usize {
+        self.max_rpc_message_bytes
+            .unwrap_or(DEFAULT_MAX_RPC_MESSAGE_BYTES)
+    }
+
+    pub fn get_batch_size(&self) -> usize {
+        self.batch_size.unwrap_or(DEFAULT_BATCH_SIZE)
+    }
+
+    pub fn get_on_startup_timeout(&self) -> Duration {
+        Duration::from_secs_f64(
+            self.on_startup_timeout_secs
+                .unwrap_or(DEFAULT_HOOK_TIMEOUT_SECS),
+        )
+    }
+
+    pub fn get_output_interval(&self) -> Duration {
+        Duration::from_secs_f64(self.output_interval)
+    }
+
+    /// Bounds of the adaptive output interval, if enabled.
+    pub fn get_adaptive_output_interval(
+        &self,
+    ) -> Option<(Duration, Duration)> {
+        if !self.adaptive_output_interval.unwrap_or(false) {
+            return None;
+        }
+        let min = Duration::from_millis(
+            self.min_output_interval_ms
+                .unwrap_or(DEFAULT_MIN_OUTPUT_INTERVAL_MS),
+        );
+        let max = self.max_output_interval_ms.map_or_else(
+            || self.get_output_interval(),
+            Duration::from_millis,
+        );
+        Some((min, max.max(min)))
+    }
+
+    pub fn get_abstract_socket(&self) -> bool {
+        self.abstract_socket.unwrap_or(false)
+    }
+
+    pub fn get_overflow_suffix(&self) -> &str {
+        self.overflow_suffix.as_deref().unwrap_or("...")
+    }
+
+    pub fn get_output_on_reload(&self) -> bool {
+        self.output_on_reload.unwrap_or(false)
+    }
+
+    pub fn get_preserve_on_reload(&self) -> bool {
+        self.preserve_on_reload.unwrap_or(false)
+    }
+
+    pub fn get_feeds_dir(&self, dir: &Path) -> PathBuf {
+        path_feeds_dir(dir, self.feeds_dir.as_deref())
+    }
+}
+
+/// Written next to the feed's PID file, for finding and inspecting feed
+/// processes, including those left behind by a crashed server.
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+pub struct FeedStartInfo {
+    pub pid: u32,
+    pub pgid: u32,
+    /// The cmd, or the path of the written script.
+    pub cmd: String,
+    /// RFC 3339.
+    pub started_at: String,
+    pub shell: PathBuf,
+}
+
+impl FeedStartInfo {
+    pub fn new(pid: u32, pgid: u32, cmd: String, shell: PathBuf) -> Self {
+        Self {
+            pid,
+            pgid,
+            cmd,
+            started_at: timestamp_now(),
+            shell,
+        }
+    }
+
+    /// Written to a temporary file first, so that it's never seen half
+    /// written.
+    pub async fn write(&self, feed_dir: &Path) -> anyhow::Result<()> {
+        let file = path_feed_start_info(feed_dir);
+        let tmp = file.with_extension("json.tmp");
+        fs::write(&tmp, serde_json::to_string_pretty(self)?)
+            .await
+            .context(format!(
+                "Failed to write feed start info: {:?}",
+                &tmp
+            ))?;
+        fs::rename(&tmp, &file).await.context(format!(
+            "Failed to rename feed start info: {:?} -> {:?}",
+            &tmp, &file
+        ))?;
+        Ok(())
+    }
+
+    pub async fn read(feed_dir: &Path) -> anyhow::Result<Self> {
+        let file = path_feed_start_info(feed_dir);
+        let data = fs::read_to_string(&file).await.context(format!(
+            "Failed to read feed start info: {:?}",
+            &file
+        ))?;
+        let info = serde_json::from_str(&data)
+            .context(format!("Invalid feed start info: {:?}", &file))?;
+        Ok(info)
+    }
+}
+
+pub(crate) fn timestamp_now() -> String {
+    humantime::format_rfc3339_seconds(SystemTime::now()).to_string()
+}
+
+fn override_field(
+    conf: &Conf,
+    field: &str,
+    raw: &str,
+) -> anyhow::Result<Conf> {
+    let toml::Value::Table(table) = toml::Value::try_from(conf)? else {
+        unreachable!("Conf is not serialized as a table.");
+    };
+    if let Some(toml::Value::Table(_) | toml::Value::Array(_)) =
+        table.get(field)
+    {
+        bail!("Only scalar fields can be overridden: {}", field);
+    }
+    // Unquoted strings aren't valid TOML, so are tried as is if nothing
+    // else fits.
+    let parsed = toml::from_str::<toml::Table>(&format!("v = {}", raw))
+        .ok()
+        .and_then(|mut t| t.remove("v"))
+        .filter(|v| {
+            !matches!(v, toml::Value::Table(_) | toml::Value::Array(_))
+        });
+    let candidates = parsed
+        .into_iter()
+        .chain(std::iter::once(toml::Value::String(raw.to_string())));
+    for value in candidates {
+        let mut table = table.clone();
+        table.insert(field.to_string(), value);
+        let Ok(mut overridden) = toml::Value::Table(table).try_into::<Conf>()
+        else {
+            continue;
+        };
+        // Not otherwise kept by serde.
+        for (feed, orig) in overridden.feeds.iter_mut().zip(&conf.feeds) {
+            feed.config_pos = orig.config_pos;
+        }
+        // Unknown fields are silently ignored when deserializing.
+        match toml::Value::try_from(&overridden)? {
+            toml::Value::Table(t) if t.contains_key(field) => {
+                return Ok(overridden)
+            }
+            _ => bail!("Unknown or non-overridable field: {}", field),
+        }
+    }
+    bail!("Value {:?} doesn't fit field: {}", raw, field)
+}
+
+/// Feeds of the included files, depth-first, in the order of inclusion.
+/// chain holds the canonical paths of the files currently being included,
+/// so that a file which includes itself, directly or not, is an error.
+fn load_included_feeds<'a>(
+    parent: &'a Path,
+    includes: &'a [PathBuf],
+    chain: &'a mut HashSet<PathBuf>,
+) -> BoxFuture<'a, anyhow::Result<Vec<Feed>>> {
+    Box::pin(async move {
+        let mut feeds = Vec::new();
+        for include in includes {
+            let file = include_path(parent, include)?;
+            let canonical =
+                fs::canonicalize(&file).await.context(format!(
+                    "Failed to canonicalize included path: {:?}. From: {:?}",
+                    &file, parent
+                ))?;
+            if !chain.insert(canonical.clone()) {
+                bail!("Circular include of {:?} from {:?}", &file, parent);
+            }
+            let data: String = fs::read_to_string(&file)
+                .await
+                .context(format!("Failed to read file: {:?}", &file))?;
+            let included: Included = toml::from_str(&data)
+                .map_err(|error| TomlParseError::new(&file, &data, &error))?;
+            feeds.extend(included.feeds);
+            if let Some(includes) = &included.includes {
+                feeds.extend(
+                    load_included_feeds(&file, includes, chain).await?,
+                );
+            }
+            chain.remove(&canonical);
+        }
+        Ok(feeds)
+    })
+}
+
+fn include_path(parent: &Path, include: &Path) -> anyhow::Result<PathBuf> {
+    let path = expand_tilde(include)?;
+    let path = match parent.parent() {
+        Some(dir) if path.is_relative() => dir.join(path),
+        _ => path,
+    };
+    Ok(path)
+}
+
+pub fn expand_tilde(path: &Path) -> anyhow::Result<PathBuf> {
+    let path = path.to_string_lossy();
+    let path = expanduser::expanduser(&path)
+        .context(format!("Failed to expand tilde in path: {:?}", &path))?;
+    Ok(path)
 }
 
 pub fn path_server_pid(dir: &Path) -> PathBuf {
     dir.join(FILE_NAME_SERVER_PID)
 }
 
-pub fn path_server_sock(dir: &Path) -> PathBuf {
-    dir.join(FILE_NAME_SERVER_SOCK)
+/// In abstract mode the path only serves as a unique name, prefixed with
+/// the NUL byte which places it in the abstract namespace.
+pub fn path_server_sock(dir: &Path, abstract_socket: bool) -> PathBuf {
+    let path = dir.join(FILE_NAME_SERVER_SOCK);
+    if abstract_socket {
+        let mut name = OsString::from("\0");
+        name.push(path.with_extension(""));
+        PathBuf::from(name)
+    } else {
+        path
+    }
 }
 
-pub fn path_feeds_dir(dir: &Path) -> PathBuf {
-    dir.join(DIR_NAME_FEEDS)
+pub fn path_feeds_dir(dir: &Path, feeds_dir: Option<&Path>) -> PathBuf {
+    match feeds_dir {
+        None => dir.join(DIR_NAME_FEEDS),
+        Some(feeds_dir) => dir.join(feeds_dir),
+    }
 }
 
 pub fn path_feed_log(feed_dir: &Path) -> PathBuf {
     feed_dir.join(FILE_NAME_FEED_LOG)
 }
 
+pub fn path_feed_log_rotated(feed_dir: &Path) -> PathBuf {
+    feed_dir.join(FILE_NAME_FEED_LOG_ROTATED)
+}
+
 pub fn path_feed_pid(feed_dir: &Path) -> PathBuf {
     feed_dir.join(FILE_NAME_FEED_PID)
 }
 
+pub fn path_feed_start_info(feed_dir: &Path) -> PathBuf {
+    feed_dir.join(FILE_NAME_FEED_START_INFO)
+}
+
+pub fn path_feed_script(feed_dir: &Path) -> PathBuf {
+    feed_dir.join(FILE_NAME_FEED_SCRIPT)
+}
+
+pub fn path_server_info(dir: &Path) -> PathBuf {
+    dir.join(FILE_NAME_SERVER_INFO)
+}
+
+pub fn path_bar_snapshot(dir: &Path) -> PathBuf {
+    dir.join(FILE_NAME_BAR_SNAPSHOT)
+}
+
 pub fn path_feed_dir(
-    main_dir: &Path,
+    feeds_dir: &Path,
     feed_pos: usize,
     feed_name: &str,
 ) -> PathBuf {
     let dir_name_feed = format!("{:02}-{}", feed_pos, feed_name);
-    main_dir.join(DIR_NAME_FEEDS).join(dir_name_feed)
+    feeds_dir.join(dir_name_feed)
 }
 
-fn path_conf(dir: &Path) -> PathBuf {
+pub fn path_conf(dir: &Path) -> PathBuf {
     dir.join(FILE_NAME_CONF)
 }
+
+/// Runs the watch command, with the default shell, in dir, and signals on
+/// tx for every line it outputs. Signals are dropped while one is already
+/// pending, since a single reload covers them all. Ends when the receiver
+/// is gone, or with an error when the command exits.
+pub async fn config_watcher(
+    cmd: String,
+    dir: PathBuf,
+    tx: mpsc::Sender<()>,
+) -> anyhow::Result<()> {
+    let mut child = process::Command::new(default_shell())
+        .arg("-c")
+        .arg(&cmd)
+        .current_dir(&dir)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .context(format!("Failed to spawn watch command: {:?}", &cmd))?;
+    let stdout = child.stdout.take().unwrap_or_else(|| {
+        unreachable!("stdout not requested at process spawn.")
+    });
+    let mut lines = BufReader::new(stdout).lines();
+    while let Some(line) = lines.next_line().await? {
+        tracing::debug!(?line, "Watch command output.");
+        match tx.try_send(()) {
+            Ok(()) | Err(TrySendError::Full(())) => {}
+            Err(TrySendError::Closed(())) => return Ok(()),
+        }
+    }
+    let status = child.wait().await?;
+    bail!("Watch command {:?} exited: {}", &cmd, status)
+}
+
+/// Runs a startup or shutdown command, with the default shell, in dir, and
+/// waits for it to exit successfully. Killed if it runs past timeout, along
+/// with whatever it started, as it's in a process group of its own.
+pub async fn run_hook(
+    cmd: &str,
+    dir: &Path,
+    timeout: Duration,
+) -> anyhow::Result<()> {
+    let mut child = process::Command::new(default_shell())
+        .arg("-c")
+        .arg(cmd)
+        .current_dir(dir)
+        .stdin(Stdio::null())
+        .process_group(0)
+        .kill_on_drop(true)
+        .spawn()
+        .context(format!("Failed to spawn command: {:?}", cmd))?;
+    let Ok(status) = tokio::time::timeout(timeout, child.wait()).await else {
+        // PGID is the PID, since process_group(0).
+        if let Some(pid) = child.id().and_then(|id| i32::try_from(id).ok()) {
+            let pgid = nix::unistd::Pid::from_raw(pid);
+            if let Err(errno) = nix::sys::signal::killpg(
+                pgid,
+                nix::sys::signal::Signal::SIGKILL,
+            ) {
+                tracing::error!(?cmd, ?errno, "Failed to kill hook.");
+            }
+        }
+        bail!("Command {:?} timed out after {:?}", cmd, timeout);
+    };
+    let status = status?;
+    if !status.success() {
+        bail!("Command {:?} failed: {}", cmd, status);
+    }
+    Ok(())
+}
+
+/// Slots are filled with the character in place of each of theirs, which
+/// is only lined up if it takes exactly 1 column.
+fn validate_fill_character(field: &str, c: char) -> anyhow::Result<()> {
+    if c.is_control() {
+        bail!("{} must not be a control character: {:?}", field, c);
+    }
+    if UnicodeWidthChar::width(c) != Some(1) {
+        bail!("{} must be 1 column wide: {:?}", field, c);
+    }
+    Ok(())
+}
+
+/// tracing::Level by name, e.g. "warn", since it has no serde support.
+mod level {
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(
+        level: &Option<tracing::Level>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        match level {
+            None => serializer.serialize_none(),
+            Some(level) => {
+                serializer.serialize_some(&level.as_str().to_lowercase())
+            }
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<tracing::Level>, D::Error> {
+        Option::<String>::deserialize(deserializer)?
+            .map(|name| name.parse().map_err(serde::de::Error::custom))
+            .transpose()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{path::Path, time::Duration};
+
+    use super::{
+        Conf, ConfDiff, Feed, FeedCmd, FeedDefaults, OnStop, ValidationError,
+    };
+
+    #[tokio::test]
+    async fn config_watcher() {
+        let dir = std::env::temp_dir();
+        let cmd = "sleep 0.1; echo a; sleep 0.1; echo b".to_string();
+        let (tx, mut rx) = tokio::sync::mpsc::channel(1);
+        let watcher = tokio::spawn(super::config_watcher(cmd, dir, tx));
+        let timeout = std::time::Duration::from_secs(5);
+        for _ in 0..2 {
+            let signal = tokio::time::timeout(timeout, rx.recv()).await;
+            assert_eq!(Ok(Some(())), signal);
+        }
+        // Exited, so no more signals.
+        assert_eq!(Ok(None), tokio::time::timeout(timeout, rx.recv()).await);
+        assert!(watcher.await.unwrap().is_err());
+    }
+
+    #[tokio::test]
+    async fn run_hook() {
+        let dir = crate::tmp_path("run_hook");
+        tokio::fs::create_dir_all(&dir).await.unwrap();
+        let timeout = std::time::Duration::from_secs(5);
+
+        // Waited for, and run in dir.
+        super::run_hook("sleep 0.1; touch started", &dir, timeout)
+            .await
+            .unwrap();
+        assert!(dir.join("started").exists());
+
+        assert!(super::run_hook("exit 3", &dir, timeout).await.is_err());
+        let short = std::time::Duration::from_millis(100);
+        let error =
+            super::run_hook("sleep 10", &dir, short).await.unwrap_err();
+        assert!(error.to_string().contains("timed out"));
+
+        // What it started goes along with it.
+        let cmd = "sleep 10 & echo $! > bg_pid; wait";
+        assert!(super::run_hook(cmd, &dir, short).await.is_err());
+        let bg_pid: i32 = tokio::fs::read_to_string(dir.join("bg_pid"))
+            .await
+            .unwrap()
+            .trim()
+            .parse()
+            .unwrap();
+        let bg_pid = nix::unistd::Pid::from_raw(bg_pid);
+        let mut retries = 50;
+        while nix::sys::signal::kill(bg_pid, None).is_ok() {
+            assert!(retries > 0, "Hook's child still alive: {}", bg_pid);
+            retries -= 1;
+            tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        }
+
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+
+    #[test]
+    fn parse_error_location() {
+        let data = toml::to_string_pretty(&Conf::default()).unwrap();
+        let mut lines: Vec<&str> = data.lines().collect();
+        let line_num = lines.len() / 2;
+        lines.insert(line_num, "sep = = 5");
+        let data = lines.join("\n");
+        let error = Conf::parse(Path::new("conf.toml"), &data).unwrap_err();
+        assert_eq!(line_num + 1, error.line);
+        assert_eq!(7, error.column);
+        assert!(error.to_string().starts_with(&format!(
+            "Config error at conf.toml:{}:7: ",
+            error.line
+        )));
+    }
+
+    #[test]
+    fn validate_feed_name() {
+        for name in ["uptime", "my feed", "a.b", ".hidden", "", "ü"] {
+            assert_eq!(Ok(()), Feed::validate_name(name), "{:?}", name);
+        }
+        for name in ["a/b", "/", "a\\b", "\\"] {
+            assert!(matches!(
+                Feed::validate_name(name),
+                Err(ValidationError::PathSeparator(_))
+            ));
+        }
+        for name in ["..", "a..b", "..."] {
+            assert!(matches!(
+                Feed::validate_name(name),
+                Err(ValidationError::ParentDir(_))
+            ));
+        }
+        assert!(matches!(
+            Feed::validate_name("a\0b"),
+            Err(ValidationError::NullByte(_))
+        ));
+        assert_eq!(Ok(()), Feed::validate_name(&"x".repeat(64)));
+        assert!(matches!(
+            Feed::validate_name(&"x".repeat(65)),
+            Err(ValidationError::NameTooLong { max: 64, .. })
+        ));
+        // Length is in bytes, as limited by file systems.
+        assert!(Feed::validate_name(&"ü".repeat(33)).is_err());
+
+        let mut conf = Conf::default();
+        conf.feeds[0].name = "../oops".to_string();
+        assert!(conf.validate().is_err());
+        conf.feeds[0].name = "x".repeat(64);
+        assert!(conf.validate().is_ok());
+        conf.feeds[0].config_pos = 100;
+        assert!(conf.validate().is_err());
+    }
+
+    #[test]
+    fn validate_socket_backlog() {
+        let mut conf = Conf::default();
+        assert!(conf.validate().is_ok());
+        conf.socket_backlog = Some(1);
+        assert!(conf.validate().is_ok());
+        conf.socket_backlog = Some(0);
+        assert!(conf.validate().is_err());
+    }
+
+    #[test]
+    fn on_stop() {
+        let mut conf = Conf::default();
+        let feed = &mut conf.feeds[0];
+        for (clear_on_stop, expire_on_stop, expected) in [
+            (None, None, Some(OnStop::Expire)),
+            (Some(true), None, Some(OnStop::Clear)),
+            (Some(true), Some(false), Some(OnStop::Clear)),
+            (None, Some(true), Some(OnStop::Expire)),
+            (None, Some(false), Some(OnStop::Keep)),
+            (Some(false), Some(true), Some(OnStop::Expire)),
+            (Some(false), None, Some(OnStop::Keep)),
+            (Some(false), Some(false), Some(OnStop::Keep)),
+            (Some(true), Some(true), None),
+        ] {
+            feed.clear_on_stop = clear_on_stop;
+            feed.expire_on_stop = expire_on_stop;
+            match expected {
+                Some(on_stop) => {
+                    assert!(feed.validate().is_ok());
+                    assert_eq!(on_stop, feed.get_on_stop());
+                }
+                None => assert!(feed.validate().is_err()),
+            }
+        }
+        assert!(conf.validate().is_err());
+    }
+
+    #[test]
+    fn validate_timezone() {
+        let mut conf = Conf::default();
+        for (timezone, valid) in [
+            (None, true),
+            (Some("UTC"), true),
+            (Some("Europe/Paris"), true),
+            (Some("Mars/Olympus_Mons"), false),
+            (Some(""), false),
+        ] {
+            conf.timezone = timezone.map(String::from);
+            assert_eq!(valid, conf.validate().is_ok());
+        }
+    }
+
+    #[test]
+    fn validate_cmd() {
+        let mut conf = Conf::default();
+        for (cmd, valid) in [
+            ("date", true),
+            ("/bin/sh -c date", true),
+            ("nonexistent-barista-test-bin", true),
+            ("./nonexistent-barista-test-bin", true),
+            ("'/nonexistent/barista-test-bin'", true),
+            ("/nonexistent/barista-test-bin", false),
+            ("  /nonexistent/barista-test-bin --flag", false),
+            ("/nonexistent/barista-test-bin; date", false),
+            ("/nonexistent/barista-test-bin|cat", false),
+        ] {
+            conf.feeds[0].cmd = FeedCmd::Cmd(cmd.to_string());
+            assert_eq!(valid, conf.validate().is_ok(), "{:?}", cmd);
+        }
+        let error = conf.validate().unwrap_err().to_string();
+        assert!(error.contains("/nonexistent/barista-test-bin"), "{}", error);
+        assert!(error.contains("PATH="), "{}", error);
+
+        conf.feeds[0].cmd =
+            FeedCmd::Script("/nonexistent/barista-test-bin".to_string());
+        assert!(conf.validate().is_ok());
+    }
+
+    #[test]
+    fn validate_stop_timeout() {
+        let mut feed = Conf::default().feeds[0].clone();
+        for (secs, valid) in [
+            (None, true),
+            (Some(0.5), true),
+            (Some(0.0), false),
+            (Some(-1.0), false),
+            (Some(f64::NAN), false),
+            (Some(f64::INFINITY), false),
+        ] {
+            feed.stop_timeout_secs = secs;
+            assert_eq!(valid, feed.validate().is_ok());
+        }
+    }
+
+    #[test]
+    fn validate_systemd_run_sandboxing() {
+        let mut feed = Conf::default().feeds[0].clone();
+        for (use_systemd_run, no_new_privs, seccomp_filter, valid) in [
+            (Some(true), None, None, true),
+            (Some(true), Some(false), None, true),
+            (Some(true), Some(true), None, false),
+            (Some(true), None, Some("filter.bpf"), false),
+            (Some(false), Some(true), Some("filter.bpf"), true),
+            (None, Some(true), Some("filter.bpf"), true),
+        ] {
+            feed.use_systemd_run = use_systemd_run;
+            feed.no_new_privs = no_new_privs;
+            feed.seccomp_filter = seccomp_filter.map(Into::into);
+            assert_eq!(valid, feed.validate().is_ok());
+        }
+    }
+
+    #[test]
+    fn restart_delay() {
+        let mut feed = Conf::default().feeds[0].clone();
+        for (secs, valid) in [
+            (None, true),
+            (Some(0.5), true),
+            (Some(0.0), false),
+            (Some(-1.0), false),
+            (Some(f64::NAN), false),
+            (Some(f64::INFINITY), false),
+        ] {
+            feed.restart_delay_secs = secs;
+            assert_eq!(valid, feed.validate().is_ok());
+        }
+        feed.restart_delay_secs = Some(0.5);
+        for (restarts, secs) in
+            [(0, 0.5), (1, 1.0), (3, 4.0), (7, 60.0), (u32::MAX, 60.0)]
+        {
+            assert_eq!(
+                Duration::from_secs_f64(secs),
+                feed.get_restart_delay(restarts)
+            );
+        }
+        feed.restart_delay_secs = Some(90.0);
+        assert_eq!(Duration::from_secs(90), feed.get_restart_delay(2));
+    }
+
+    #[test]
+    fn apply_defaults() {
+        let data = r#"
+            sep = " "
+            pad_left = ""
+            pad_right = ""
+            expiry_character = "_"
+            output_interval = 1.0
+
+            [defaults]
+            ttl = 5.0
+            shell = "/bin/sh"
+
+            [[feeds]]
+            name = "a"
+            cmd = "date"
+
+            [[feeds]]
+            name = "b"
+            cmd = "date"
+            ttl = 10.0
+        "#;
+        let conf = Conf::parse(Path::new("conf.toml"), data).unwrap();
+        assert_eq!(Some(5.0), conf.feeds[0].ttl);
+        assert_eq!(Some(10.0), conf.feeds[1].ttl);
+        for feed in &conf.feeds {
+            assert_eq!(Some(Path::new("/bin/sh")), feed.shell.as_deref());
+            assert_eq!(None, feed.max_stdout_bytes_per_line);
+        }
+
+        // Idempotent.
+        let defaults = conf.defaults.clone().unwrap();
+        let feed = conf.feeds[0].apply_defaults(&defaults);
+        assert_eq!(Some(5.0), feed.ttl);
+        let feed = feed.apply_defaults(&FeedDefaults {
+            ttl: Some(1.0),
+            ..FeedDefaults::default()
+        });
+        assert_eq!(Some(5.0), feed.ttl);
+    }
+
+    #[test]
+    fn fill_characters() {
+        let mut conf = Conf::default();
+        for (c, valid) in [
+            ('_', true),
+            ('é', true),
+            ('█', true),
+            // Outside of the BMP, i.e. a surrogate pair in UTF-16.
+            ('𝔸', true),
+            ('😀', false),
+            ('\u{200D}', false), // Zero-width joiner.
+            ('\u{301}', false),  // Combining acute accent.
+            ('\t', false),
+            ('\u{7}', false),
+            ('\u{85}', false),
+        ] {
+            conf.expiry_character = c;
+            conf.clear_character = None;
+            assert_eq!(valid, conf.validate().is_ok(), "{:?}", c);
+            conf.expiry_character = '_';
+            conf.clear_character = Some(c);
+            assert_eq!(valid, conf.validate().is_ok(), "{:?}", c);
+        }
+
+        // More than one scalar, e.g. a family emoji, joined by ZWJs.
+        let data = r#"
+            sep = " "
+            pad_left = ""
+            pad_right = ""
+            expiry_character = "👨‍👩‍👧"
+            output_interval = 1.0
+        "#;
+        assert!(Conf::parse(Path::new("conf.toml"), data).is_err());
+        // Lone surrogate.
+        let data = data.replace("👨‍👩‍👧", r"\uD83D");
+        assert!(Conf::parse(Path::new("conf.toml"), &data).is_err());
+    }
+
+    #[test]
+    fn log_level() {
+        let data = r#"
+            sep = " "
+            pad_left = ""
+            pad_right = ""
+            expiry_character = "_"
+            output_interval = 1.0
+
+            [[feeds]]
+            name = "a"
+            cmd = "date"
+            log_level = "warn"
+
+            [[feeds]]
+            name = "b"
+            cmd = "date"
+        "#;
+        let conf = Conf::parse(Path::new("conf.toml"), data).unwrap();
+        assert_eq!(Some(tracing::Level::WARN), conf.feeds[0].log_level);
+        assert_eq!(None, conf.feeds[1].log_level);
+        let toml = toml::to_string(&conf).unwrap();
+        assert!(toml.contains(r#"log_level = "warn""#));
+        let again: Conf = toml::from_str(&toml).unwrap();
+        assert_eq!(conf.feeds, again.feeds);
+
+        let data = data.replace(r#""warn""#, r#""loud""#);
+        assert!(Conf::parse(Path::new("conf.toml"), &data).is_err());
+    }
+
+    #[test]
+    fn diff() {
+        let old = r#"
+            sep = " "
+            pad_left = ""
+            pad_right = ""
+            expiry_character = "_"
+            output_interval = 1.0
+
+            [[feeds]]
+            name = "a"
+            cmd = "date"
+
+            [[feeds]]
+            name = "b"
+            cmd = "date"
+
+            [[feeds]]
+            name = "c"
+            cmd = "date"
+
+            [[feeds]]
+            name = "e"
+            cmd = "date"
+        "#;
+        let new = r#"
+            sep = " "
+            pad_left = ""
+            pad_right = ""
+            expiry_character = "_"
+            output_interval = 1.0
+
+            [[feeds]]
+            name = "a"
+            cmd = "date"
+            priority = 1
+
+            [[feeds]]
+            name = "b"
+            cmd = "uptime"
+
+            [[feeds]]
+            name = "e"
+            cmd = "date"
+
+            [[feeds]]
+            name = "d"
+            cmd = "date"
+        "#;
+        let old = Conf::parse(Path::new("old.toml"), old).unwrap();
+        let mut new = Conf::parse(Path::new("new.toml"), new).unwrap();
+        assert!(old.feeds[0].is_equivalent(&new.feeds[0]));
+        assert!(!old.feeds[1].is_equivalent(&new.feeds[1]));
+        assert_eq!(
+            ConfDiff {
+                added: vec![3],
+                removed: vec![2],
+                // e moved from 3 to 2.
+                changed: vec![1, 2],
+                unchanged: vec![0],
+            },
+            Conf::diff(&old, &new)
+        );
+
+        new.timezone = Some("UTC".to_string());
+        let diff = Conf::diff(&old, &new);
+        assert_eq!(vec![0, 1, 2], diff.changed);
+        assert!(diff.unchanged.is_empty());
+    }
+
+    fn tmp_dir(name: &str) -> std::path::PathBuf {
+        let dir = crate::tmp_path(name);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn write_conf(dir: &Path, file: &str, includes: &[&str], feeds: &[&str]) {
+        let mut data = String::from(
+            r#"
+            sep = " "
+            pad_left = ""
+            pad_right = ""
+            expiry_character = "_"
+            output_interval = 1.0
+            "#,
+        );
+        data.push_str(&format!("includes = {:?}\n", includes));
+        for feed in feeds {
+            data.push_str(&format!(
+                "[[feeds]]\nname = {:?}\ncmd = \"date\"\n",
+                feed
+            ));
+        }
+        std::fs::write(dir.join(file), data).unwrap();
+    }
+
+    fn feed_names(conf: &Conf) -> Vec<&str> {
+        conf.feeds.iter().map(|feed| feed.name.as_str()).collect()
+    }
+
+    #[tokio::test]
+    async fn includes() {
+        let dir = tmp_dir("includes");
+        std::fs::create_dir_all(dir.join("sub")).unwrap();
+        write_conf(&dir, "a.toml", &["b.toml", "sub/c.toml"], &["a1", "a2"]);
+        write_conf(&dir, "b.toml", &[], &["b1"]);
+        // Relative to the including file, which is in sub/.
+        write_conf(&dir, "sub/c.toml", &["d.toml"], &["c1"]);
+        write_conf(&dir, "sub/d.toml", &[], &["d1", "d2"]);
+        let conf = Conf::from_file(&dir.join("a.toml")).await.unwrap();
+        assert_eq!(
+            vec!["a1", "a2", "b1", "c1", "d1", "d2"],
+            feed_names(&conf)
+        );
+
+        // Including the same file twice is not circular.
+        write_conf(&dir, "e.toml", &["b.toml", "b.toml"], &["e1"]);
+        let conf = Conf::from_file(&dir.join("e.toml")).await.unwrap();
+        assert_eq!(vec!["e1", "b1", "b1"], feed_names(&conf));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn feeds_dir_tilde() {
+        let dir = tmp_dir("feeds_dir_tilde");
+        let file = dir.join("conf.toml");
+        std::fs::write(
+            &file,
+            format!("feeds_dir = \"~/feeds\"\n{}", Conf::default_toml_str()),
+        )
+        .unwrap();
+        let conf = Conf::from_file(&file).await.unwrap();
+        assert_eq!(
+            expanduser::expanduser("~/feeds").unwrap(),
+            conf.get_feeds_dir(&dir)
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn includes_circular() {
+        let dir = tmp_dir("includes_circular");
+        write_conf(&dir, "a.toml", &["a.toml"], &["a1"]);
+        assert!(Conf::from_file(&dir.join("a.toml")).await.is_err());
+
+        write_conf(&dir, "b.toml", &["c.toml"], &["b1"]);
+        write_conf(&dir, "c.toml", &["d.toml"], &["c1"]);
+        write_conf(&dir, "d.toml", &["b.toml"], &["d1"]);
+        let error = Conf::from_file(&dir.join("b.toml")).await.unwrap_err();
+        assert!(error.to_string().starts_with("Circular include"));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn feed_cmd() {
+        let data = r#"
+            sep = " "
+            pad_left = ""
+            pad_right = ""
+            expiry_character = "_"
+            output_interval = 1.0
+
+            [[feeds]]
+            name = "a"
+            cmd = "date"
+
+            [[feeds]]
+            name = "b"
+            script = """
+            echo foo
+            echo bar
+            """
+        "#;
+        let conf = Conf::parse(Path::new("conf.toml"), data).unwrap();
+        assert_eq!(FeedCmd::Cmd("date".to_string()), conf.feeds[0].cmd);
+        assert!(matches!(
+            &conf.feeds[1].cmd,
+            FeedCmd::Script(script) if script.lines().count() == 3
+        ));
+
+        let data = toml::to_string_pretty(&conf).unwrap();
+        let conf = Conf::parse(Path::new("conf.toml"), &data).unwrap();
+        assert_eq!(FeedCmd::Cmd("date".to_string()), conf.feeds[0].cmd);
+
+        let data = r#"
+            sep = " "
+            pad_left = ""
+            pad_right = ""
+            expiry_character = "_"
+            output_interval = 1.0
+
+            [[feeds]]
+            name = "a"
+        "#;
+        assert!(Conf::parse(Path::new("conf.toml"), data).is_err());
+    }
+
+    #[test]
+    fn parse_default() {
+        let data = toml::to_string_pretty(&Conf::default()).unwrap();
+        assert!(Conf::parse(Path::new("conf.toml"), &data).is_ok());
+    }
+
+    #[test]
+    fn default_toml_str() {
+        let conf: Conf = toml::from_str(Conf::default_toml_str()).unwrap();
+        assert!(conf.validate().is_ok());
+        // No PartialEq for Conf (because of floats), so comparing through
+        // the serialized form, which covers every field.
+        assert_eq!(
+            toml::to_string(&Conf::default()).unwrap(),
+            toml::to_string(&conf).unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn env_overrides() {
+        let vars = |pairs: &[(&str, &str)]| -> Vec<(String, String)> {
+            pairs
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect()
+        };
+        let mut conf = Conf::default();
+        conf.apply_overrides(vars(&[
+            ("BARISTA_CONF_OUTPUT_INTERVAL", "0.5"),
+            ("BARISTA_CONF_SEP", " | "),
+            ("BARISTA_CONF_EXPIRY_CHARACTER", "'?'"),
+            ("BARISTA_CONF_SOCKET_BACKLOG", "8"),
+            ("BARISTA_CONF_BAR_TITLE", "42"),
+            ("BARISTA_CONF_CLEAR_ON_START", "true"),
+            ("BARISTA_CONF_DST", "StdOut"),
+            ("NOT_BARISTA_CONF_SEP", "x"),
+        ]))
+        .unwrap();
+        assert_eq!(0.5, conf.output_interval);
+        assert_eq!(" | ", conf.sep);
+        assert_eq!('?', conf.expiry_character);
+        assert_eq!(Some(8), conf.socket_backlog);
+        assert_eq!(Some("42"), conf.bar_title.as_deref());
+        assert_eq!(Some(true), conf.clear_on_start);
+        assert!(matches!(conf.dst, Some(super::Dst::StdOut)));
+        assert_eq!(2, conf.feeds.len());
+
+        for bad in [
+            ("BARISTA_CONF_OUTPUT_INTERVAL", "fast"),
+            ("BARISTA_CONF_SOCKET_BACKLOG", "-1"),
+            ("BARISTA_CONF_FEEDS", "[]"),
+            ("BARISTA_CONF_NO_SUCH_FIELD", "1"),
+        ] {
+            let mut conf = Conf::default();
+            let result = conf.apply_overrides(vars(&[bad]));
+            assert!(result.is_err(), "{:?}", bad);
+        }
+
+        // Over the file.
+        let dir = tmp_dir("env_overrides");
+        write_conf(&dir, "conf.toml", &[], &["a"]);
+        let conf = Conf::from_file_with_vars(
+            &dir.join("conf.toml"),
+            vars(&[("BARISTA_CONF_BAR_TITLE", "container")]),
+        )
+        .await
+        .unwrap();
+        assert_eq!(Some("container"), conf.bar_title.as_deref());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
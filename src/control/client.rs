@@ -1,13 +1,17 @@
 use std::{
+    net::SocketAddr,
     path::Path,
-    time::{Duration, SystemTime},
+    time::{Duration, Instant, SystemTime},
 };
 
-use anyhow::anyhow;
+use anyhow::{anyhow, bail, Context};
 use tarpc::{
     tokio_serde::formats::Bincode, tokio_util::codec::LengthDelimitedCodec,
 };
-use tokio::net::UnixStream;
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::{TcpStream, UnixStream},
+};
 
 use crate::{bar, conf, control};
 
@@ -17,9 +21,36 @@ pub struct Client {
 }
 
 impl Client {
-    pub async fn new(dir: &Path, timeout: Duration) -> anyhow::Result<Self> {
-        let conn = UnixStream::connect(conf::path_server_sock(dir)).await?;
-        let codec_builder = LengthDelimitedCodec::builder();
+    pub async fn new(
+        dir: &Path,
+        abstract_socket: bool,
+        timeout: Duration,
+        max_message_bytes: usize,
+    ) -> anyhow::Result<Self> {
+        let sock_file = conf::path_server_sock(dir, abstract_socket);
+        let conn = UnixStream::connect(sock_file).await?;
+        Self::from_conn(conn, timeout, max_message_bytes)
+    }
+
+    pub async fn new_tcp(
+        addr: SocketAddr,
+        timeout: Duration,
+        max_message_bytes: usize,
+    ) -> anyhow::Result<Self> {
+        let conn = TcpStream::connect(addr).await?;
+        Self::from_conn(conn, timeout, max_message_bytes)
+    }
+
+    pub(crate) fn from_conn<C>(
+        conn: C,
+        timeout: Duration,
+        max_message_bytes: usize,
+    ) -> anyhow::Result<Self>
+    where
+        C: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    {
+        let mut codec_builder = LengthDelimitedCodec::builder();
+        codec_builder.max_frame_length(max_message_bytes);
         let transport = tarpc::serde_transport::new(
             codec_builder.new_framed(conn),
             Bincode::default(),
@@ -47,32 +78,304 @@ impl Client {
         Ok(())
     }
 
-    pub async fn status(&self, machine: bool) -> anyhow::Result<()> {
-        let status = match self.client.status(self.ctx).await {
+    /// Writes status to the output file, or to stdout if there is none or it
+    /// is "-".
+    pub async fn status(
+        &self,
+        format: bar::status::Format,
+        color: bool,
+        output_file: Option<&Path>,
+    ) -> anyhow::Result<()> {
+        let status = self.get_status().await;
+        output_status(&status, format, color, output_file).await
+    }
+
+    /// Failures to get the status are reported as the status.
+    async fn get_status(&self) -> bar::status::Status {
+        match self.client.status(self.ctx).await {
             Ok(Ok(status)) => status,
             Ok(Err(error)) => {
-                tracing::error!(?error, "Server failed to compute status.");
-                bar::status::Status::default()
+                tracing::error!(?error, "Server failed to get status.");
+                bar::status::Status::Failed {
+                    error: error.to_string(),
+                }
             }
             Err(error) => {
                 tracing::error!(
                     ?error,
                     "Failed to communicate with the server."
                 );
-                bar::status::Status::default()
+                bar::status::Status::Unreachable {
+                    reason: error.to_string(),
+                }
+            }
+        }
+    }
+
+    /// Prints the feed fields which changed by more than the threshold
+    /// since the baseline, which is a status saved in JSON format.
+    pub async fn diff(
+        &self,
+        baseline: &Path,
+        threshold: f64,
+    ) -> anyhow::Result<()> {
+        let data = tokio::fs::read_to_string(baseline)
+            .await
+            .context(format!("Failed to read baseline: {:?}", baseline))?;
+        let old: bar::status::Status = serde_json::from_str(&data)
+            .context(format!("Invalid baseline status: {:?}", baseline))?;
+        let new = self.get_status().await;
+        for (status, which) in [(&old, "Baseline"), (&new, "Current")] {
+            if !matches!(status, bar::status::Status::UpOn { .. }) {
+                bail!(
+                    "{} status has no feeds to compare: {}",
+                    which,
+                    status.to_str(bar::status::Audience::Machine)
+                );
             }
-        };
-        let audience = if machine {
-            bar::status::Audience::Machine
+        }
+        let diffs = bar::status::diff(&old, &new, threshold);
+        if diffs.is_empty() {
+            println!("No changes beyond {}%.", threshold * 100.0);
         } else {
-            bar::status::Audience::Human
-        };
-        println!("{}", status.to_str(audience));
+            println!("{}", bar::status::diffs_to_str(&diffs));
+        }
+        Ok(())
+    }
+
+    /// Round-trip time.
+    pub async fn ping(&self) -> anyhow::Result<Duration> {
+        let start = Instant::now();
+        self.client.ping(self.ctx).await??;
+        Ok(start.elapsed())
+    }
+
+    /// The config the server is currently using.
+    pub async fn get_conf(&self) -> anyhow::Result<conf::Conf> {
+        let data = self.client.get_conf(self.ctx).await??;
+        let conf = toml::from_str(&data)
+            .context("Failed to parse config received from the server")?;
+        Ok(conf)
+    }
+
+    /// The config the server is currently using for the feed at the given
+    /// (0-based) position.
+    pub async fn get_feed_conf(
+        &self,
+        pos: usize,
+    ) -> anyhow::Result<conf::Feed> {
+        let data = self.client.get_feed_conf(self.ctx, pos).await??;
+        let feed = toml::from_str(&data).context(
+            "Failed to parse feed config received from the server",
+        )?;
+        Ok(feed)
+    }
+
+    pub async fn set_separator(&self, sep: String) -> anyhow::Result<()> {
+        self.client.set_separator(self.ctx, sep).await??;
+        Ok(())
+    }
+
+    /// Current contents of all slots, in order.
+    pub async fn snapshot_bar(&self) -> anyhow::Result<Vec<String>> {
+        let slots = self.client.snapshot_bar(self.ctx).await??;
+        Ok(slots)
+    }
+
+    /// Writes the bar right away, even if it hasn't changed.
+    pub async fn force_output(&self) -> anyhow::Result<()> {
+        self.client.force_output(self.ctx).await??;
+        Ok(())
+    }
+
+    /// Reloads take longer than most requests, so may need a longer
+    /// timeout than the client's. The server finishes the reload even if
+    /// the client gives up on it.
+    pub async fn reload(
+        &self,
+        timeout: Option<Duration>,
+    ) -> anyhow::Result<()> {
+        let mut ctx = self.ctx;
+        if let Some(timeout) = timeout {
+            ctx.deadline = SystemTime::now()
+                .checked_add(timeout)
+                .ok_or(anyhow!("Bad timeout value"))?;
+        }
+        self.client.reload(ctx).await??;
+        Ok(())
+    }
+
+    pub async fn clear_feed_log(&self, pos: usize) -> anyhow::Result<()> {
+        self.client.clear_feed_log(self.ctx, pos).await??;
         Ok(())
     }
 
-    pub async fn reload(&self) -> anyhow::Result<()> {
-        self.client.reload(self.ctx).await??;
+    pub async fn search_feed_log(
+        &self,
+        pos: usize,
+        pattern: &str,
+        max_lines: usize,
+    ) -> anyhow::Result<()> {
+        let lines = self
+            .client
+            .search_feed_log(self.ctx, pos, pattern.to_string(), max_lines)
+            .await??;
+        for line in lines {
+            println!("{}", line);
+        }
         Ok(())
     }
 }
+
+/// Status for when the server could not be connected to.
+pub async fn status_without_server(
+    dir: &Path,
+    error: &anyhow::Error,
+) -> bar::status::Status {
+    // An abstract socket leaves nothing behind, so it just looks down.
+    let sock_file = conf::path_server_sock(dir, false);
+    if tokio::fs::try_exists(&sock_file).await.unwrap_or(false) {
+        bar::status::Status::Unreachable {
+            reason: format!("{:#}", error),
+        }
+    } else {
+        bar::status::status_from_server_info(dir).await
+    }
+}
+
+pub async fn output_status(
+    status: &bar::status::Status,
+    format: bar::status::Format,
+    color: bool,
+    output_file: Option<&Path>,
+) -> anyhow::Result<()> {
+    let output = match format {
+        bar::status::Format::Table => {
+            status.to_str_colored(bar::status::Audience::Human, color)
+        }
+        bar::status::Format::Tsv => status.to_tsv(),
+        bar::status::Format::Json => status.to_json()?,
+    };
+    write_output(output_file, &output).await
+}
+
+async fn write_output(file: Option<&Path>, data: &str) -> anyhow::Result<()> {
+    match file {
+        Some(file) if file != Path::new("-") => {
+            tokio::fs::write(file, format!("{}\n", data))
+                .await
+                .context(format!("Failed to write output to: {:?}", file))?;
+        }
+        _ => println!("{}", data),
+    }
+    Ok(())
+}
+
+/// For tests: connects over the Unix socket in dir, see connect_with.
+/// Public only for the binary's tests.
+#[doc(hidden)]
+pub async fn connect(dir: &Path) -> Client {
+    connect_with(|| {
+        Client::new(
+            dir,
+            false,
+            Duration::from_secs(5),
+            conf::DEFAULT_MAX_RPC_MESSAGE_BYTES,
+        )
+    })
+    .await
+}
+
+/// For tests: retries the connection until the server is up, such as
+/// right after it was started, and panics if it is not up in a few
+/// seconds, rather than hang. Public only for the binary's tests.
+#[doc(hidden)]
+pub async fn connect_with<F, Fut>(new: F) -> Client
+where
+    F: Fn() -> Fut,
+    Fut: std::future::Future<Output = anyhow::Result<Client>>,
+{
+    let deadline = Instant::now() + Duration::from_secs(5);
+    loop {
+        match new().await {
+            Ok(client) => return client,
+            Err(error) if Instant::now() >= deadline => {
+                panic!("Server not up in time: {:?}", error);
+            }
+            Err(_) => tokio::time::sleep(Duration::from_millis(10)).await,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::{status_without_server, write_output};
+    use crate::{
+        bar::status::{ServerInfo, Status},
+        conf,
+    };
+
+    #[tokio::test]
+    async fn write_output_file() {
+        let file = crate::tmp_path("write_output");
+        write_output(Some(&file), "up off").await.unwrap();
+        assert_eq!(
+            "up off\n",
+            tokio::fs::read_to_string(&file).await.unwrap()
+        );
+        tokio::fs::remove_file(&file).await.unwrap();
+
+        // Stdout.
+        write_output(Some(Path::new("-")), "up off").await.unwrap();
+        assert!(!Path::new("-").exists());
+        write_output(None, "up off").await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn status_when_down() {
+        let dir = crate::tmp_path("status_when_down");
+        tokio::fs::create_dir_all(&dir).await.unwrap();
+        let error = anyhow::anyhow!("Connection refused");
+
+        // Crashed, so nothing left behind.
+        assert!(matches!(
+            status_without_server(&dir, &error).await,
+            Status::Down {
+                since: None,
+                last_error: None
+            }
+        ));
+
+        // Shut down after an error.
+        tokio::fs::write(conf::path_conf(&dir), "").await.unwrap();
+        ServerInfo::write_started(&dir, &dir.join("feeds"))
+            .await
+            .unwrap();
+        ServerInfo::write_stopped(
+            &dir,
+            Some("Premature server exit".to_string()),
+        )
+        .await
+        .unwrap();
+        assert!(matches!(
+            status_without_server(&dir, &error).await,
+            Status::Down {
+                since: Some(_),
+                last_error: Some(e)
+            } if e == "Premature server exit"
+        ));
+
+        // Socket left behind, but nobody listening.
+        tokio::fs::write(conf::path_server_sock(&dir, false), "")
+            .await
+            .unwrap();
+        assert!(matches!(
+            status_without_server(&dir, &error).await,
+            Status::Unreachable { reason } if reason == "Connection refused"
+        ));
+
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+}
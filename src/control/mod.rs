@@ -8,24 +8,92 @@ use serde::{Deserialize, Serialize};
 use crate::bar;
 
 #[derive(Debug, thiserror::Error, Serialize, Deserialize)]
-#[error("{text:?}")]
-pub struct Error {
-    text: String,
+pub enum ControlError {
+    #[error("Bar server is not running")]
+    NotRunning,
+
+    #[error("Bar server is already running")]
+    AlreadyRunning,
+
+    #[error("Feed not found at position: {pos}")]
+    FeedNotFound { pos: usize },
+
+    #[error("Config error: {detail}")]
+    ConfigError { detail: String },
+
+    #[error("Timed out: {op}")]
+    Timeout { op: String },
+
+    #[error("Internal error: {detail}")]
+    Internal { detail: String },
 }
 
-impl From<bar::server::ApiError> for Error {
+impl From<bar::server::ApiError> for ControlError {
     fn from(e: bar::server::ApiError) -> Self {
-        let text = e.to_string();
-        Self { text }
+        use bar::server::ApiError;
+
+        match e {
+            ApiError::Dead(_) => Self::NotRunning,
+            ApiError::FeedNotFound { pos } => Self::FeedNotFound { pos },
+            ApiError::Timeout { op } => Self::Timeout { op },
+            ApiError::ConfFailed(error) => Self::ConfigError {
+                detail: format!("{:?}", error),
+            },
+            e @ (ApiError::OpFailed(_) | ApiError::Crashed(_)) => {
+                Self::Internal {
+                    detail: e.to_string(),
+                }
+            }
+        }
     }
 }
 
-pub type Result<T> = result::Result<T, Error>;
+pub type Result<T> = result::Result<T, ControlError>;
 
 #[tarpc::service]
 pub trait BarCtl {
     async fn on() -> Result<()>;
     async fn off() -> Result<()>;
     async fn status() -> Result<bar::status::Status>;
+    async fn ping() -> Result<()>;
+    /// As TOML, since Conf doesn't fit the binary transport format.
+    async fn get_conf() -> Result<String>;
+    /// As TOML, same as get_conf.
+    async fn get_feed_conf(pos: usize) -> Result<String>;
+    async fn set_separator(sep: String) -> Result<()>;
+    /// Current contents of all slots, in order.
+    async fn snapshot_bar() -> Result<Vec<String>>;
+    async fn force_output() -> Result<()>;
     async fn reload() -> Result<()>;
+    async fn clear_feed_log(pos: usize) -> Result<()>;
+    async fn search_feed_log(
+        pos: usize,
+        pattern: String,
+        max_lines: usize,
+    ) -> Result<Vec<String>>;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ControlError;
+    use crate::bar::server::ApiError;
+
+    #[test]
+    fn from_api_error() {
+        let (tx, rx) = tokio::sync::oneshot::channel::<()>();
+        drop(tx);
+        let recv_error = rx.blocking_recv().unwrap_err();
+        assert!(matches!(
+            ControlError::from(ApiError::Crashed(recv_error)),
+            ControlError::Internal { .. }
+        ));
+        assert!(matches!(
+            ControlError::from(ApiError::OpFailed(anyhow::anyhow!("x"))),
+            ControlError::Internal { .. }
+        ));
+        assert!(matches!(
+            ControlError::from(ApiError::ConfFailed(anyhow::anyhow!("x"))),
+            ControlError::ConfigError { .. }
+        ));
+    }
 }
@@ -1,93 +1,1239 @@
-use std::{future::Future, path::PathBuf};
+use std::{
+    collections::HashMap,
+    future::Future,
+    io,
+    net::SocketAddr,
+    path::PathBuf,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    task::{Context, Poll},
+    time::{Duration, SystemTime},
+};
 
-use futures_util::StreamExt;
+use futures_util::{
+    future::{ready, AbortHandle, Abortable},
+    StreamExt,
+};
 use tarpc::{
     context,
     server::{BaseChannel, Channel},
     tokio_serde::formats::Bincode,
     tokio_util::codec::LengthDelimitedCodec,
 };
-use tokio::net::UnixSocket;
-use tracing::Instrument;
+use tokio::{
+    io::{AsyncRead, AsyncWrite, ReadBuf},
+    net::{TcpListener, TcpSocket, TcpStream, UnixSocket, UnixStream},
+    sync::watch,
+    task::LocalSet,
+};
+use tokio_util::sync::CancellationToken;
+use tracing::{info_span, Instrument, Span};
 
 use crate::{
     bar, conf,
     control::{self, BarCtl},
 };
 
+/// Distinguishes log lines of concurrent clients.
+static NEXT_CONNECTION_ID: AtomicU64 = AtomicU64::new(0);
+
 #[derive(Clone)]
 struct BarCtlServer {
     bar_tx: bar::server::ApiSender,
+    connections: watch::Receiver<usize>,
+    // Handlers are spawned by tarpc outside of the connection's span,
+    // so they have to be told which connection they serve.
+    connection_id: u64,
+    /// Process on the other end of a Unix socket connection.
+    peer_pid: Option<u32>,
+}
+
+/// Who connected, as far as the socket can tell. Nothing for TCP.
+#[derive(Debug, Clone, Copy, Default)]
+struct Peer {
+    pid: Option<u32>,
+    uid: Option<u32>,
+    gid: Option<u32>,
+}
+
+impl Peer {
+    fn of_unix(conn: &UnixStream) -> Self {
+        match conn.peer_cred() {
+            Ok(cred) => Self {
+                pid: cred.pid().and_then(|pid| u32::try_from(pid).ok()),
+                uid: Some(cred.uid()),
+                gid: Some(cred.gid()),
+            },
+            Err(error) => {
+                tracing::warn!(?error, "Failed to get peer credentials.");
+                Self::default()
+            }
+        }
+    }
+
+    /// Tells peers apart in logs, without exposing their credentials.
+    /// Stable for the same credentials, so can be matched across logs.
+    fn hash(&self) -> Option<String> {
+        use sha2::Digest;
+
+        let Self { pid, uid, gid } = self;
+        if pid.is_none() && uid.is_none() && gid.is_none() {
+            return None;
+        }
+        let creds = format!("{:?}:{:?}:{:?}", pid, uid, gid);
+        let hash: String = sha2::Sha256::digest(creds.as_bytes())
+            .iter()
+            .take(8)
+            .map(|byte| format!("{:02x}", byte))
+            .collect();
+        Some(hash)
+    }
+}
+
+/// Requests of a connection which are still being handled, by request ID,
+/// so that they can be cancelled once the client is gone, rather than
+/// finish for nobody.
+#[derive(Clone, Default)]
+struct InFlightTracker {
+    requests: Arc<Mutex<HashMap<u64, AbortHandle>>>,
+}
+
+impl InFlightTracker {
+    fn track<F>(&self, id: u64, fut: F) -> impl Future<Output = ()>
+    where
+        F: Future<Output = ()>,
+    {
+        let (abort_handle, abort_registration) = AbortHandle::new_pair();
+        self.lock().insert(id, abort_handle);
+        let tracker = self.clone();
+        async move {
+            if Abortable::new(fut, abort_registration).await.is_err() {
+                tracing::debug!(id, "Request cancelled.");
+            }
+            tracker.lock().remove(&id);
+        }
+    }
+
+    /// Returns how many were cancelled.
+    fn abort_all(&self) -> usize {
+        let mut requests = self.lock();
+        for abort_handle in requests.values() {
+            abort_handle.abort();
+        }
+        let n = requests.len();
+        requests.clear();
+        n
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<u64, AbortHandle>> {
+        self.requests.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Cancels the token when the client closes its end of the connection, as
+/// seen by a read of nothing, or breaks it.
+struct EofSignal<C> {
+    conn: C,
+    eof: CancellationToken,
+}
+
+impl<C: AsyncRead + Unpin> AsyncRead for EofSignal<C> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let filled = buf.filled().len();
+        let room = buf.remaining() > 0;
+        let result = Pin::new(&mut self.conn).poll_read(cx, buf);
+        match &result {
+            Poll::Ready(Ok(())) if room && buf.filled().len() == filled => {
+                self.eof.cancel();
+            }
+            Poll::Ready(Err(_)) => self.eof.cancel(),
+            Poll::Ready(Ok(())) | Poll::Pending => {}
+        }
+        result
+    }
+}
+
+impl<C: AsyncWrite + Unpin> AsyncWrite for EofSignal<C> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.conn).poll_write(cx, buf)
+    }
+
+    fn poll_flush(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.conn).poll_flush(cx)
+    }
+
+    fn poll_shutdown(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.conn).poll_shutdown(cx)
+    }
 }
 
 impl control::BarCtl for BarCtlServer {
-    #[tracing::instrument(skip_all)]
-    async fn on(self, _: context::Context) -> control::Result<()> {
+    #[tracing::instrument(
+        skip_all,
+        fields(
+            connection_id = self.connection_id,
+            peer_pid = self.peer_pid
+        )
+    )]
+    async fn on(self, ctx: context::Context) -> control::Result<()> {
         tracing::debug!("Received start req.");
-        bar::server::on(&self.bar_tx).await?;
+        let on = bar::server::on(&self.bar_tx);
+        bar::server::with_timeout("on", remaining(&ctx), on).await?;
         Ok(())
     }
 
-    #[tracing::instrument(skip_all)]
-    async fn off(self, _: context::Context) -> control::Result<()> {
+    #[tracing::instrument(
+        skip_all,
+        fields(
+            connection_id = self.connection_id,
+            peer_pid = self.peer_pid
+        )
+    )]
+    async fn off(self, ctx: context::Context) -> control::Result<()> {
         tracing::debug!("Received stop req.");
-        bar::server::off(&self.bar_tx).await?;
+        let off = bar::server::off(&self.bar_tx);
+        bar::server::with_timeout("off", remaining(&ctx), off).await?;
         Ok(())
     }
 
-    #[tracing::instrument(skip_all)]
+    #[tracing::instrument(
+        skip_all,
+        fields(
+            connection_id = self.connection_id,
+            peer_pid = self.peer_pid
+        )
+    )]
     async fn status(
         self,
         _: context::Context,
     ) -> control::Result<bar::status::Status> {
         tracing::debug!("Received status req.");
-        let status = bar::server::status(&self.bar_tx).await?;
+        let mut status = bar::server::status(&self.bar_tx).await?;
+        status.set_connections(*self.connections.borrow());
         Ok(status)
     }
 
-    #[tracing::instrument(skip_all)]
-    async fn reload(self, _: context::Context) -> control::Result<()> {
+    #[tracing::instrument(
+        skip_all,
+        fields(
+            connection_id = self.connection_id,
+            peer_pid = self.peer_pid
+        )
+    )]
+    async fn ping(self, _: context::Context) -> control::Result<()> {
+        tracing::debug!("Received ping req.");
+        bar::server::ping(&self.bar_tx).await?;
+        Ok(())
+    }
+
+    #[tracing::instrument(
+        skip_all,
+        fields(
+            connection_id = self.connection_id,
+            peer_pid = self.peer_pid
+        )
+    )]
+    async fn get_conf(self, _: context::Context) -> control::Result<String> {
+        tracing::debug!("Received get conf req.");
+        let conf = bar::server::get_conf(&self.bar_tx).await?;
+        toml::to_string(&conf).map_err(|error| {
+            control::ControlError::Internal {
+                detail: format!("Failed to serialize config: {:?}", error),
+            }
+        })
+    }
+
+    #[tracing::instrument(
+        skip_all,
+        fields(
+            connection_id = self.connection_id,
+            peer_pid = self.peer_pid
+        )
+    )]
+    async fn get_feed_conf(
+        self,
+        _: context::Context,
+        pos: usize,
+    ) -> control::Result<String> {
+        tracing::debug!(pos, "Received get feed conf req.");
+        let feed = bar::server::get_feed_conf(&self.bar_tx, pos).await?;
+        toml::to_string(&feed).map_err(|error| {
+            control::ControlError::Internal {
+                detail: format!(
+                    "Failed to serialize feed config: {:?}",
+                    error
+                ),
+            }
+        })
+    }
+
+    #[tracing::instrument(
+        skip_all,
+        fields(
+            connection_id = self.connection_id,
+            peer_pid = self.peer_pid
+        )
+    )]
+    async fn set_separator(
+        self,
+        _: context::Context,
+        sep: String,
+    ) -> control::Result<()> {
+        tracing::debug!(?sep, "Received set separator req.");
+        bar::server::set_separator(&self.bar_tx, sep).await?;
+        Ok(())
+    }
+
+    #[tracing::instrument(
+        skip_all,
+        fields(
+            connection_id = self.connection_id,
+            peer_pid = self.peer_pid
+        )
+    )]
+    async fn snapshot_bar(
+        self,
+        _: context::Context,
+    ) -> control::Result<Vec<String>> {
+        tracing::debug!("Received snapshot bar req.");
+        let slots = bar::server::snapshot_bar(&self.bar_tx).await?;
+        Ok(slots)
+    }
+
+    #[tracing::instrument(
+        skip_all,
+        fields(
+            connection_id = self.connection_id,
+            peer_pid = self.peer_pid
+        )
+    )]
+    async fn force_output(self, _: context::Context) -> control::Result<()> {
+        tracing::debug!("Received force output req.");
+        bar::server::force_output(&self.bar_tx).await?;
+        Ok(())
+    }
+
+    #[tracing::instrument(
+        skip_all,
+        fields(
+            connection_id = self.connection_id,
+            peer_pid = self.peer_pid
+        )
+    )]
+    async fn reload(self, ctx: context::Context) -> control::Result<()> {
         tracing::debug!("Received reload req.");
-        bar::server::reload(&self.bar_tx).await?;
+        let bar_tx = self.bar_tx.clone();
+        // In its own task, so that it isn't left half-done, with the bar
+        // off, when the client gives up waiting and this one is dropped.
+        // Concurrent reloads take turns in the bar server, and waiting for
+        // a turn counts against the client's deadline.
+        let reload = async {
+            let reload = tokio::spawn(
+                async move { bar::server::reload(&bar_tx).await }
+                    .in_current_span(),
+            );
+            reload.await.map_err(|error| {
+                bar::server::ApiError::OpFailed(error.into())
+            })?
+        };
+        bar::server::with_timeout("reload", remaining(&ctx), reload).await?;
+        Ok(())
+    }
+
+    #[tracing::instrument(
+        skip_all,
+        fields(
+            connection_id = self.connection_id,
+            peer_pid = self.peer_pid
+        )
+    )]
+    async fn clear_feed_log(
+        self,
+        _: context::Context,
+        pos: usize,
+    ) -> control::Result<()> {
+        tracing::debug!(pos, "Received clear feed log req.");
+        bar::server::clear_feed_log(&self.bar_tx, pos).await?;
         Ok(())
     }
+
+    #[tracing::instrument(
+        skip_all,
+        fields(
+            connection_id = self.connection_id,
+            peer_pid = self.peer_pid
+        )
+    )]
+    async fn search_feed_log(
+        self,
+        _: context::Context,
+        pos: usize,
+        pattern: String,
+        max_lines: usize,
+    ) -> control::Result<Vec<String>> {
+        tracing::debug!(
+            pos,
+            pattern,
+            max_lines,
+            "Received search feed log req."
+        );
+        let lines = bar::server::search_feed_log(
+            &self.bar_tx,
+            pos,
+            pattern,
+            max_lines,
+        )
+        .await?;
+        Ok(lines)
+    }
 }
 
-#[tracing::instrument(name = "control", skip_all)]
+/// Time left until the client's deadline.
+fn remaining(ctx: &context::Context) -> Duration {
+    ctx.deadline
+        .duration_since(SystemTime::now())
+        .unwrap_or_default()
+}
+
+/// Compatibility shim over run_local, for callers which need a Send
+/// future, such as to spawn it: serves on a blocking thread of its own,
+/// until done or dropped.
 pub async fn run(
     dir: PathBuf,
     backlog: u32,
+    abstract_socket: bool,
+    tcp_bind: Option<SocketAddr>,
+    max_message_bytes: usize,
     bar_tx: bar::server::ApiSender,
 ) -> anyhow::Result<()> {
-    let sock_file = conf::path_server_sock(&dir);
-    let bar_ctl_srv = BarCtlServer { bar_tx };
+    let stop = CancellationToken::new();
+    let _stop_on_drop = stop.clone().drop_guard();
+    let runtime = tokio::runtime::Handle::current();
+    let span = Span::current();
+    // Subscribers may be set just for the current thread, such as in tests.
+    let dispatch = tracing::dispatcher::get_default(Clone::clone);
+    tokio::task::spawn_blocking(move || {
+        let _dispatch = tracing::dispatcher::set_default(&dispatch);
+        runtime.block_on(
+            async {
+                tokio::select! {
+                    biased;
+                    () = stop.cancelled() => Ok(()),
+                    result = run_local(
+                        dir,
+                        backlog,
+                        abstract_socket,
+                        tcp_bind,
+                        max_message_bytes,
+                        bar_tx,
+                    ) => result,
+                }
+            }
+            .instrument(span),
+        )
+    })
+    .await?
+}
+
+/// Serves all connections on a LocalSet of the current thread, so that
+/// handlers need not be Send.
+#[tracing::instrument(name = "control", skip_all)]
+pub async fn run_local(
+    dir: PathBuf,
+    backlog: u32,
+    abstract_socket: bool,
+    tcp_bind: Option<SocketAddr>,
+    max_message_bytes: usize,
+    bar_tx: bar::server::ApiSender,
+) -> anyhow::Result<()> {
+    let sock_file = conf::path_server_sock(&dir, abstract_socket);
+    let (connections_tx, connections_rx) = watch::channel(0);
+    let connections_tx = Arc::new(connections_tx);
+    let bar_ctl_srv = BarCtlServer {
+        bar_tx,
+        connections: connections_rx,
+        connection_id: 0,
+        peer_pid: None,
+    };
     let socket = UnixSocket::new_stream()?;
     socket.bind(&sock_file)?;
+    let unix_listener = socket.listen(backlog)?;
+    let tcp_listener =
+        tcp_bind.map(|addr| tcp_listen(addr, backlog)).transpose()?;
+    let local = LocalSet::new();
+    local
+        .run_until(async {
+            loop {
+                tracing::debug!("Waiting ...");
+                tokio::select! {
+                    accepted = unix_listener.accept() => match accepted {
+                        Ok((conn, addr)) => {
+                            let peer = Peer::of_unix(&conn);
+                            tracing::debug!(
+                                from = ?addr,
+                                peer = peer.hash(),
+                                "Accepted"
+                            );
+                            handle_connection(
+                                conn,
+                                peer,
+                                max_message_bytes,
+                                &bar_ctl_srv,
+                                &connections_tx,
+                            );
+                        }
+                        Err(error) => {
+                            tracing::error!(
+                                ?error,
+                                "Error accepting connection"
+                            );
+                            // Errors which persist, such as once the
+                            // runtime is shutting down, would otherwise
+                            // spin here without ever letting run stop us.
+                            tokio::task::yield_now().await;
+                        }
+                    },
+                    accepted = tcp_accept(tcp_listener.as_ref()) => {
+                        match accepted {
+                            Ok((conn, addr)) => {
+                                tracing::debug!(from = ?addr, "Accepted TCP");
+                                handle_connection(
+                                    conn,
+                                    Peer::default(),
+                                    max_message_bytes,
+                                    &bar_ctl_srv,
+                                    &connections_tx,
+                                );
+                            }
+                            Err(error) => {
+                                tracing::error!(
+                                    ?error,
+                                    "Error accepting TCP connection"
+                                );
+                                tokio::task::yield_now().await;
+                            }
+                        }
+                    }
+                }
+            }
+        })
+        .await
+}
+
+fn tcp_listen(addr: SocketAddr, backlog: u32) -> io::Result<TcpListener> {
+    let socket = match addr {
+        SocketAddr::V4(_) => TcpSocket::new_v4()?,
+        SocketAddr::V6(_) => TcpSocket::new_v6()?,
+    };
+    socket.set_reuseaddr(true)?;
+    socket.bind(addr)?;
     let listener = socket.listen(backlog)?;
-    let codec_builder = LengthDelimitedCodec::builder();
-    loop {
-        tracing::debug!("Waiting ...");
-        let (conn, _addr) = match listener.accept().await {
-            Ok((conn, addr)) => {
-                tracing::debug!(from = ?addr, "Accepted");
-                (conn, addr)
+    tracing::info!(?addr, "Listening on TCP.");
+    Ok(listener)
+}
+
+/// Never ready if there's no listener.
+async fn tcp_accept(
+    listener: Option<&TcpListener>,
+) -> io::Result<(TcpStream, SocketAddr)> {
+    match listener {
+        Some(listener) => listener.accept().await,
+        None => std::future::pending().await,
+    }
+}
+
+fn handle_connection<C>(
+    conn: C,
+    peer: Peer,
+    max_message_bytes: usize,
+    bar_ctl_srv: &BarCtlServer,
+    connections_tx: &Arc<watch::Sender<usize>>,
+) where
+    C: AsyncRead + AsyncWrite + Unpin + 'static,
+{
+    let (bar_ctl_srv, span) = next_connection(bar_ctl_srv, peer);
+    let eof = CancellationToken::new();
+    let conn = EofSignal {
+        conn,
+        eof: eof.clone(),
+    };
+    let framed = LengthDelimitedCodec::builder()
+        .max_frame_length(max_message_bytes)
+        .new_framed(conn);
+    let transport = tarpc::serde_transport::new(framed, Bincode::default());
+    let serve = bar_ctl_srv.serve();
+    let in_flight = InFlightTracker::default();
+    let fut = BaseChannel::with_defaults(transport)
+        .requests()
+        .take_while(|request| ready(request.is_ok()))
+        .filter_map(|request| ready(request.ok()))
+        .map({
+            let in_flight = in_flight.clone();
+            move |request| {
+                let id = request.get().id;
+                in_flight.track(id, request.execute(serve.clone()))
             }
-            Err(error) => {
-                tracing::error!(?error, "Error accepting connection");
-                continue;
+        })
+        .for_each(spawn_local);
+    let connections_tx = connections_tx.clone();
+    tokio::task::spawn_local(
+        async move {
+            connections_tx.send_modify(|n| *n += 1);
+            // Isolating each connection in its own task, so that
+            // whatever a misbehaving client causes stays contained.
+            let mut task = tokio::task::spawn_local(fut.in_current_span());
+            tokio::select! {
+                result = &mut task => if let Err(error) = result {
+                    tracing::warn!(?error, "Connection handler failed.");
+                },
+                _ = eof.cancelled() => {
+                    task.abort();
+                    client_gone(&in_flight);
+                }
             }
-        };
-        let framed = codec_builder.new_framed(conn);
-        let transport =
-            tarpc::serde_transport::new(framed, Bincode::default());
+            connections_tx.send_modify(|n| *n -= 1);
+            tracing::debug!("Connection closed.");
+        }
+        .instrument(span),
+    );
+}
 
-        let fut = BaseChannel::with_defaults(transport)
-            .execute(bar_ctl_srv.clone().serve())
-            .for_each(spawn);
-        tokio::spawn(fut.in_current_span());
+/// Nobody to respond to anymore, so no point in going on with the
+/// connection's requests. Those which change state in ways which shouldn't
+/// be left half-done, such as reload, do so in tasks of their own.
+fn client_gone(in_flight: &InFlightTracker) {
+    let cancelled = in_flight.abort_all();
+    if cancelled > 0 {
+        tracing::info!(cancelled, "Client gone. Cancelled its requests.");
     }
 }
 
-async fn spawn(fut: impl Future<Output = ()> + Send + 'static) {
-    tokio::spawn(fut);
+fn next_connection(
+    bar_ctl_srv: &BarCtlServer,
+    peer: Peer,
+) -> (BarCtlServer, Span) {
+    let connection_id = NEXT_CONNECTION_ID.fetch_add(1, Ordering::Relaxed);
+    let span = info_span!(
+        "connection",
+        id = connection_id,
+        peer_pid = peer.pid,
+        peer = peer.hash()
+    );
+    let bar_ctl_srv = BarCtlServer {
+        connection_id,
+        peer_pid: peer.pid,
+        ..bar_ctl_srv.clone()
+    };
+    (bar_ctl_srv, span)
+}
+
+async fn spawn_local(fut: impl Future<Output = ()> + 'static) {
+    tokio::task::spawn_local(fut);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::SocketAddr, os::unix::fs::FileTypeExt, path::Path,
+        time::Duration,
+    };
+
+    use tokio::task::JoinSet;
+
+    use crate::{
+        bar,
+        conf::{self, Conf},
+        control::{
+            self,
+            client::{connect, connect_with, Client},
+        },
+        tracing::LogCapture,
+    };
+
+    #[tokio::test]
+    async fn peer() {
+        let (conn, _other_end) = tokio::net::UnixStream::pair().unwrap();
+        let peer = super::Peer::of_unix(&conn);
+        assert_eq!(Some(std::process::id()), peer.pid);
+        assert!(peer.uid.is_some());
+        assert!(peer.gid.is_some());
+
+        let hash = peer.hash().unwrap();
+        assert_eq!(16, hash.len());
+        assert_eq!(Some(&hash), super::Peer::of_unix(&conn).hash().as_ref());
+        assert_ne!(Some(&hash), super::Peer::default().hash().as_ref());
+
+        let logs = LogCapture::default();
+        let subscriber = {
+            let logs = logs.clone();
+            tracing_subscriber::fmt()
+                .with_max_level(tracing::Level::DEBUG)
+                .with_ansi(false)
+                .with_writer(move || logs.clone())
+                .finish()
+        };
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        let dir = crate::tmp_path("control_peer");
+        tokio::fs::create_dir_all(&dir).await.unwrap();
+        let mut siblings = start_test_server(&dir, false, None);
+        let client = connect(&dir).await;
+        client.ping().await.unwrap();
+        siblings.abort_all();
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+
+        let logs = String::from_utf8(logs.0.lock().unwrap().clone()).unwrap();
+        let accepted = logs
+            .lines()
+            .find(|line| line.contains("Accepted"))
+            .unwrap_or_else(|| panic!("{}", logs));
+        assert!(accepted.contains(&format!("peer=\"{}\"", hash)), "{}", logs);
+        let ping = logs
+            .lines()
+            .find(|line| line.contains("Received ping req."))
+            .unwrap_or_else(|| panic!("{}", logs));
+        assert!(
+            ping.contains(&format!("peer_pid={}", std::process::id())),
+            "{}",
+            logs
+        );
+        assert!(!logs.contains("uid"), "{}", logs);
+        assert!(!logs.contains("peer_gid"), "{}", logs);
+    }
+
+    fn test_conf(dir: &Path) -> Conf {
+        let mut conf = Conf::default();
+        conf.dst = Some(conf::Dst::File {
+            path: dir.join("bar"),
+        });
+        conf.feeds.truncate(1);
+        conf.feeds[0].cmd = conf::FeedCmd::Cmd("sleep 10".to_string());
+        conf
+    }
+
+    const MAX_MESSAGE_BYTES: usize = conf::DEFAULT_MAX_RPC_MESSAGE_BYTES;
+
+    fn start_test_server(
+        dir: &Path,
+        abstract_socket: bool,
+        tcp_bind: Option<SocketAddr>,
+    ) -> JoinSet<anyhow::Result<()>> {
+        let mut siblings = JoinSet::new();
+        let bar_tx = bar::server::start(&mut siblings, dir, test_conf(dir));
+        siblings.spawn(super::run(
+            dir.to_path_buf(),
+            8,
+            abstract_socket,
+            tcp_bind,
+            MAX_MESSAGE_BYTES,
+            bar_tx,
+        ));
+        siblings
+    }
+
+    #[tokio::test]
+    async fn tcp() {
+        let dir = crate::tmp_path("control_tcp");
+        tokio::fs::create_dir_all(&dir).await.unwrap();
+        let addr = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let mut siblings = start_test_server(&dir, false, Some(addr));
+
+        let timeout = Duration::from_secs(5);
+        let client = connect_with(|| {
+            Client::new_tcp(addr, timeout, MAX_MESSAGE_BYTES)
+        })
+        .await;
+        client.on().await.unwrap();
+        client
+            .status(
+                bar::status::Format::Tsv,
+                false,
+                Some(&dir.join("status")),
+            )
+            .await
+            .unwrap();
+        let status =
+            tokio::fs::read_to_string(dir.join("status")).await.unwrap();
+        assert!(status.starts_with("POSITION\t"));
+        assert_eq!(2, status.lines().count());
+        client.off().await.unwrap();
+
+        siblings.abort_all();
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn status_when_bar_gone() {
+        let dir = crate::tmp_path("control_status_when_bar_gone");
+        tokio::fs::create_dir_all(&dir).await.unwrap();
+        let (bar_tx, _) = tokio::sync::mpsc::unbounded_channel();
+        let mut siblings = JoinSet::new();
+        siblings.spawn(super::run(
+            dir.to_path_buf(),
+            8,
+            false,
+            None,
+            MAX_MESSAGE_BYTES,
+            bar_tx,
+        ));
+
+        let client = connect(&dir).await;
+        // Answered, so neither down nor unreachable.
+        let file = dir.join("status");
+        client
+            .status(bar::status::Format::Table, false, Some(&file))
+            .await
+            .unwrap();
+        assert_eq!(
+            "failed\nerror: Bar server is not running",
+            tokio::fs::read_to_string(&file).await.unwrap().trim_end()
+        );
+
+        siblings.abort_all();
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn abstract_socket() {
+        let dir = crate::tmp_path("control_abstract_socket");
+        tokio::fs::create_dir_all(&dir).await.unwrap();
+        let mut siblings = start_test_server(&dir, true, None);
+
+        let timeout = Duration::from_secs(5);
+        let client = connect_with(|| {
+            Client::new(&dir, true, timeout, MAX_MESSAGE_BYTES)
+        })
+        .await;
+        client.on().await.unwrap();
+        client.off().await.unwrap();
+        assert!(!conf::path_server_sock(&dir, false).exists());
+        let mut entries = tokio::fs::read_dir(&dir).await.unwrap();
+        while let Some(entry) = entries.next_entry().await.unwrap() {
+            assert!(!entry.file_type().await.unwrap().is_socket());
+        }
+        // Not reachable as a file.
+        assert!(Client::new(&dir, false, timeout, MAX_MESSAGE_BYTES)
+            .await
+            .is_err());
+
+        siblings.abort_all();
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn oversized_message() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let dir = crate::tmp_path("control_oversized_message");
+        tokio::fs::create_dir_all(&dir).await.unwrap();
+        let mut siblings = start_test_server(&dir, false, None);
+        let sock_file = conf::path_server_sock(&dir, false);
+        let mut conn = loop {
+            match tokio::net::UnixStream::connect(&sock_file).await {
+                Ok(conn) => break conn,
+                Err(_) => tokio::time::sleep(Duration::from_millis(10)).await,
+            }
+        };
+
+        // Only the length prefix is needed for the frame to be refused.
+        let len = u32::try_from(MAX_MESSAGE_BYTES + 1).unwrap();
+        conn.write_all(&len.to_be_bytes()).await.unwrap();
+        conn.write_all(&[0; 1024]).await.unwrap();
+        let mut buf = Vec::new();
+        let read = tokio::time::timeout(
+            Duration::from_secs(5),
+            conn.read_to_end(&mut buf),
+        )
+        .await
+        .unwrap();
+        assert!(!matches!(read, Ok(n) if n > 0));
+
+        // Server is still up for everyone else.
+        let timeout = Duration::from_secs(5);
+        let client = Client::new(&dir, false, timeout, MAX_MESSAGE_BYTES)
+            .await
+            .unwrap();
+        client.on().await.unwrap();
+        client.off().await.unwrap();
+
+        siblings.abort_all();
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn connection_ids() {
+        let logs = LogCapture::default();
+        let subscriber = {
+            let logs = logs.clone();
+            tracing_subscriber::fmt()
+                .with_max_level(tracing::Level::DEBUG)
+                .with_ansi(false)
+                .with_writer(move || logs.clone())
+                .finish()
+        };
+        // Current-thread runtime, so this covers all spawned tasks too.
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        let dir = crate::tmp_path("control_connection_ids");
+        tokio::fs::create_dir_all(&dir).await.unwrap();
+        let mut siblings = start_test_server(&dir, false, None);
+        let (client_a, client_b) = tokio::join!(connect(&dir), connect(&dir));
+        let out = dir.join("status");
+        let format = bar::status::Format::Tsv;
+        let (a, b) = tokio::join!(
+            client_a.status(format, false, Some(&out)),
+            client_b.status(format, false, Some(&out))
+        );
+        a.unwrap();
+        b.unwrap();
+        siblings.abort_all();
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+
+        let logs = String::from_utf8(logs.0.lock().unwrap().clone()).unwrap();
+        let mut ids: Vec<&str> = logs
+            .lines()
+            .filter(|line| line.contains("Received status req."))
+            .map(|line| {
+                let (_, rest) = line.split_once("connection_id=").unwrap();
+                rest.split(|c: char| !c.is_ascii_digit()).next().unwrap()
+            })
+            .collect();
+        assert_eq!(2, ids.len(), "{}", logs);
+        ids.dedup();
+        assert_eq!(2, ids.len(), "{}", logs);
+    }
+
+    #[tokio::test]
+    async fn get_conf() {
+        let dir = crate::tmp_path("control_get_conf");
+        tokio::fs::create_dir_all(&dir).await.unwrap();
+        let mut siblings = start_test_server(&dir, false, None);
+        let client = connect(&dir).await;
+
+        // Changed, but not reloaded.
+        tokio::fs::write(conf::path_conf(&dir), Conf::default_toml_str())
+            .await
+            .unwrap();
+        let conf = client.get_conf().await.unwrap();
+        assert_eq!(1, conf.feeds.len());
+        assert_eq!(
+            conf::FeedCmd::Cmd("sleep 10".to_string()),
+            conf.feeds[0].cmd
+        );
+        assert!(matches!(
+            conf.dst,
+            Some(conf::Dst::File { path }) if path == dir.join("bar")
+        ));
+
+        siblings.abort_all();
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn get_feed_conf() {
+        let dir = crate::tmp_path("control_get_feed_conf");
+        tokio::fs::create_dir_all(&dir).await.unwrap();
+        let mut conf = test_conf(&dir);
+        conf.feeds[0].name = "b".to_string();
+        conf.feeds[0].ttl = Some(2.5);
+        conf.feeds[0].max_log_lines = Some(7);
+        conf.feeds[0].output_prefix = Some("> ".to_string());
+        let expected = conf.feeds[0].clone();
+        let mut siblings = JoinSet::new();
+        let bar_tx = bar::server::start(&mut siblings, &dir, conf);
+        siblings.spawn(super::run(
+            dir.clone(),
+            8,
+            false,
+            None,
+            MAX_MESSAGE_BYTES,
+            bar_tx,
+        ));
+        let client = connect(&dir).await;
+
+        assert_eq!(expected, client.get_feed_conf(0).await.unwrap());
+        let error = client.get_feed_conf(1).await.unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<control::ControlError>(),
+            Some(control::ControlError::FeedNotFound { pos: 1 })
+        ));
+
+        siblings.abort_all();
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn local() {
+        let dir = crate::tmp_path("control_local");
+        tokio::fs::create_dir_all(&dir).await.unwrap();
+        let mut siblings = JoinSet::new();
+        let bar_tx = bar::server::start(&mut siblings, &dir, test_conf(&dir));
+        let server = super::run_local(
+            dir.clone(),
+            8,
+            false,
+            None,
+            MAX_MESSAGE_BYTES,
+            bar_tx,
+        );
+        let clients = async {
+            let clients =
+                tokio::join!(connect(&dir), connect(&dir), connect(&dir));
+            let format = bar::status::Format::Table;
+            let outs = [0, 1, 2].map(|i| dir.join(format!("status-{}", i)));
+            let results = tokio::join!(
+                clients.0.status(format, false, Some(&outs[0])),
+                clients.1.status(format, false, Some(&outs[1])),
+                clients.2.status(format, false, Some(&outs[2])),
+            );
+            results.0.unwrap();
+            results.1.unwrap();
+            results.2.unwrap();
+            for out in &outs {
+                let status = tokio::fs::read_to_string(out).await.unwrap();
+                assert!(status.trim_end().ends_with("connections: 3"));
+            }
+        };
+        tokio::select! {
+            result = server => unreachable!("Server exited: {:?}", result),
+            () = clients => {}
+        }
+
+        siblings.abort_all();
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn reload_timeout() {
+        let dir = crate::tmp_path("control_reload_timeout");
+        tokio::fs::create_dir_all(&dir).await.unwrap();
+        let mut conf = test_conf(&dir);
+        // Slow to stop, since the stdout it shares with the escaped sleep
+        // stays open until that one exits.
+        conf.feeds[0].cmd = conf::FeedCmd::Cmd(
+            "setsid sleep 2 & echo started; sleep 10".to_string(),
+        );
+        conf.output_interval = 0.05;
+        // For reconf. Differs, so that the reload restarts the feed.
+        let mut reconf = conf.clone();
+        reconf.feeds[0].cmd = conf::FeedCmd::Cmd(
+            "setsid sleep 2 & echo started; sleep 9".to_string(),
+        );
+        tokio::fs::write(
+            conf::path_conf(&dir),
+            toml::to_string(&reconf).unwrap(),
+        )
+        .await
+        .unwrap();
+        let mut siblings = JoinSet::new();
+        let bar_tx = bar::server::start(&mut siblings, &dir, conf);
+        siblings.spawn(super::run(
+            dir.clone(),
+            8,
+            false,
+            None,
+            MAX_MESSAGE_BYTES,
+            bar_tx,
+        ));
+        let client = connect(&dir).await;
+        client.on().await.unwrap();
+        // The escaped sleep is running by the time this is output.
+        while tokio::fs::read_to_string(dir.join("bar"))
+            .await
+            .map_or(true, |bar| !bar.contains("started"))
+        {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        let result = client.reload(Some(Duration::from_millis(200))).await;
+        assert!(result.is_err());
+
+        // Back on, eventually.
+        let out = dir.join("status");
+        let status = loop {
+            client
+                .status(bar::status::Format::Tsv, false, Some(&out))
+                .await
+                .unwrap();
+            let status = tokio::fs::read_to_string(&out).await.unwrap();
+            if status.starts_with("POSITION\t") {
+                break status;
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        };
+        assert_eq!(2, status.lines().count());
+        client.off().await.unwrap();
+
+        siblings.abort_all();
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn concurrent_reloads() {
+        let dir = crate::tmp_path("control_concurrent_reloads");
+        tokio::fs::create_dir_all(&dir).await.unwrap();
+        let mut reconf = test_conf(&dir);
+        reconf.feeds[0].cmd = conf::FeedCmd::Cmd("sleep 9".to_string());
+        tokio::fs::write(
+            conf::path_conf(&dir),
+            toml::to_string(&reconf).unwrap(),
+        )
+        .await
+        .unwrap();
+        let mut siblings = start_test_server(&dir, false, None);
+        let client = connect(&dir).await;
+        let other = connect(&dir).await;
+        client.on().await.unwrap();
+
+        // The second waits for the first, rather than failing on it.
+        let (a, b) = tokio::join!(client.reload(None), other.reload(None));
+        a.unwrap();
+        b.unwrap();
+        let conf = client.get_conf().await.unwrap();
+        assert_eq!(reconf.feeds[0].cmd, conf.feeds[0].cmd);
+        client.off().await.unwrap();
+
+        siblings.abort_all();
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn client_gone_mid_request() {
+        let logs = LogCapture::default();
+        let subscriber = {
+            let logs = logs.clone();
+            tracing_subscriber::fmt()
+                .with_max_level(tracing::Level::DEBUG)
+                .with_ansi(false)
+                .with_writer(move || logs.clone())
+                .finish()
+        };
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        let dir = crate::tmp_path("control_client_gone_mid_request");
+        tokio::fs::create_dir_all(&dir).await.unwrap();
+        let mut conf = test_conf(&dir);
+        // Slow to stop, as in reload_timeout.
+        conf.feeds[0].cmd = conf::FeedCmd::Cmd(
+            "setsid sleep 2 & echo started; sleep 10".to_string(),
+        );
+        conf.output_interval = 0.05;
+        // Differs, so that the reload restarts the feed.
+        let mut reconf = conf.clone();
+        reconf.feeds[0].cmd = conf::FeedCmd::Cmd(
+            "setsid sleep 2 & echo started; sleep 9".to_string(),
+        );
+        tokio::fs::write(
+            conf::path_conf(&dir),
+            toml::to_string(&reconf).unwrap(),
+        )
+        .await
+        .unwrap();
+        let mut siblings = JoinSet::new();
+        let bar_tx = bar::server::start(&mut siblings, &dir, conf);
+        siblings.spawn(super::run(
+            dir.clone(),
+            8,
+            false,
+            None,
+            MAX_MESSAGE_BYTES,
+            bar_tx,
+        ));
+        let timeout = Duration::from_secs(30);
+        let client = connect_with(|| {
+            Client::new(&dir, false, timeout, MAX_MESSAGE_BYTES)
+        })
+        .await;
+        client.on().await.unwrap();
+        while tokio::fs::read_to_string(dir.join("bar"))
+            .await
+            .map_or(true, |bar| !bar.contains("started"))
+        {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        let out = dir.join("status");
+        let feed_pid = || async {
+            client
+                .status(bar::status::Format::Tsv, false, Some(&out))
+                .await
+                .unwrap();
+            let status = tokio::fs::read_to_string(&out).await.unwrap();
+            let mut rows = status.lines().map(|row| row.split('\t'));
+            let column = rows.next()?.position(|name| name == "PID")?;
+            let pid = rows.next()?.nth(column)?.to_string();
+            (pid != "-").then_some(pid)
+        };
+        let pid_before = feed_pid().await.unwrap();
+
+        // As if killed while waiting, so without a chance to tell the
+        // server to cancel, which is what the client does when it merely
+        // drops the request.
+        let (client_end, mut proxy_end) =
+            tokio::net::UnixStream::pair().unwrap();
+        let mut server_end = tokio::net::UnixStream::connect(
+            conf::path_server_sock(&dir, false),
+        )
+        .await
+        .unwrap();
+        let proxy = tokio::spawn(async move {
+            tokio::io::copy_bidirectional(&mut proxy_end, &mut server_end)
+                .await
+        });
+        let doomed =
+            Client::from_conn(client_end, timeout, MAX_MESSAGE_BYTES)
+                .unwrap();
+        let reload = tokio::spawn(async move { doomed.reload(None).await });
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        proxy.abort();
+        assert!(proxy.await.unwrap_err().is_cancelled());
+
+        // Gone well before the reload is done.
+        loop {
+            client
+                .status(bar::status::Format::Table, false, Some(&out))
+                .await
+                .unwrap();
+            let status = tokio::fs::read_to_string(&out).await.unwrap();
+            if status.trim_end().ends_with("connections: 1") {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        let logs = String::from_utf8(logs.0.lock().unwrap().clone()).unwrap();
+        assert!(logs.contains("Client gone. Cancelled its requests."));
+        assert!(logs.contains("cancelled=1"));
+
+        // The reload itself still goes through.
+        loop {
+            match feed_pid().await {
+                Some(pid) if pid != pid_before => break,
+                _ => tokio::time::sleep(Duration::from_millis(50)).await,
+            }
+        }
+        assert!(reload.await.unwrap().is_err());
+        client.off().await.unwrap();
+
+        siblings.abort_all();
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
 }
@@ -0,0 +1,176 @@
+//! Running the bar from within another program, without the CLI or the
+//! control server, e.g. to show it in a widget of its own.
+
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Context};
+use tokio::task::JoinSet;
+
+use crate::{
+    bar::{
+        self,
+        server::{ApiResult, ApiSender, OnUpdate},
+    },
+    conf::{self, Conf},
+};
+
+#[derive(Default)]
+pub struct BarBuilder {
+    conf: Option<Conf>,
+    dir: Option<PathBuf>,
+    on_update: Option<OnUpdate>,
+}
+
+impl BarBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Required.
+    pub fn with_conf(mut self, conf: Conf) -> Self {
+        self.conf = Some(conf);
+        self
+    }
+
+    /// Required. Where the feeds' files go, along with the config, which
+    /// is written there for reload to re-read.
+    pub fn with_dir(mut self, dir: PathBuf) -> Self {
+        self.dir = Some(dir);
+        self
+    }
+
+    /// Called with the bar every time it changes, instead of it being
+    /// written to the config's dst. Runs on the bar server's loop, which
+    /// waits for it, so it should be quick, e.g. just send the bar on to
+    /// a channel.
+    pub fn with_on_update(mut self, on_update: OnUpdate) -> Self {
+        self.on_update = Some(on_update);
+        self
+    }
+
+    /// Starts the bar, off. Must be called from within a tokio runtime.
+    pub fn build(self) -> anyhow::Result<BarHandle> {
+        let conf = self.conf.ok_or_else(|| anyhow!("Missing conf"))?;
+        conf.validate()?;
+        let dir = self.dir.ok_or_else(|| anyhow!("Missing dir"))?;
+        std::fs::create_dir_all(&dir)
+            .context(format!("Failed to create directory: {:?}", &dir))?;
+        let conf_file = conf::path_conf(&dir);
+        std::fs::write(&conf_file, toml::to_string_pretty(&conf)?)
+            .context(format!("Failed to write conf: {:?}", &conf_file))?;
+        let mut tasks = JoinSet::new();
+        let tx =
+            bar::server::start_with(&mut tasks, &dir, conf, self.on_update);
+        Ok(BarHandle {
+            tx,
+            dir,
+            _tasks: tasks,
+        })
+    }
+}
+
+/// Dropping the handle stops the bar and kills its feeds, which turning it
+/// off first would instead stop as configured, e.g. with on_stop.
+pub struct BarHandle {
+    tx: ApiSender,
+    dir: PathBuf,
+    /// Holds the bar server, which is aborted when dropped.
+    _tasks: JoinSet<anyhow::Result<()>>,
+}
+
+impl BarHandle {
+    pub async fn on(&self) -> ApiResult<()> {
+        bar::server::on(&self.tx).await
+    }
+
+    pub async fn off(&self) -> ApiResult<()> {
+        bar::server::off(&self.tx).await
+    }
+
+    /// Re-reads the config from the directory.
+    pub async fn reload(&self) -> ApiResult<()> {
+        bar::server::reload(&self.tx).await
+    }
+
+    pub async fn status(&self) -> ApiResult<bar::status::Status> {
+        bar::server::status(&self.tx).await
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::BarBuilder;
+    use crate::{bar::status::Status, conf};
+
+    #[tokio::test]
+    async fn on_update() {
+        let mut conf = conf::Conf::default();
+        conf.feeds.truncate(1);
+        conf.feeds[0].cmd =
+            conf::FeedCmd::Cmd("echo hello; sleep 10".to_string());
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        let bar = BarBuilder::new()
+            .with_conf(conf.clone())
+            .with_dir(crate::tmp_path("embed_on_update"))
+            .with_on_update(Box::new(move |data| {
+                let _ = tx.send(data.to_string());
+            }))
+            .build()
+            .unwrap();
+        assert!(BarBuilder::new().build().is_err());
+        assert!(BarBuilder::new().with_conf(conf).build().is_err());
+
+        bar.on().await.unwrap();
+        let timeout = Duration::from_secs(5);
+        loop {
+            let update = tokio::time::timeout(timeout, rx.recv()).await;
+            if update.unwrap().unwrap().contains("hello") {
+                break;
+            }
+        }
+        assert!(matches!(bar.status().await.unwrap(), Status::UpOn { .. }));
+
+        bar.off().await.unwrap();
+        tokio::fs::remove_dir_all(bar.dir()).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn drop_kills_feeds() {
+        let mut conf = conf::Conf::default();
+        conf.feeds.truncate(1);
+        conf.feeds[0].cmd = conf::FeedCmd::Cmd("sleep 10".to_string());
+        let name = conf.feeds[0].name.clone();
+        let dir = crate::tmp_path("embed_drop_kills_feeds");
+        let bar = BarBuilder::new()
+            .with_conf(conf)
+            .with_dir(dir.clone())
+            .build()
+            .unwrap();
+        bar.on().await.unwrap();
+        let feed_dir =
+            conf::path_feed_dir(&conf::path_feeds_dir(&dir, None), 0, &name);
+        let feed_pid: i32 =
+            tokio::fs::read_to_string(conf::path_feed_pid(&feed_dir))
+                .await
+                .unwrap()
+                .parse()
+                .unwrap();
+        let feed_pid = nix::unistd::Pid::from_raw(feed_pid);
+
+        drop(bar);
+        let mut retries = 50;
+        while nix::sys::signal::kill(feed_pid, None).is_ok() {
+            assert!(retries > 0, "Feed still alive: {}", feed_pid);
+            retries -= 1;
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+}
@@ -1,17 +1,272 @@
-use std::{path::Path, time::SystemTime};
+use std::{
+    io,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
 
-use tokio::fs;
+use anyhow::Context;
+use tokio::{fs, sync::mpsc, task::JoinHandle};
 
-pub async fn size_in_bytes<P: AsRef<Path>>(path: P) -> anyhow::Result<u64> {
-    let path = path.as_ref();
-    let meta = fs::metadata(path).await?;
-    let size = meta.len();
-    Ok(size)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FileStats {
+    pub mtime: SystemTime,
+    pub size_bytes: u64,
+    /// When false, mtime is the epoch and size_bytes is 0.
+    pub exists: bool,
 }
 
-pub async fn mtime<P: AsRef<Path>>(path: P) -> anyhow::Result<SystemTime> {
+/// Size and mtime from a single stat. A missing file, such as a log not
+/// yet written to, is not an error.
+pub async fn file_stats<P: AsRef<Path>>(
+    path: P,
+) -> anyhow::Result<FileStats> {
     let path = path.as_ref();
-    let meta = fs::metadata(path).await?;
-    let mtime = meta.modified()?;
-    Ok(mtime)
+    let meta = match fs::metadata(path).await {
+        Ok(meta) => meta,
+        Err(error) if error.kind() == io::ErrorKind::NotFound => {
+            return Ok(FileStats {
+                mtime: SystemTime::UNIX_EPOCH,
+                size_bytes: 0,
+                exists: false,
+            });
+        }
+        Err(error) => {
+            return Err(error)
+                .context(format!("Failed to stat file: {:?}", path));
+        }
+    };
+    Ok(FileStats {
+        mtime: meta.modified()?,
+        size_bytes: meta.len(),
+        exists: true,
+    })
+}
+
+/// Total size of the files under the directory, recursively. Symlinks are
+/// not followed and entries removed while walking are skipped.
+pub async fn dir_size<P: AsRef<Path>>(path: P) -> anyhow::Result<u64> {
+    let mut total = 0;
+    let mut dirs = vec![path.as_ref().to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        let mut entries = fs::read_dir(&dir).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let meta = match fs::symlink_metadata(entry.path()).await {
+                Ok(meta) => meta,
+                Err(error) if error.kind() == io::ErrorKind::NotFound => {
+                    continue;
+                }
+                Err(error) => return Err(error.into()),
+            };
+            if meta.is_dir() {
+                dirs.push(entry.path());
+            } else {
+                total += meta.len();
+            }
+        }
+    }
+    Ok(total)
+}
+
+/// How watch_file notices changes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchMode {
+    #[cfg(target_os = "linux")]
+    Inotify,
+    /// Compares mtime and size at every interval.
+    Poll { interval: Duration },
+}
+
+impl WatchMode {
+    #[cfg(target_os = "linux")]
+    pub const DEFAULT: Self = Self::Inotify;
+    #[cfg(not(target_os = "linux"))]
+    pub const DEFAULT: Self = Self::Poll {
+        interval: Self::POLL_INTERVAL,
+    };
+
+    pub const POLL_INTERVAL: Duration = Duration::from_secs(1);
+}
+
+/// Sends a message for every change of the file, until the returned task
+/// is aborted or the receiver is dropped. Changes made before the receiver
+/// got around to the last message are merged into it. The file itself,
+/// rather than its path, is watched, so one replaced by a rename, as some
+/// editors save, is no longer watched.
+pub fn watch_file(
+    path: &Path,
+    tx: mpsc::Sender<()>,
+) -> anyhow::Result<JoinHandle<()>> {
+    watch_file_with(path, tx, WatchMode::DEFAULT)
+}
+
+pub fn watch_file_with(
+    path: &Path,
+    tx: mpsc::Sender<()>,
+    mode: WatchMode,
+) -> anyhow::Result<JoinHandle<()>> {
+    let path = path.to_path_buf();
+    std::fs::metadata(&path)
+        .context(format!("Failed to access file: {:?}", &path))?;
+    let interval = match mode {
+        #[cfg(target_os = "linux")]
+        WatchMode::Inotify => match inotify_events(&path) {
+            Ok(events) => {
+                return Ok(tokio::spawn(watch_inotify(events, tx)));
+            }
+            Err(error) => {
+                tracing::warn!(
+                    ?path,
+                    ?error,
+                    "inotify unavailable. Polling instead."
+                );
+                WatchMode::POLL_INTERVAL
+            }
+        },
+        WatchMode::Poll { interval } => interval,
+    };
+    Ok(tokio::spawn(watch_poll(path, interval, tx)))
+}
+
+/// Whether to keep watching.
+fn notify(tx: &mpsc::Sender<()>) -> bool {
+    match tx.try_send(()) {
+        Ok(()) | Err(mpsc::error::TrySendError::Full(())) => true,
+        Err(mpsc::error::TrySendError::Closed(())) => false,
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn inotify_events(
+    path: &Path,
+) -> io::Result<inotify::EventStream<[u8; 1024]>> {
+    use inotify::{Inotify, WatchMask};
+
+    let inotify = Inotify::init()?;
+    inotify
+        .watches()
+        .add(path, WatchMask::MODIFY | WatchMask::CLOSE_WRITE)?;
+    inotify.into_event_stream([0; 1024])
+}
+
+#[cfg(target_os = "linux")]
+async fn watch_inotify(
+    mut events: inotify::EventStream<[u8; 1024]>,
+    tx: mpsc::Sender<()>,
+) {
+    use futures_util::StreamExt;
+
+    while let Some(event) = events.next().await {
+        if let Err(error) = event {
+            tracing::error!(?error, "Failed to read inotify event.");
+            return;
+        }
+        if !notify(&tx) {
+            return;
+        }
+    }
+}
+
+async fn watch_poll(path: PathBuf, interval: Duration, tx: mpsc::Sender<()>) {
+    let stamp = |path: PathBuf| async move {
+        let meta = fs::metadata(path).await.ok()?;
+        Some((meta.modified().ok()?, meta.len()))
+    };
+    let mut interval = tokio::time::interval(interval);
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    let mut prev = stamp(path.clone()).await;
+    loop {
+        interval.tick().await;
+        let curr = stamp(path.clone()).await;
+        if curr != prev && !notify(&tx) {
+            return;
+        }
+        prev = curr;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tokio::sync::mpsc;
+
+    use super::WatchMode;
+
+    #[tokio::test]
+    async fn file_stats() {
+        let file = crate::tmp_path("fs_file_stats");
+        let stats = super::file_stats(&file).await.unwrap();
+        assert!(!stats.exists);
+        assert_eq!(0, stats.size_bytes);
+
+        tokio::fs::write(&file, [0; 1000]).await.unwrap();
+        let meta = std::fs::metadata(&file).unwrap();
+        let stats = super::file_stats(&file).await.unwrap();
+        assert!(stats.exists);
+        assert_eq!(1000, stats.size_bytes);
+        assert_eq!(meta.modified().unwrap(), stats.mtime);
+
+        tokio::fs::remove_file(&file).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn dir_size() {
+        let dir = crate::tmp_path("fs_dir_size");
+        let sub = dir.join("hooks");
+        tokio::fs::create_dir_all(&sub).await.unwrap();
+        assert_eq!(0, super::dir_size(&dir).await.unwrap());
+
+        tokio::fs::write(dir.join("log"), [0; 1000]).await.unwrap();
+        tokio::fs::write(dir.join("pid"), "12345").await.unwrap();
+        tokio::fs::write(sub.join("on_exit"), [0; 20])
+            .await
+            .unwrap();
+        std::os::unix::fs::symlink("/etc/passwd", dir.join("link")).unwrap();
+        let link_size = tokio::fs::symlink_metadata(dir.join("link"))
+            .await
+            .unwrap()
+            .len();
+        assert_eq!(
+            1000 + 5 + 20 + link_size,
+            super::dir_size(&dir).await.unwrap()
+        );
+        assert!(super::dir_size(dir.join("log")).await.is_err());
+
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+
+    async fn watch_file(name: &str, mode: WatchMode) {
+        let file = crate::tmp_path(&format!("fs_{}", name));
+        tokio::fs::write(&file, "a").await.unwrap();
+        let (tx, mut rx) = mpsc::channel(1);
+        let watcher = super::watch_file_with(&file, tx, mode).unwrap();
+        // Let the poller take its first look.
+        tokio::time::sleep(Duration::from_millis(20)).await;
+
+        tokio::fs::write(&file, "bb").await.unwrap();
+        tokio::time::timeout(Duration::from_millis(100), rx.recv())
+            .await
+            .unwrap()
+            .unwrap();
+
+        watcher.abort();
+        assert!(watcher.await.unwrap_err().is_cancelled());
+        assert_eq!(None, rx.recv().await);
+
+        tokio::fs::remove_file(&file).await.unwrap();
+        let (tx, _) = mpsc::channel(1);
+        assert!(super::watch_file_with(&file, tx, mode).is_err());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn watch_file_inotify() {
+        watch_file("watch_file_inotify", WatchMode::Inotify).await;
+    }
+
+    #[tokio::test]
+    async fn watch_file_poll() {
+        let interval = Duration::from_millis(10);
+        watch_file("watch_file_poll", WatchMode::Poll { interval }).await;
+    }
 }
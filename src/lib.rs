@@ -1,14 +1,30 @@
 pub mod bar;
+pub mod circuit_breaker;
 pub mod conf;
 pub mod control;
+pub mod embed;
 pub mod fs;
 pub mod ps;
 pub mod tracing;
 pub mod x11;
 
+pub use embed::{BarBuilder, BarHandle};
+
 #[macro_export]
 macro_rules! NAME {
     () => {
         env!("CARGO_CRATE_NAME")
     };
 }
+
+/// Unique to the test process, for a test to keep its files under. Public
+/// only for the binary's tests.
+#[doc(hidden)]
+pub fn tmp_path(name: &str) -> std::path::PathBuf {
+    std::env::temp_dir().join(format!(
+        "{}-test-{}-{}",
+        crate::NAME!(),
+        std::process::id(),
+        name
+    ))
+}
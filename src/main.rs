@@ -1,7 +1,13 @@
-use std::{path::Path, time::Duration};
+use std::{
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    time::Duration,
+};
 
 use anyhow::{anyhow, bail, Context};
-use barista::conf;
+use barista::{
+    bar::status::Format, conf, control::ControlError, tracing::Rotation,
+};
 use clap::Parser;
 
 use tokio::{fs, task::JoinSet};
@@ -11,7 +17,11 @@ use tracing::Instrument;
 #[command(version, about)]
 struct Cli {
     /// Path to the working directory.
-    #[clap(long, default_value = concat!("~/.", barista::NAME!()))]
+    #[clap(
+        long,
+        default_value = concat!("~/.", barista::NAME!()),
+        env = conf::BARISTA_DIR_ENV_VAR
+    )]
     dir: String,
 
     /// Enables RPC logging. Sets level to DEBUG.
@@ -22,9 +32,26 @@ struct Cli {
     #[clap(short, long = "log")]
     log_level: Option<tracing::Level>,
 
+    /// Also write JSON-formatted logs to this file.
+    #[clap(long)]
+    log_file: Option<String>,
+
+    /// How often to rotate the log file, if any.
+    #[clap(long, value_enum, default_value_t = Rotation::Never)]
+    log_rotation: Rotation,
+
+    /// Comma-separated names of spans to drop log events from.
+    #[clap(long, value_delimiter = ',')]
+    quiet_spans: Vec<String>,
+
     #[clap(short, long, default_value_t = 5.0)]
     timeout: f64,
 
+    /// Connect to the server over TCP at this address, instead of over the
+    /// Unix socket in the working directory.
+    #[clap(long)]
+    tcp: Option<SocketAddr>,
+
     #[clap(subcommand)]
     cmd: Cmd,
 }
@@ -33,14 +60,36 @@ struct Cli {
 enum Cmd {
     /// Run the server.
     Server {
-        #[clap(long, default_value_t = 1024)]
-        backlog: u32,
+        /// Server socket backlog. Takes precedence over `socket_backlog`
+        /// in the config file. Defaults to 1024 if neither is set.
+        #[clap(long)]
+        backlog: Option<u32>,
+
+        /// Turn-on the feeds immediately after start. The default, so
+        /// only needed to override an earlier `--no-start-on`.
+        #[clap(long, overrides_with = "no_start_on")]
+        start_on: bool,
+
+        /// Do not turn-on the feeds immediately after start, but wait for
+        /// an explicit `on` request instead.
+        #[clap(long, alias = "no-on", overrides_with = "start_on")]
+        no_start_on: bool,
 
-        /// Turn-on the feeds immediately after start.
-        #[clap(long, default_value_t = true)]
-        on: bool,
+        /// Where to keep the feed directories. Takes precedence over
+        /// `feeds_dir` in the config file. Defaults to "feeds" in the
+        /// working directory.
+        #[clap(long)]
+        feeds_dir: Option<PathBuf>,
     },
 
+    /// Check the config file for errors, without involving the server.
+    Validate,
+
+    /// Kill feed processes left behind by a server which did not shut down
+    /// cleanly, as found in feed PID files. Only while no server is running.
+    /// Looks in the feeds directory the last server used.
+    KillOrphans,
+
     /// Ask the server to turn-on the bar feeds.
     On,
 
@@ -62,23 +111,139 @@ enum Cmd {
     //
     /// Ask the server for its current status.
     Status {
-        /// Machine-friendly output - i.e. no spaces in table cells.
+        /// Machine-friendly output. Same as `--format tsv`.
         #[clap(short, long, default_value_t = false)]
         machine: bool,
+
+        /// Output format.
+        #[clap(short, long, value_enum, default_value_t = Format::Table)]
+        format: Format,
+
+        /// Write to this file instead of stdout. "-" means stdout.
+        #[clap(short, long)]
+        output: Option<PathBuf>,
+
+        /// Color process states in the table. By default only when
+        /// writing to a terminal.
+        #[clap(long, conflicts_with = "no_color")]
+        color: bool,
+
+        /// Never color.
+        #[clap(long)]
+        no_color: bool,
+    },
+
+    /// Ask the server to clear the log file of a specified feed.
+    ClearLog {
+        /// Feed position, as shown in status output (starting from 1).
+        #[clap(short, long)]
+        pos: usize,
+    },
+
+    /// Ask the server to search the log file of a specified feed.
+    SearchLog {
+        /// Feed position, as shown in status output (starting from 1).
+        #[clap(short, long)]
+        pos: usize,
+
+        /// Regular expression to match log lines against.
+        pattern: String,
+
+        /// Maximum number of matching lines to return, most recent first.
+        #[clap(short, long, default_value_t = 100)]
+        max_lines: usize,
+    },
+
+    /// Check that the server is up and responsive, and how quickly.
+    Ping,
+
+    /// Print the config currently used by the server, which may differ
+    /// from the file, if it was changed since last (re)loaded.
+    GetConf {
+        #[clap(short, long, value_enum, default_value_t = OutputFormat::Toml)]
+        format: OutputFormat,
     },
 
-    /// Ask the server to:
-    /// (1) turn-off feeds
-    /// (2) re-read config
-    /// (3) turn-on feeds
-    Reload,
+    /// Print the config currently used by the server for a specified feed.
+    GetFeedConf {
+        /// Feed position, as shown in status output (starting from 1).
+        #[clap(short, long)]
+        pos: usize,
+
+        #[clap(short, long, value_enum, default_value_t = OutputFormat::Toml)]
+        format: OutputFormat,
+    },
+
+    /// Compare the current status against a baseline saved with
+    /// `status --format json --output <baseline>`.
+    Diff {
+        baseline: PathBuf,
+
+        /// Report changes of more than this, as a percentage of the
+        /// baseline value, with or without the % sign.
+        #[clap(short, long, default_value = "10%", value_parser = parse_pct)]
+        threshold: f64,
+    },
+
+    /// Print the current contents of all slots, which is cheaper than
+    /// `status`.
+    Snapshot {
+        #[clap(short, long, value_enum, default_value_t = SnapshotFormat::Lines)]
+        format: SnapshotFormat,
+    },
+
+    /// Change the separator between feed outputs, until the next reload.
+    SetSeparator { sep: String },
+
+    /// Write the bar right away, rather than at the next output interval,
+    /// even if it hasn't changed since last written.
+    ForceOutput,
+
+    /// Ask the server to re-read config and restart only the feeds whose
+    /// config changed. Turns on the feeds, if they were off.
+    Reload {
+        /// Seconds to wait for the reload to finish. Takes precedence over
+        /// `reload_timeout_secs` in the config file. Defaults to the
+        /// general timeout if neither is set.
+        #[clap(long)]
+        reload_timeout: Option<f64>,
+    },
+}
+
+#[derive(Debug, Clone, Copy, clap::ValueEnum)]
+enum OutputFormat {
+    Toml,
+    Json,
+}
+
+#[derive(Debug, Clone, Copy, clap::ValueEnum)]
+enum SnapshotFormat {
+    /// An array of strings.
+    Json,
+    /// One slot per line.
+    Lines,
 }
 
 impl Cli {
     #[tokio::main]
     #[tracing::instrument(name = "barista", skip_all)]
     async fn run(&self) -> anyhow::Result<()> {
-        barista::tracing::init(self.log_level, self.debug)?;
+        let log_file = self
+            .log_file
+            .as_ref()
+            .map(|path| {
+                expanduser::expanduser(path).context(format!(
+                    "Failed to expand tilde in path: {:?}",
+                    path
+                ))
+            })
+            .transpose()?;
+        barista::tracing::init(
+            self.log_level,
+            self.debug,
+            log_file.as_deref().map(|path| (path, self.log_rotation)),
+            &self.quiet_spans,
+        )?;
         tracing::debug!(?self, "Running");
 
         let dir = expanduser::expanduser(&self.dir).context(format!(
@@ -92,48 +257,124 @@ impl Cli {
         ))?;
         let timeout = Duration::from_secs_f64(self.timeout);
 
-        if let Cmd::Server { backlog, on } = &self.cmd {
-            // TODO Use timeout in the server?
-            server(&dir, *backlog, *on).await
-        } else {
-            client(&self.cmd, &dir, timeout).await
+        match &self.cmd {
+            Cmd::Server {
+                backlog,
+                start_on: _,
+                no_start_on,
+                feeds_dir,
+            } => {
+                // TODO Use timeout in the server?
+                let start_on = !no_start_on;
+                server(&dir, *backlog, start_on, feeds_dir.as_deref()).await
+            }
+            Cmd::Validate => validate(&dir).await,
+            Cmd::KillOrphans => kill_orphans(&dir).await,
+            cmd => client(cmd, &dir, self.tcp, timeout).await,
         }
     }
 }
 
+/// Only the hard limit can be raised above, which needs privileges, so a
+/// higher n is lowered to it, rather than failing the start.
+fn set_max_open_files(n: u32) -> anyhow::Result<()> {
+    use nix::sys::resource::{getrlimit, setrlimit, Resource};
+
+    let (_, hard) = getrlimit(Resource::RLIMIT_NOFILE)?;
+    let soft = max_open_files_soft(n, hard);
+    if soft < u64::from(n) {
+        tracing::warn!(
+            requested = n,
+            hard,
+            "Max open files above the hard limit. Using the hard limit."
+        );
+    }
+    setrlimit(Resource::RLIMIT_NOFILE, soft, hard).context(format!(
+        "Failed to set max open files to {}. Hard limit: {}",
+        soft, hard
+    ))?;
+    tracing::info!(soft, hard, "Set max open files.");
+    Ok(())
+}
+
+/// The soft limit to set for n max open files, which cannot be above the
+/// hard limit.
+fn max_open_files_soft(n: u32, hard: u64) -> u64 {
+    u64::from(n).min(hard)
+}
+
 #[tracing::instrument(skip_all)]
-async fn server(dir: &Path, backlog: u32, on: bool) -> anyhow::Result<()> {
-    tracing::info!(?dir, backlog, on, "Starting");
+async fn server(
+    dir: &Path,
+    backlog: Option<u32>,
+    start_on: bool,
+    feeds_dir: Option<&Path>,
+) -> anyhow::Result<()> {
+    tracing::info!(?dir, ?backlog, start_on, ?feeds_dir, "Starting");
     let pid_file = conf::path_server_pid(dir);
-    let sock_file = conf::path_server_sock(dir);
     if fs::try_exists(&pid_file).await? {
-        bail!(
+        return Err(anyhow!(ControlError::AlreadyRunning)).context(format!(
             "PID file exists. Another server instance possibly running. \
             If you're sure it is not - manually remove this file: {:?}",
             &pid_file
-        );
+        ));
+    }
+    let mut conf = conf::Conf::load_or_init(dir).await?;
+    if let Some(feeds_dir) = feeds_dir {
+        conf.feeds_dir = Some(conf::expand_tilde(feeds_dir)?);
     }
-    if fs::try_exists(&sock_file).await? {
+    let feeds_dir = conf.get_feeds_dir(dir);
+    let abstract_socket = conf.get_abstract_socket();
+    let sock_file = conf::path_server_sock(dir, abstract_socket);
+    if !abstract_socket && fs::try_exists(&sock_file).await? {
         bail!(
             "Socket file exists. Another server instance possibly running. \
             If you're sure it is not - manually remove this file: {:?}",
             &sock_file
         );
     }
+    barista::bar::status::ServerInfo::write_started(dir, &feeds_dir).await?;
+    if let Some(max_open_files) = conf.max_open_files {
+        set_max_open_files(max_open_files)?;
+    }
+    // Last of the steps which can fail, so that a failure doesn't leave
+    // behind a PID file which blocks the next start.
     fs::write(&pid_file, std::process::id().to_string()).await?;
     let mut siblings = JoinSet::new();
-    let bar_tx = barista::bar::server::start(&mut siblings, dir).await?;
+    let backlog = backlog.unwrap_or_else(|| conf.get_socket_backlog());
+    let tcp_bind = conf.control_tcp_bind;
+    let max_rpc_message_bytes = conf.get_max_rpc_message_bytes();
+    let watch_command = conf.watch_command.clone();
+    let on_startup_cmd = conf.on_startup_cmd.clone();
+    let on_startup_timeout = conf.get_on_startup_timeout();
+    let on_shutdown_cmd = conf.on_shutdown_cmd.clone();
+    // Before the control server, which could otherwise be asked to turn
+    // the feeds on while the hook is still preparing for them.
+    if let Some(cmd) = on_startup_cmd {
+        run_hook("startup", &cmd, dir, on_startup_timeout).await;
+    }
+    let bar_tx = barista::bar::server::start(&mut siblings, dir, conf);
     siblings.spawn(
         barista::control::server::run(
             dir.to_path_buf(),
             backlog,
+            abstract_socket,
+            tcp_bind,
+            max_rpc_message_bytes,
             bar_tx.clone(),
         )
         .in_current_span(),
     );
-    if on {
+    if start_on {
         barista::bar::server::on(&bar_tx).await?;
     }
+    // Not a sibling, since the server can go on without it.
+    if let Some(cmd) = watch_command {
+        tokio::spawn(
+            watch_conf(dir.to_path_buf(), cmd, bar_tx.clone())
+                .in_current_span(),
+        );
+    }
     let mut sigterm = tokio::signal::unix::signal(
         tokio::signal::unix::SignalKind::terminate(),
     )?;
@@ -159,22 +400,53 @@ async fn server(dir: &Path, backlog: u32, on: bool) -> anyhow::Result<()> {
             orphaned processes might be remaining. \
             Attempting to kill PIDs found in feed PID files."
         );
-        if let Err(error) = barista::bar::feed::try_kill_all(dir).await {
-            tracing::error!(
-                ?error,
-                "Killing feed processes failed - \
-                orphaned processes might still be remaining."
-            );
+        match barista::bar::feed::try_kill_all(&feeds_dir).await {
+            Ok(report) => {
+                for (path, error) in &report.failed {
+                    tracing::error!(
+                        ?error,
+                        ?path,
+                        "Failed to lookup and kill feed process group - \
+                        orphaned processes might still be remaining."
+                    );
+                }
+                tracing::info!(
+                    killed = report.killed.len(),
+                    failed = report.failed.len(),
+                    "Done killing feed process groups."
+                );
+            }
+            Err(error) => {
+                tracing::error!(
+                    ?error,
+                    "Killing feed processes failed - \
+                    orphaned processes might still be remaining."
+                );
+            }
         }
     }
-    fs::remove_file(&sock_file).await.context(format!(
-        "Failed to remove server socket file: {:?}",
-        &sock_file
-    ))?;
-    fs::remove_file(&pid_file).await.context(format!(
+    if let Some(cmd) = on_shutdown_cmd {
+        run_hook("shutdown", &cmd, dir, on_startup_timeout).await;
+    }
+    // Already gone if the bar server panicked and cleaned up after itself.
+    if !abstract_socket {
+        remove_file_if_exists(&sock_file).await.context(format!(
+            "Failed to remove server socket file: {:?}",
+            &sock_file
+        ))?;
+    }
+    remove_file_if_exists(&pid_file).await.context(format!(
         "Failed to remove server PID file: {:?}",
         &pid_file
     ))?;
+    let last_error =
+        result.as_ref().err().map(|error| format!("{:#}", error));
+    if let Err(error) =
+        barista::bar::status::ServerInfo::write_stopped(dir, last_error).await
+    {
+        tracing::error!(?error, "Failed to record server stop.");
+    }
+    barista::tracing::finish().await;
     result
 }
 
@@ -212,25 +484,505 @@ async fn join(siblings: &mut JoinSet<anyhow::Result<()>>) -> usize {
     errors
 }
 
+async fn validate(dir: &Path) -> anyhow::Result<()> {
+    let file = conf::path_conf(dir);
+    conf::Conf::from_file(&file).await?;
+    println!("OK: {:?}", &file);
+    Ok(())
+}
+
+async fn kill_orphans(dir: &Path) -> anyhow::Result<()> {
+    let pid_file = conf::path_server_pid(dir);
+    if fs::try_exists(&pid_file).await? {
+        bail!(
+            "PID file exists. A server instance is possibly running, \
+            whose feeds are not orphans. \
+            If you're sure it is not - manually remove this file: {:?}",
+            &pid_file
+        );
+    }
+    // The last server may have been given another feeds dir with
+    // --feeds-dir.
+    let feeds_dir = match barista::bar::status::ServerInfo::read(dir).await {
+        Ok(info) => info.feeds_dir,
+        Err(error) => {
+            tracing::debug!(?error, "No server info.");
+            None
+        }
+    };
+    let feeds_dir = match feeds_dir {
+        Some(feeds_dir) => feeds_dir,
+        None => conf::Conf::load_or_init(dir).await?.get_feeds_dir(dir),
+    };
+    let report = barista::bar::feed::try_kill_all(&feeds_dir).await?;
+    println!("{}", report.display());
+    if report.has_failures() {
+        bail!("Failed to kill some feeds.");
+    }
+    Ok(())
+}
+
 #[tracing::instrument(skip_all)]
 async fn client(
     cmd: &Cmd,
     dir: &Path,
+    tcp: Option<SocketAddr>,
     timeout: Duration,
 ) -> anyhow::Result<()> {
-    tracing::debug!(?cmd, ?dir, ?timeout, "Starting");
-    let client = barista::control::client::Client::new(dir, timeout).await?;
+    tracing::debug!(?cmd, ?dir, ?tcp, ?timeout, "Starting");
+    use barista::control::client::{self, Client};
+
+    let conf = read_conf(dir).await;
+    let max_message_bytes = conf
+        .as_ref()
+        .map_or(conf::DEFAULT_MAX_RPC_MESSAGE_BYTES, |conf| {
+            conf.get_max_rpc_message_bytes()
+        });
+    let connected = match tcp {
+        None => {
+            let abstract_socket =
+                conf.as_ref().is_some_and(|conf| conf.get_abstract_socket());
+            Client::new(dir, abstract_socket, timeout, max_message_bytes)
+                .await
+        }
+        Some(addr) => Client::new_tcp(addr, timeout, max_message_bytes).await,
+    };
+    let client = match (connected, cmd) {
+        (Ok(client), _) => client,
+        (
+            Err(error),
+            Cmd::Status {
+                machine,
+                format,
+                output,
+                color,
+                no_color,
+            },
+        ) => {
+            tracing::error!(?error, "Failed to connect to the server.");
+            let status = client::status_without_server(dir, &error).await;
+            let format = if *machine { Format::Tsv } else { *format };
+            let color = use_color(*color, *no_color, output.as_deref());
+            return client::output_status(
+                &status,
+                format,
+                color,
+                output.as_deref(),
+            )
+            .await;
+        }
+        (Err(error), _) => return Err(error),
+    };
     match cmd {
-        Cmd::Server { .. } => {
-            unreachable!("Server command passed to the client function.")
+        Cmd::Server { .. } | Cmd::Validate | Cmd::KillOrphans => {
+            unreachable!("Non-client command passed to the client function.")
         }
         Cmd::On => client.on().await,
         Cmd::Off => client.off().await,
-        Cmd::Status { machine } => client.status(*machine).await,
-        Cmd::Reload => client.reload().await,
+        Cmd::Status {
+            machine,
+            format,
+            output,
+            color,
+            no_color,
+        } => {
+            let format = if *machine { Format::Tsv } else { *format };
+            let color = use_color(*color, *no_color, output.as_deref());
+            client.status(format, color, output.as_deref()).await
+        }
+        Cmd::Ping => {
+            let latency = client.ping().await?;
+            println!("pong {:?}", latency);
+            Ok(())
+        }
+        Cmd::GetConf { format } => {
+            let conf = client.get_conf().await?;
+            let output = match format {
+                OutputFormat::Toml => toml::to_string_pretty(&conf)?,
+                OutputFormat::Json => serde_json::to_string_pretty(&conf)?,
+            };
+            println!("{}", output);
+            Ok(())
+        }
+        Cmd::GetFeedConf { pos, format } => {
+            let feed = client.get_feed_conf(feed_index(*pos)?).await?;
+            let output = match format {
+                OutputFormat::Toml => toml::to_string_pretty(&feed)?,
+                OutputFormat::Json => serde_json::to_string_pretty(&feed)?,
+            };
+            println!("{}", output);
+            Ok(())
+        }
+        Cmd::Snapshot { format } => {
+            let slots = client.snapshot_bar().await?;
+            match format {
+                SnapshotFormat::Json => {
+                    println!("{}", serde_json::to_string(&slots)?);
+                }
+                SnapshotFormat::Lines => {
+                    for slot in slots {
+                        println!("{}", slot);
+                    }
+                }
+            }
+            Ok(())
+        }
+        Cmd::SetSeparator { sep } => client.set_separator(sep.clone()).await,
+        Cmd::ForceOutput => client.force_output().await,
+        Cmd::Diff {
+            baseline,
+            threshold,
+        } => client.diff(baseline, *threshold).await,
+        Cmd::Reload { reload_timeout } => {
+            let secs = match reload_timeout {
+                Some(secs) => Some(*secs),
+                None => {
+                    conf.as_ref().and_then(|conf| conf.reload_timeout_secs)
+                }
+            };
+            let timeout = secs
+                .map(Duration::try_from_secs_f64)
+                .transpose()
+                .context("Invalid reload timeout")?;
+            client.reload(timeout).await
+        }
+        Cmd::ClearLog { pos } => {
+            client.clear_feed_log(feed_index(*pos)?).await
+        }
+        Cmd::SearchLog {
+            pos,
+            pattern,
+            max_lines,
+        } => {
+            client
+                .search_feed_log(feed_index(*pos)?, pattern, *max_lines)
+                .await
+        }
+    }
+}
+
+/// Failures are only logged, since the server is still useful without
+/// whatever the command was meant to do.
+async fn run_hook(name: &str, cmd: &str, dir: &Path, timeout: Duration) {
+    tracing::info!(name, ?cmd, "Running hook.");
+    match conf::run_hook(cmd, dir, timeout).await {
+        Ok(()) => tracing::info!(name, "Hook done."),
+        Err(error) => tracing::error!(name, ?error, "Hook failed."),
+    }
+}
+
+async fn remove_file_if_exists(path: &Path) -> std::io::Result<()> {
+    match fs::remove_file(path).await {
+        Err(error) if error.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        result => result,
+    }
+}
+
+/// For client-side settings, read once per command. Missing or broken config
+/// is not the client's problem, so it just goes with defaults.
+async fn read_conf(dir: &Path) -> Option<conf::Conf> {
+    let file = conf::path_conf(dir);
+    if !fs::try_exists(&file).await.unwrap_or(false) {
+        return None;
+    }
+    conf::Conf::from_file(&file)
+        .await
+        .map_err(|error| {
+            tracing::warn!(?file, ?error, "Failed to read config.");
+        })
+        .ok()
+}
+
+/// Convert user-facing (1-based) feed position to an internal index.
+fn feed_index(pos: usize) -> anyhow::Result<usize> {
+    pos.checked_sub(1)
+        .ok_or_else(|| anyhow!("Feed positions start from 1."))
+}
+
+/// Reloads for every signal from the config watcher, for as long as the
+/// watch command runs.
+#[tracing::instrument(name = "watch_conf", skip_all)]
+async fn watch_conf(
+    dir: PathBuf,
+    cmd: String,
+    bar_tx: barista::bar::server::ApiSender,
+) {
+    let (tx, mut rx) = tokio::sync::mpsc::channel(1);
+    let watcher =
+        tokio::spawn(conf::config_watcher(cmd, dir, tx).in_current_span());
+    while rx.recv().await.is_some() {
+        tracing::info!("Config changed. Reloading.");
+        // Unlike the reload command, never turning on a bar which is off,
+        // such as by the user.
+        match barista::bar::server::reconf(&bar_tx).await {
+            Ok(true) => {}
+            Ok(false) => tracing::info!("Bar off. Config kept for next on."),
+            Err(error) => tracing::error!(?error, "Reload failed."),
+        }
+    }
+    match watcher.await {
+        Ok(Ok(())) => {}
+        Ok(Err(error)) => {
+            tracing::error!(?error, "Config watcher failed.");
+        }
+        Err(error) => {
+            tracing::error!(?error, "Config watcher crashed.");
+        }
+    }
+}
+
+/// Color only if asked to, or if writing to a terminal and not asked not
+/// to.
+fn use_color(color: bool, no_color: bool, output: Option<&Path>) -> bool {
+    use std::io::IsTerminal;
+
+    match (color, no_color) {
+        (true, _) => true,
+        (_, true) => false,
+        (false, false) => {
+            let to_stdout =
+                output.is_none() || output == Some(Path::new("-"));
+            to_stdout && std::io::stdout().is_terminal()
+        }
+    }
+}
+
+/// Percentage, such as "10%" or "10", as a fraction, such as 0.1.
+fn parse_pct(s: &str) -> Result<f64, String> {
+    let pct: f64 = s
+        .strip_suffix('%')
+        .unwrap_or(s)
+        .trim()
+        .parse()
+        .map_err(|error| format!("Invalid percentage {:?}: {}", s, error))?;
+    if !(pct.is_finite() && pct >= 0.0) {
+        return Err(format!("Percentage must be non-negative: {:?}", s));
     }
+    Ok(pct / 100.0)
 }
 
 fn main() -> anyhow::Result<()> {
     Cli::parse().run()
 }
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use barista::control::client::connect;
+    use clap::Parser;
+
+    use super::{
+        conf, max_open_files_soft, parse_pct, server, watch_conf, Cli, Cmd,
+        ControlError,
+    };
+
+    fn start_on(args: &[&str]) -> bool {
+        match Cli::try_parse_from(args).unwrap().cmd {
+            Cmd::Server { no_start_on, .. } => !no_start_on,
+            cmd => panic!("Unexpected command: {:?}", cmd),
+        }
+    }
+
+    #[test]
+    fn server_start_on() {
+        assert!(start_on(&["barista", "server"]));
+        assert!(!start_on(&["barista", "server", "--no-start-on"]));
+        assert!(!start_on(&["barista", "server", "--no-on"]));
+        assert!(start_on(&["barista", "server", "--start-on"]));
+        assert!(start_on(&[
+            "barista",
+            "server",
+            "--no-start-on",
+            "--start-on"
+        ]));
+        assert!(!start_on(&[
+            "barista",
+            "server",
+            "--start-on",
+            "--no-start-on"
+        ]));
+    }
+
+    #[tokio::test]
+    async fn server_already_running() {
+        let dir = barista::tmp_path("server_already_running");
+        tokio::fs::create_dir_all(&dir).await.unwrap();
+        tokio::fs::write(conf::path_server_pid(&dir), "1")
+            .await
+            .unwrap();
+        let error = server(&dir, None, false, None).await.unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<ControlError>(),
+            Some(ControlError::AlreadyRunning)
+        ));
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn server_no_start_on() {
+        let dir = barista::tmp_path("server_no_start_on");
+        tokio::fs::create_dir_all(&dir).await.unwrap();
+        let mut conf = conf::Conf {
+            dst: Some(conf::Dst::StdOut),
+            ..conf::Conf::default()
+        };
+        conf.feeds.truncate(1);
+        conf.feeds[0].cmd = conf::FeedCmd::Cmd(format!(
+            "touch {}/started; sleep 10",
+            dir.display()
+        ));
+        tokio::fs::write(
+            conf::path_conf(&dir),
+            toml::to_string(&conf).unwrap(),
+        )
+        .await
+        .unwrap();
+
+        let server = tokio::spawn({
+            let dir = dir.clone();
+            async move { server(&dir, None, false, None).await }
+        });
+        let client = connect(&dir).await;
+        client.ping().await.unwrap();
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        assert!(!dir.join("started").exists());
+
+        // Until explicitly asked to.
+        client.on().await.unwrap();
+        let mut retries = 50;
+        while !dir.join("started").exists() {
+            assert!(retries > 0, "Feed did not start.");
+            retries -= 1;
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+
+        server.abort();
+        let _ = server.await;
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn server_startup_hook_before_feeds() {
+        let dir = barista::tmp_path("server_startup_hook_before_feeds");
+        tokio::fs::create_dir_all(&dir).await.unwrap();
+        let mut conf = conf::Conf {
+            dst: Some(conf::Dst::StdOut),
+            on_startup_cmd: Some("sleep 0.5; touch hook_done".to_string()),
+            ..conf::Conf::default()
+        };
+        conf.feeds.truncate(1);
+        conf.feeds[0].cmd = conf::FeedCmd::Cmd(format!(
+            "if test -e {0}/hook_done; then touch {0}/after; \
+            else touch {0}/before; fi; sleep 10",
+            dir.display()
+        ));
+        tokio::fs::write(
+            conf::path_conf(&dir),
+            toml::to_string(&conf).unwrap(),
+        )
+        .await
+        .unwrap();
+
+        let server = tokio::spawn({
+            let dir = dir.clone();
+            async move { server(&dir, None, false, None).await }
+        });
+        // As soon as the server can be asked to.
+        let client = connect(&dir).await;
+        client.on().await.unwrap();
+        let mut retries = 50;
+        while !dir.join("after").exists() && !dir.join("before").exists() {
+            assert!(retries > 0, "Feed did not start.");
+            retries -= 1;
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        assert!(dir.join("after").exists());
+
+        // The bar server kills its feeds when aborted along with it.
+        server.abort();
+        let _ = server.await;
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn watch_conf_keeps_bar_off() {
+        let dir = barista::tmp_path("watch_conf_keeps_bar_off");
+        tokio::fs::create_dir_all(&dir).await.unwrap();
+        let mut conf = conf::Conf {
+            dst: Some(conf::Dst::StdOut),
+            ..conf::Conf::default()
+        };
+        let mut siblings = tokio::task::JoinSet::new();
+        let bar_tx =
+            barista::bar::server::start(&mut siblings, &dir, conf.clone());
+        conf.sep = " / ".to_string();
+        tokio::fs::write(
+            conf::path_conf(&dir),
+            toml::to_string(&conf).unwrap(),
+        )
+        .await
+        .unwrap();
+
+        // One change, then the watcher exits.
+        watch_conf(dir.clone(), "echo changed".to_string(), bar_tx.clone())
+            .await;
+        assert!(matches!(
+            barista::bar::server::status(&bar_tx).await.unwrap(),
+            barista::bar::status::Status::UpOff { .. }
+        ));
+        let reloaded = barista::bar::server::get_conf(&bar_tx).await.unwrap();
+        assert_eq!(" / ", reloaded.sep);
+
+        siblings.abort_all();
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+
+    #[test]
+    fn max_open_files_above_hard_limit() {
+        assert_eq!(1024, max_open_files_soft(1025, 1024));
+        assert_eq!(1024, max_open_files_soft(u32::MAX, 1024));
+        assert_eq!(1024, max_open_files_soft(1024, 1024));
+        assert_eq!(512, max_open_files_soft(512, 1024));
+        assert_eq!(512, max_open_files_soft(512, u64::MAX));
+    }
+
+    #[test]
+    fn dir_from_env() {
+        use clap::CommandFactory;
+
+        // Checked rather than set, since changing the environment would
+        // race the tests which read it or spawn processes.
+        let cmd = Cli::command();
+        let arg = cmd
+            .get_arguments()
+            .find(|arg| arg.get_id() == "dir")
+            .unwrap();
+        assert_eq!(
+            Some(std::ffi::OsStr::new(conf::BARISTA_DIR_ENV_VAR)),
+            arg.get_env()
+        );
+        assert_eq!(
+            "/from-arg",
+            Cli::try_parse_from(["barista", "--dir", "/from-arg", "status"])
+                .unwrap()
+                .dir
+        );
+    }
+
+    #[test]
+    fn diff_threshold() {
+        assert_eq!(Ok(0.1), parse_pct("10%"));
+        assert_eq!(Ok(0.1), parse_pct("10"));
+        assert_eq!(Ok(0.0), parse_pct("0%"));
+        assert!(parse_pct("-5%").is_err());
+        assert!(parse_pct("ten").is_err());
+        match Cli::try_parse_from(["barista", "diff", "base.json"])
+            .unwrap()
+            .cmd
+        {
+            Cmd::Diff { threshold, .. } => assert_eq!(0.1, threshold),
+            cmd => panic!("Unexpected command: {:?}", cmd),
+        }
+    }
+}
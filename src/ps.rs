@@ -1,15 +1,76 @@
-use std::collections::{HashMap, HashSet};
+use std::{
+    cmp::Ordering,
+    collections::{HashMap, HashSet, VecDeque},
+    fmt,
+    hash::{Hash, Hasher},
+    str::FromStr,
+    time::{Duration, SystemTime},
+};
 
 use anyhow::{anyhow, bail, Context};
 
-#[derive(
-    Debug, Clone, Eq, PartialEq, Hash, serde::Serialize, serde::Deserialize,
-)]
+use platform::PsFormat;
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub struct Proc {
     pub pid: u32,
     pub ppid: u32,
     pub pgrp: u32,
     pub state: State,
+    pub cpu_pct: f32,
+    pub mem_pct: f32,
+    /// Executable name, as in ps's comm column.
+    pub cmd: String,
+}
+
+// XXX Floats compared and hashed by their bit patterns, which is fine
+//     since we only ever get them from parsing ps output and never do any
+//     arithmetic on them.
+impl PartialEq for Proc {
+    fn eq(&self, other: &Self) -> bool {
+        self.pid == other.pid
+            && self.ppid == other.ppid
+            && self.pgrp == other.pgrp
+            && self.state == other.state
+            && self.cpu_pct.to_bits() == other.cpu_pct.to_bits()
+            && self.mem_pct.to_bits() == other.mem_pct.to_bits()
+            && self.cmd == other.cmd
+    }
+}
+
+impl Eq for Proc {}
+
+impl Hash for Proc {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.pid.hash(state);
+        self.ppid.hash(state);
+        self.pgrp.hash(state);
+        self.state.hash(state);
+        self.cpu_pct.to_bits().hash(state);
+        self.mem_pct.to_bits().hash(state);
+        self.cmd.hash(state);
+    }
+}
+
+/// Ordered by PID. The remaining fields only break ties, to keep the
+/// ordering consistent with `Eq`.
+impl Ord for Proc {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.pid
+            .cmp(&other.pid)
+            .then(self.ppid.cmp(&other.ppid))
+            .then(self.pgrp.cmp(&other.pgrp))
+            .then(self.state.cmp(&other.state))
+            .then(self.cpu_pct.to_bits().cmp(&other.cpu_pct.to_bits()))
+            .then(self.mem_pct.to_bits().cmp(&other.mem_pct.to_bits()))
+            .then_with(|| self.cmd.cmp(&other.cmd))
+    }
+}
+
+impl PartialOrd for Proc {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
 }
 
 // > D    uninterruptible sleep (usually IO)
@@ -22,6 +83,13 @@ pub struct Proc {
 // > X    dead (should never be seen)
 // > Z    defunct ("zombie") process, terminated but not reaped by
 // >      its parent
+//
+// macOS also has:
+// > U    Marks a process in uninterruptible wait.
+// > I    Marks a process that is idle (sleeping for longer than about 20
+// >      seconds).
+// and follows the state letter with flags, such as "s" for session
+// leader or "+" for the foreground process group.
 
 #[derive(
     Debug,
@@ -29,6 +97,8 @@ pub struct Proc {
     Copy,
     Eq,
     PartialEq,
+    Ord,
+    PartialOrd,
     Hash,
     serde::Serialize,
     serde::Deserialize,
@@ -48,7 +118,7 @@ pub enum State {
 impl State {
     fn parse(s: &str) -> anyhow::Result<Self> {
         match s {
-            "D" => Ok(Self::SleepUninterruptible),
+            "D" | "U" => Ok(Self::SleepUninterruptible),
             "I" => Ok(Self::Idle),
             "R" => Ok(Self::RunQueue),
             "S" => Ok(Self::SleepInterruptible),
@@ -76,35 +146,99 @@ impl State {
     }
 }
 
+impl fmt::Display for State {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.to_str())
+    }
+}
+
+impl FromStr for State {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::parse(s)
+    }
+}
+
+pub mod platform {
+    /// Flavor of ps, which differ in column names and state values.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum PsFormat {
+        /// procps.
+        Linux,
+        /// BSD-derived. No pgrp column, but pgid, and flags after states.
+        MacOs,
+    }
+
+    impl PsFormat {
+        pub const CURRENT: Self = if cfg!(target_os = "macos") {
+            Self::MacOs
+        } else {
+            Self::Linux
+        };
+
+        /// comm last, since it may contain spaces.
+        pub fn columns(self) -> &'static str {
+            match self {
+                Self::Linux => "pid,ppid,pgrp,state,%cpu,%mem,comm",
+                Self::MacOs => "pid,ppid,pgid,state,%cpu,%mem,comm",
+            }
+        }
+
+        /// The state letter, without the flags which may follow it.
+        pub fn state(self, field: &str) -> &str {
+            match self {
+                Self::Linux => field,
+                Self::MacOs => field.get(..1).unwrap_or(field),
+            }
+        }
+    }
+}
+
 pub async fn list() -> anyhow::Result<Vec<Proc>> {
-    let out = ps_exec().await?;
-    ps_parse(&out)
+    let format = PsFormat::CURRENT;
+    let out = ps_exec(format).await?;
+    ps_parse(&out, format)
 }
 
-async fn ps_exec() -> anyhow::Result<String> {
-    exec("ps", &["-eo", "pid,ppid,pgrp,state"]).await
+async fn ps_exec(format: PsFormat) -> anyhow::Result<String> {
+    exec("ps", &["-eo", format.columns()]).await
 }
 
-fn ps_parse(out: &str) -> anyhow::Result<Vec<Proc>> {
+fn ps_parse(out: &str, format: PsFormat) -> anyhow::Result<Vec<Proc>> {
     let mut list = Vec::new();
     // Skip headers.
     for line in out.lines().skip(1) {
         let fields: Vec<&str> = line.split_whitespace().collect();
+        // Up to and including the first word of the command.
+        if fields.len() < 7 {
+            bail!("Invalid ps output line: {:?}", line);
+        }
         let pids: Vec<u32> = fields[0..3]
             .iter()
             .filter_map(|num| num.parse().ok())
             .collect();
         let state: Vec<State> = fields[3..4]
             .iter()
-            .filter_map(|s| State::parse(s).ok())
+            .filter_map(|s| State::parse(format.state(s)).ok())
             .collect();
-        match (&pids[..], &state[..]) {
-            ([pid, ppid, pgrp], [state]) => {
+        let pcts: Vec<f32> = fields[4..6]
+            .iter()
+            .filter_map(|num| num.parse().ok())
+            .collect();
+        let cmd = fields[6..].join(" ");
+        match (&pids[..], &state[..], &pcts[..]) {
+            ([pid, ppid, pgrp], [state], [cpu_pct, mem_pct])
+                if !cmd.is_empty() =>
+            {
                 let info = Proc {
                     pid: *pid,
                     ppid: *ppid,
                     pgrp: *pgrp,
                     state: *state,
+                    cpu_pct: *cpu_pct,
+                    mem_pct: *mem_pct,
+                    cmd,
                 };
                 list.push(info);
             }
@@ -123,6 +257,21 @@ pub fn states(procs: &[Proc]) -> HashMap<u32, State> {
         .collect()
 }
 
+/// PID -> (%CPU, %MEM)
+pub fn usage(procs: &[Proc]) -> HashMap<u32, (f32, f32)> {
+    procs
+        .iter()
+        .map(
+            |Proc {
+                 pid,
+                 cpu_pct,
+                 mem_pct,
+                 ..
+             }| (*pid, (*cpu_pct, *mem_pct)),
+        )
+        .collect()
+}
+
 pub fn groups(procs: &[Proc]) -> HashMap<u32, HashSet<u32>> {
     let mut pgroup2pids: HashMap<u32, HashSet<u32>> = HashMap::new();
     for proc in procs {
@@ -136,6 +285,27 @@ pub fn groups(procs: &[Proc]) -> HashMap<u32, HashSet<u32>> {
     pgroup2pids
 }
 
+pub fn proc_by_pid(procs: &[Proc], pid: u32) -> Option<&Proc> {
+    procs.iter().find(|proc| proc.pid == pid)
+}
+
+/// Processes in the group, ordered by PID.
+pub fn group_summary(procs: &[Proc], pgid: u32) -> Vec<&Proc> {
+    let mut group: Vec<&Proc> =
+        procs.iter().filter(|proc| proc.pgrp == pgid).collect();
+    group.sort_by_key(|proc| proc.pid);
+    group
+}
+
+/// Formatted as `pgid=N: [cmd1:state, cmd2:state]`.
+pub fn group_display(procs: &[Proc], pgid: u32) -> String {
+    let members: Vec<String> = group_summary(procs, pgid)
+        .iter()
+        .map(|proc| format!("{}:{}", proc.cmd, proc.state))
+        .collect();
+    format!("pgid={}: [{}]", pgid, members.join(", "))
+}
+
 fn children(procs: &[Proc]) -> HashMap<u32, HashSet<Proc>> {
     let mut parent2children: HashMap<u32, HashSet<Proc>> = HashMap::new();
     for child in procs {
@@ -154,12 +324,12 @@ pub fn descendants(procs: &[Proc]) -> HashMap<u32, HashSet<Proc>> {
     let mut parent2descendants = HashMap::new();
     for ancestor in parent2children.keys() {
         let mut descendants = HashSet::new();
-        let mut ancestors = vec![*ancestor];
-        while let Some(ancestor) = ancestors.pop() {
+        let mut ancestors = VecDeque::from([*ancestor]);
+        while let Some(ancestor) = ancestors.pop_front() {
             if let Some(children) = parent2children.get(&ancestor) {
                 for child in children {
                     descendants.insert(child.clone());
-                    ancestors.push(child.pid);
+                    ancestors.push_back(child.pid);
                 }
             }
         }
@@ -168,6 +338,65 @@ pub fn descendants(procs: &[Proc]) -> HashMap<u32, HashSet<Proc>> {
     parent2descendants
 }
 
+/// Number of generations below root. 0 if root has no children.
+pub fn tree_depth(procs: &[Proc], root: u32) -> usize {
+    let parent2children = children(procs);
+    let mut max_depth = 0;
+    let mut queue = VecDeque::from([(root, 0)]);
+    while let Some((parent, depth)) = queue.pop_front() {
+        max_depth = max_depth.max(depth);
+        if let Some(children) = parent2children.get(&parent) {
+            for child in children {
+                queue.push_back((child.pid, depth + 1));
+            }
+        }
+    }
+    max_depth
+}
+
+/// When the process started, going by its start time in /proc, which is
+/// relative to boot.
+pub async fn start_time(pid: u32) -> anyhow::Result<SystemTime> {
+    use nix::unistd::{sysconf, SysconfVar};
+
+    let stat = tokio::fs::read_to_string(format!("/proc/{}/stat", pid))
+        .await
+        .context(format!("Failed to read stat of PID {}", pid))?;
+    let uptime = tokio::fs::read_to_string("/proc/uptime").await?;
+    let now = SystemTime::now();
+    let ticks = stat_start_ticks(&stat)?;
+    let ticks_per_sec = sysconf(SysconfVar::CLK_TCK)?
+        .filter(|n| *n > 0)
+        .ok_or_else(|| anyhow!("Unknown clock ticks per second."))?;
+    let uptime: f64 = uptime
+        .split_whitespace()
+        .next()
+        .ok_or_else(|| anyhow!("Empty /proc/uptime"))?
+        .parse()?;
+    let boot = now
+        .checked_sub(Duration::try_from_secs_f64(uptime)?)
+        .ok_or_else(|| anyhow!("Boot time out of range."))?;
+    let since_boot =
+        Duration::from_secs_f64(ticks as f64 / ticks_per_sec as f64);
+    Ok(boot + since_boot)
+}
+
+/// Field 22 of /proc/<pid>/stat, in clock ticks since boot.
+fn stat_start_ticks(stat: &str) -> anyhow::Result<u64> {
+    // comm (field 2) is in parens and may itself contain spaces and
+    // parens, so counting from the last paren, which is followed by
+    // field 3.
+    let (_, rest) = stat
+        .rsplit_once(')')
+        .ok_or_else(|| anyhow!("Invalid stat: {:?}", stat))?;
+    let ticks = rest
+        .split_whitespace()
+        .nth(22 - 3)
+        .ok_or_else(|| anyhow!("Too few fields in stat: {:?}", stat))?
+        .parse()?;
+    Ok(ticks)
+}
+
 async fn exec(cmd: &str, args: &[&str]) -> anyhow::Result<String> {
     use std::process::Output;
 
@@ -198,70 +427,155 @@ async fn exec(cmd: &str, args: &[&str]) -> anyhow::Result<String> {
 mod tests {
     use super::*;
 
-    const OUT_0: &str = "PID PPID PGRP";
-    const OUT_1: &str = "  PID  PPID  PGRP STATE
-    1     0     1     Z
-    2     1     2     Z
-    3     1     3     Z
-    4     1     4     Z
-    5     4     4     Z
+    const OUT_0: &str = "PID PPID PGRP STATE %CPU %MEM COMMAND";
+    const OUT_1: &str = "  PID  PPID  PGRP STATE %CPU %MEM COMMAND
+    1     0     1     Z  0.0  0.1 init
+    2     1     2     Z  0.5  0.0 a
+    3     1     3     Z 12.3  1.5 b
+    4     1     4     Z  0.0  0.0 sh
+    5     4     4     Z 99.9 45.6 Web Content
 ";
 
-    const PROC_1_1: Proc = Proc {
-        pid: 1,
-        ppid: 0,
-        pgrp: 1,
-        state: State::Zombie,
-    };
-    const PROC_1_2: Proc = Proc {
-        pid: 2,
-        ppid: 1,
-        pgrp: 2,
-        state: State::Zombie,
-    };
-    const PROC_1_3: Proc = Proc {
-        pid: 3,
-        ppid: 1,
-        pgrp: 3,
-        state: State::Zombie,
-    };
-    const PROC_1_4: Proc = Proc {
-        pid: 4,
-        ppid: 1,
-        pgrp: 4,
-        state: State::Zombie,
-    };
-    const PROC_1_5: Proc = Proc {
-        pid: 5,
-        ppid: 4,
-        pgrp: 4,
-        state: State::Zombie,
-    };
+    fn proc_1_1() -> Proc {
+        Proc {
+            pid: 1,
+            ppid: 0,
+            pgrp: 1,
+            state: State::Zombie,
+            cpu_pct: 0.0,
+            mem_pct: 0.1,
+            cmd: "init".to_string(),
+        }
+    }
+    fn proc_1_2() -> Proc {
+        Proc {
+            pid: 2,
+            ppid: 1,
+            pgrp: 2,
+            state: State::Zombie,
+            cpu_pct: 0.5,
+            mem_pct: 0.0,
+            cmd: "a".to_string(),
+        }
+    }
+    fn proc_1_3() -> Proc {
+        Proc {
+            pid: 3,
+            ppid: 1,
+            pgrp: 3,
+            state: State::Zombie,
+            cpu_pct: 12.3,
+            mem_pct: 1.5,
+            cmd: "b".to_string(),
+        }
+    }
+    fn proc_1_4() -> Proc {
+        Proc {
+            pid: 4,
+            ppid: 1,
+            pgrp: 4,
+            state: State::Zombie,
+            cpu_pct: 0.0,
+            mem_pct: 0.0,
+            cmd: "sh".to_string(),
+        }
+    }
+    fn proc_1_5() -> Proc {
+        Proc {
+            pid: 5,
+            ppid: 4,
+            pgrp: 4,
+            state: State::Zombie,
+            cpu_pct: 99.9,
+            mem_pct: 45.6,
+            cmd: "Web Content".to_string(),
+        }
+    }
 
     #[test]
     fn test_0_1_parse() {
-        assert!(ps_parse(OUT_0).unwrap().is_empty());
+        assert!(ps_parse(OUT_0, PsFormat::Linux).unwrap().is_empty());
     }
 
     #[test]
     fn test_0_2_process_groups() {
-        assert!(groups(ps_parse(OUT_0).unwrap().as_slice()).is_empty());
+        assert!(groups(ps_parse(OUT_0, PsFormat::Linux).unwrap().as_slice())
+            .is_empty());
     }
 
     #[test]
     fn test_0_3_children() {
-        assert!(children(ps_parse(OUT_0).unwrap().as_slice()).is_empty());
+        assert!(children(
+            ps_parse(OUT_0, PsFormat::Linux).unwrap().as_slice()
+        )
+        .is_empty());
     }
 
     #[test]
     fn test_1_1_parse() {
         let out = OUT_1;
         let list_expected =
-            vec![PROC_1_1, PROC_1_2, PROC_1_3, PROC_1_4, PROC_1_5];
-        let list_actual = ps_parse(out).unwrap();
+            vec![proc_1_1(), proc_1_2(), proc_1_3(), proc_1_4(), proc_1_5()];
+        let list_actual = ps_parse(out, PsFormat::Linux).unwrap();
         assert_eq!(list_expected, list_actual);
     }
 
+    #[test]
+    fn parse_short_lines() {
+        let header = "    PID    PPID    PGRP S %CPU %MEM COMMAND\n";
+        for line in [
+            "",
+            "1",
+            "1 0 1",
+            "1 0 1 S",
+            "1 0 1 S 0.0",
+            "1 0 1 S 0.0 0.1",
+        ] {
+            let out = format!("{}{}\n", header, line);
+            let error = ps_parse(&out, PsFormat::Linux).unwrap_err();
+            assert!(
+                error.to_string().starts_with("Invalid ps output line"),
+                "{:?}: {}",
+                line,
+                error
+            );
+        }
+    }
+
+    #[test]
+    fn parse_macos() {
+        let out = "  PID  PPID  PGID STAT  %CPU %MEM COMM
+    1     0     1 Ss    0.0  0.1 /sbin/launchd
+  400     1   400 U     0.0  0.0 diskimages-helper
+  501   500   501 S+   12.5  1.0 -zsh
+  600   501   600 I     0.0  0.0 Web Content
+  601   600   600 R<    1.0  0.0 sh
+  602   600   600 Z     0.0  0.0 (sh)
+";
+        let list = ps_parse(out, PsFormat::MacOs).unwrap();
+        let summary: Vec<(u32, u32, State, &str)> = list
+            .iter()
+            .map(|proc| (proc.pid, proc.pgrp, proc.state, proc.cmd.as_str()))
+            .collect();
+        assert_eq!(
+            vec![
+                (1, 1, State::SleepInterruptible, "/sbin/launchd"),
+                (400, 400, State::SleepUninterruptible, "diskimages-helper"),
+                (501, 501, State::SleepInterruptible, "-zsh"),
+                (600, 600, State::Idle, "Web Content"),
+                (601, 600, State::RunQueue, "sh"),
+                (602, 600, State::Zombie, "(sh)"),
+            ],
+            summary
+        );
+        // Flags aren't expected from procps.
+        assert!(ps_parse(out, PsFormat::Linux).is_err());
+        assert_eq!(
+            "pid,ppid,pgid,state,%cpu,%mem,comm",
+            PsFormat::MacOs.columns()
+        );
+    }
+
     #[test]
     fn test_1_2_process_groups() {
         let out = OUT_1;
@@ -271,7 +585,7 @@ mod tests {
             (3, HashSet::from([3])),
             (4, HashSet::from([4, 5])),
         ]);
-        let list = ps_parse(out).unwrap();
+        let list = ps_parse(out, PsFormat::Linux).unwrap();
         let groups_actual = groups(&list[..]);
         assert_eq!(groups_expected, groups_actual);
     }
@@ -280,11 +594,11 @@ mod tests {
     fn test_1_3_children() {
         let out = OUT_1;
         let children_expected = HashMap::from([
-            (0, HashSet::from([PROC_1_1])),
-            (1, HashSet::from([PROC_1_2, PROC_1_3, PROC_1_4])),
-            (4, HashSet::from([PROC_1_5])),
+            (0, HashSet::from([proc_1_1()])),
+            (1, HashSet::from([proc_1_2(), proc_1_3(), proc_1_4()])),
+            (4, HashSet::from([proc_1_5()])),
         ]);
-        let list = ps_parse(out).unwrap();
+        let list = ps_parse(out, PsFormat::Linux).unwrap();
         let children_actual = children(&list[..]);
         assert_eq!(children_expected, children_actual);
     }
@@ -296,17 +610,80 @@ mod tests {
             (
                 0,
                 HashSet::from([
-                    PROC_1_1, PROC_1_2, PROC_1_3, PROC_1_4, PROC_1_5,
+                    proc_1_1(),
+                    proc_1_2(),
+                    proc_1_3(),
+                    proc_1_4(),
+                    proc_1_5(),
                 ]),
             ),
-            (1, HashSet::from([PROC_1_2, PROC_1_3, PROC_1_4, PROC_1_5])),
-            (4, HashSet::from([PROC_1_5])),
+            (
+                1,
+                HashSet::from([
+                    proc_1_2(),
+                    proc_1_3(),
+                    proc_1_4(),
+                    proc_1_5(),
+                ]),
+            ),
+            (4, HashSet::from([proc_1_5()])),
         ]);
-        let list = ps_parse(out).unwrap();
+        let list = ps_parse(out, PsFormat::Linux).unwrap();
         let descendants_actual = descendants(&list[..]);
         assert_eq!(descendants_expected, descendants_actual);
     }
 
+    #[test]
+    fn test_1_4_tree_depth() {
+        let list = ps_parse(OUT_1, PsFormat::Linux).unwrap();
+        assert_eq!(3, tree_depth(&list[..], 0));
+        assert_eq!(2, tree_depth(&list[..], 1));
+        assert_eq!(1, tree_depth(&list[..], 4));
+        assert_eq!(0, tree_depth(&list[..], 5));
+        assert_eq!(0, tree_depth(&list[..], 42));
+    }
+
+    #[test]
+    fn test_2_deep_chain() {
+        let depth: u32 = 100;
+        let list: Vec<Proc> = (1..=depth)
+            .map(|pid| Proc {
+                pid,
+                ppid: pid - 1,
+                pgrp: 1,
+                state: State::SleepInterruptible,
+                cpu_pct: 0.0,
+                mem_pct: 0.0,
+                cmd: "sh".to_string(),
+            })
+            .collect();
+        let descendants = descendants(&list[..]);
+        for ancestor in 0..depth {
+            let expected = (depth - ancestor) as usize;
+            assert_eq!(expected, descendants[&ancestor].len());
+        }
+        assert!(!descendants.contains_key(&depth));
+        assert_eq!(depth as usize, tree_depth(&list[..], 0));
+        assert_eq!(1, tree_depth(&list[..], depth - 1));
+    }
+
+    #[test]
+    fn test_1_7_groups_display() {
+        let list = ps_parse(OUT_1, PsFormat::Linux).unwrap();
+        assert_eq!(Some(&proc_1_4()), proc_by_pid(&list[..], 4));
+        assert_eq!(None, proc_by_pid(&list[..], 42));
+        assert_eq!(
+            vec![&proc_1_4(), &proc_1_5()],
+            group_summary(&list[..], 4)
+        );
+        assert_eq!(
+            "pgid=4: [sh:Z, Web Content:Z]",
+            group_display(&list[..], 4)
+        );
+        assert_eq!("pgid=2: [a:Z]", group_display(&list[..], 2));
+        assert_eq!("pgid=42: []", group_display(&list[..], 42));
+    }
+
     #[test]
     fn test_1_5_states() {
         let out = OUT_1;
@@ -317,8 +694,65 @@ mod tests {
             (4, State::Zombie),
             (5, State::Zombie),
         ]);
-        let list = ps_parse(out).unwrap();
+        let list = ps_parse(out, PsFormat::Linux).unwrap();
         let states_actual = states(&list[..]);
         assert_eq!(states_expected, states_actual);
     }
+
+    #[test]
+    fn test_1_6_usage() {
+        let out = OUT_1;
+        let usage_expected = HashMap::from([
+            (1, (0.0, 0.1)),
+            (2, (0.5, 0.0)),
+            (3, (12.3, 1.5)),
+            (4, (0.0, 0.0)),
+            (5, (99.9, 45.6)),
+        ]);
+        let list = ps_parse(out, PsFormat::Linux).unwrap();
+        let usage_actual = usage(&list[..]);
+        assert_eq!(usage_expected, usage_actual);
+    }
+
+    #[test]
+    fn stat_start_ticks() {
+        let stat = "4242 (my (weird) cmd) S 1 4242 4242 0 -1 4194560 \
+            100 0 0 0 1 2 0 0 20 0 1 0 123456 8314880 400 \
+            18446744073709551615 1 1 0 0 0 0 0 0 0 0 0 0 17 3 0 0 0 0 0";
+        assert_eq!(123456, super::stat_start_ticks(stat).unwrap());
+        assert!(super::stat_start_ticks("4242 (cmd S 1").is_err());
+        assert!(super::stat_start_ticks("4242 (cmd) S 1 2 3").is_err());
+    }
+
+    #[tokio::test]
+    async fn start_time() {
+        let started = super::start_time(std::process::id()).await.unwrap();
+        assert!(started <= SystemTime::now());
+    }
+
+    #[test]
+    fn state_str_round_trip() {
+        for state in [
+            State::SleepUninterruptible,
+            State::SleepInterruptible,
+            State::Idle,
+            State::RunQueue,
+            State::StoppedByJobControl,
+            State::StoppedByDebugger,
+            State::Paging,
+            State::Dead,
+            State::Zombie,
+        ] {
+            assert_eq!(state, state.to_string().parse::<State>().unwrap());
+        }
+        assert!("?".parse::<State>().is_err());
+    }
+
+    #[test]
+    fn proc_ord_by_pid() {
+        let mut procs = [proc_1_5(), proc_1_2(), proc_1_4()];
+        procs.sort();
+        let pids: Vec<u32> = procs.iter().map(|p| p.pid).collect();
+        assert_eq!(vec![2, 4, 5], pids);
+    }
 }
@@ -1,12 +1,69 @@
-use std::time::Duration;
+use std::{collections::HashSet, path::Path, sync::Arc, time::Duration};
 
+use anyhow::{anyhow, Context};
 use tokio::time::sleep;
-use tracing_subscriber::{fmt, layer::SubscriberExt, EnvFilter, Layer};
+use tracing_appender::rolling::RollingFileAppender;
+use tracing_subscriber::{
+    filter::FilterExt,
+    fmt,
+    layer::{self, SubscriberExt},
+    registry::LookupSpan,
+    EnvFilter, Layer,
+};
+
+#[derive(Debug, Clone, Copy, clap::ValueEnum)]
+pub enum Rotation {
+    Daily,
+    Hourly,
+    Never,
+}
+
+impl From<Rotation> for tracing_appender::rolling::Rotation {
+    fn from(rotation: Rotation) -> Self {
+        match rotation {
+            Rotation::Daily => Self::DAILY,
+            Rotation::Hourly => Self::HOURLY,
+            Rotation::Never => Self::NEVER,
+        }
+    }
+}
 
 pub fn init(
     level: Option<tracing::Level>,
     debug: bool,
+    log_file: Option<(&Path, Rotation)>,
+    quiet_spans: &[String],
 ) -> anyhow::Result<()> {
+    let quiet = QuietSpanFilter::new(quiet_spans);
+    let layer_stderr = fmt::Layer::new()
+        .with_writer(std::io::stderr)
+        .with_ansi(true)
+        .with_file(false)
+        .with_line_number(true)
+        .with_thread_ids(true)
+        .with_filter(
+            env_filter(level, debug)
+                .and(quiet.clone())
+                .and(FeedLevelFilter),
+        );
+    let layer_file = log_file
+        .map(|(path, rotation)| {
+            layer_file(path, rotation).map(|layer| {
+                layer.with_filter(
+                    env_filter(level, debug).and(quiet).and(FeedLevelFilter),
+                )
+            })
+        })
+        .transpose()?;
+    tracing::subscriber::set_global_default(
+        tracing_subscriber::registry()
+            .with(layer_stderr)
+            .with(layer_file),
+    )?;
+    Ok(())
+}
+
+fn env_filter(level: Option<tracing::Level>, debug: bool) -> EnvFilter {
     let level = level.unwrap_or(if debug {
         tracing::Level::DEBUG
     } else {
@@ -22,22 +79,209 @@ pub fn init(
     };
     let base_env_filter =
         || EnvFilter::from_default_env().add_directive(level.into());
-    let env_filter = extra_filter_directive
-        .map_or_else(base_env_filter, |d| base_env_filter().add_directive(d));
-    let layer_stderr = fmt::Layer::new()
-        .with_writer(std::io::stderr)
-        .with_ansi(true)
+    extra_filter_directive
+        .map_or_else(base_env_filter, |d| base_env_filter().add_directive(d))
+}
+
+/// Drops events from within the named spans, at any depth. The spans
+/// themselves are kept, so that their children can be found.
+#[derive(Debug, Clone)]
+pub struct QuietSpanFilter {
+    names: Arc<HashSet<String>>,
+}
+
+impl QuietSpanFilter {
+    pub fn new(names: &[String]) -> Self {
+        Self {
+            names: Arc::new(names.iter().cloned().collect()),
+        }
+    }
+}
+
+impl<S> layer::Filter<S> for QuietSpanFilter
+where
+    S: tracing::Subscriber + for<'span> LookupSpan<'span>,
+{
+    fn enabled(
+        &self,
+        meta: &tracing::Metadata<'_>,
+        cx: &layer::Context<'_, S>,
+    ) -> bool {
+        if self.names.is_empty() || !meta.is_event() {
+            return true;
+        }
+        !cx.lookup_current().is_some_and(|span| {
+            span.scope().any(|span| self.names.contains(span.name()))
+        })
+    }
+}
+
+/// Drops events more verbose than the level in the log_level field of the
+/// nearest span which has one, as the spans of feeds do, see
+/// conf::Feed::log_level.
+#[derive(Debug, Clone, Copy)]
+pub struct FeedLevelFilter;
+
+/// Kept in the extensions of the span with the log_level field.
+struct FeedLevel(tracing::Level);
+
+#[derive(Default)]
+struct FeedLevelVisitor(Option<tracing::Level>);
+
+impl tracing::field::Visit for FeedLevelVisitor {
+    fn record_debug(
+        &mut self,
+        field: &tracing::field::Field,
+        value: &dyn std::fmt::Debug,
+    ) {
+        if field.name() == "log_level" {
+            self.0 = format!("{:?}", value).parse().ok();
+        }
+    }
+}
+
+impl<S> layer::Filter<S> for FeedLevelFilter
+where
+    S: tracing::Subscriber + for<'span> LookupSpan<'span>,
+{
+    fn enabled(
+        &self,
+        meta: &tracing::Metadata<'_>,
+        cx: &layer::Context<'_, S>,
+    ) -> bool {
+        if !meta.is_event() {
+            return true;
+        }
+        let level = cx.lookup_current().and_then(|span| {
+            span.scope().find_map(|span| {
+                span.extensions().get::<FeedLevel>().map(|level| level.0)
+            })
+        });
+        match level {
+            Some(level) => *meta.level() <= level,
+            None => true,
+        }
+    }
+
+    fn on_new_span(
+        &self,
+        attrs: &tracing::span::Attributes<'_>,
+        id: &tracing::span::Id,
+        cx: layer::Context<'_, S>,
+    ) {
+        let mut visitor = FeedLevelVisitor::default();
+        attrs.record(&mut visitor);
+        if let (Some(level), Some(span)) = (visitor.0, cx.span(id)) {
+            // Each layer's filter stores the same level.
+            span.extensions_mut().replace(FeedLevel(level));
+        }
+    }
+}
+
+fn layer_file<S>(
+    path: &Path,
+    rotation: Rotation,
+) -> anyhow::Result<impl Layer<S>>
+where
+    S: tracing::Subscriber
+        + for<'span> tracing_subscriber::registry::LookupSpan<'span>,
+{
+    let dir = path
+        .parent()
+        .ok_or_else(|| anyhow!("Log file path has no parent: {:?}", path))?;
+    let file_name = path.file_name().ok_or_else(|| {
+        anyhow!("Log file path has no file name: {:?}", path)
+    })?;
+    let appender = RollingFileAppender::builder()
+        .rotation(rotation.into())
+        .filename_prefix(file_name.to_string_lossy())
+        .build(dir)
+        .context(format!("Failed to initialize log file: {:?}", path))?;
+    let layer = fmt::Layer::new()
+        .json()
+        .with_writer(appender)
+        .with_ansi(false)
         .with_file(false)
         .with_line_number(true)
-        .with_thread_ids(true)
-        .with_filter(env_filter);
-    tracing::subscriber::set_global_default(
-        tracing_subscriber::registry().with(layer_stderr),
-    )?;
-    Ok(())
+        .with_thread_ids(true);
+    Ok(layer)
 }
 
 pub async fn finish() {
     // Terrible approximation of flushing.
     sleep(Duration::from_micros(5)).await;
 }
+
+/// Log output, for tests to look into.
+#[cfg(test)]
+#[derive(Clone, Default)]
+pub(crate) struct LogCapture(pub(crate) Arc<std::sync::Mutex<Vec<u8>>>);
+
+#[cfg(test)]
+impl std::io::Write for LogCapture {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tracing_subscriber::{layer::SubscriberExt, Layer};
+
+    use super::{layer_file, QuietSpanFilter, Rotation};
+
+    #[test]
+    fn log_file_json() {
+        let path = crate::tmp_path("log_file_json");
+        let layer = layer_file(&path, Rotation::Never).unwrap();
+        tracing::subscriber::with_default(
+            tracing_subscriber::registry().with(layer),
+            || {
+                tracing::info!(answer = 42, "Hello, file.");
+            },
+        );
+        let log = std::fs::read_to_string(&path).unwrap();
+        let line = log.lines().next().unwrap();
+        assert!(line.starts_with('{'));
+        assert!(line.contains(r#""level":"INFO""#));
+        assert!(line.contains(r#""message":"Hello, file.""#));
+        assert!(line.contains(r#""answer":42"#));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn quiet_spans() {
+        let path = crate::tmp_path("quiet_spans");
+        let quiet = QuietSpanFilter::new(&["noisy".to_string()]);
+        let layer = layer_file(&path, Rotation::Never).unwrap();
+        tracing::subscriber::with_default(
+            tracing_subscriber::registry().with(layer.with_filter(quiet)),
+            || {
+                tracing::info!("outside");
+                tracing::info_span!("noisy").in_scope(|| {
+                    tracing::info!("inside");
+                    tracing::info_span!("nested").in_scope(|| {
+                        tracing::info!("nested inside");
+                    });
+                });
+                tracing::info_span!("calm").in_scope(|| {
+                    tracing::info!("calm inside");
+                });
+            },
+        );
+        let log = std::fs::read_to_string(&path).unwrap();
+        let messages: Vec<&str> = log
+            .lines()
+            .filter_map(|line| {
+                line.split(r#""message":""#).nth(1)?.split('"').next()
+            })
+            .collect();
+        assert_eq!(vec!["outside", "calm inside"], messages);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -1,11 +1,46 @@
-use std::ffi::CString;
+use std::{
+    ffi::CString,
+    os::raw::c_int,
+    sync::atomic::{AtomicU8, Ordering},
+};
 
 use anyhow::{anyhow, bail};
 use x11::xlib;
 
+/// Error code of the last X protocol error, or 0 if none since last taken.
+/// Xlib reports errors to a single process-wide handler, hence the static.
+static LAST_ERROR: AtomicU8 = AtomicU8::new(0);
+
+unsafe extern "C" fn on_error(
+    _: *mut xlib::Display,
+    event: *mut xlib::XErrorEvent,
+) -> c_int {
+    if let Some(event) = event.as_ref() {
+        LAST_ERROR.store(event.error_code, Ordering::SeqCst);
+    }
+    0
+}
+
+/// Called by Xlib when the display connection is lost, such as when the X
+/// server exits, after which Xlib exits the process as soon as this
+/// returns, which it has to. So unlike the errors of on_error, this one
+/// can't be recovered from by reconnecting, only reported.
+unsafe extern "C" fn on_io_error(_: *mut xlib::Display) -> c_int {
+    tracing::error!("X11 display connection lost. Exiting.");
+    0
+}
+
+fn take_last_error() -> Option<u8> {
+    match LAST_ERROR.swap(0, Ordering::SeqCst) {
+        0 => None,
+        code => Some(code),
+    }
+}
+
 /// Ref: <https://xwindow.angelfire.com/page2.html>
 /// Ref: <https://www.oreilly.com/library/view/xlib-reference-manual/9780937175262/14_appendix-f.html>
 pub struct X11 {
+    /// Null after a failure, when the connection is no longer usable.
     display_ptr: *mut xlib::Display,
 }
 
@@ -17,30 +52,93 @@ impl X11 {
         if display_ptr.is_null() {
             Err(anyhow!("XOpenDisplay failed"))
         } else {
+            unsafe {
+                xlib::XSetErrorHandler(Some(on_error));
+                xlib::XSetIOErrorHandler(Some(on_io_error));
+            }
             Ok(Self { display_ptr })
         }
     }
 
-    pub fn set_root_window_name(&self, name: &str) -> anyhow::Result<()> {
+    #[cfg(test)]
+    pub fn disconnected() -> Self {
+        Self {
+            display_ptr: std::ptr::null_mut(),
+        }
+    }
+
+    pub fn is_connected(&self) -> bool {
+        !self.display_ptr.is_null()
+    }
+
+    /// On failure the connection is closed, and a new X11 needs to be
+    /// initialized to try again.
+    pub fn set_root_window_name(&mut self, name: &str) -> anyhow::Result<()> {
+        if !self.is_connected() {
+            bail!("X11 display connection is closed");
+        }
+        let result = self.store_root_window_name(name);
+        if result.is_err() {
+            self.disconnect();
+        }
+        result
+    }
+
+    fn store_root_window_name(&self, name: &str) -> anyhow::Result<()> {
         let name = CString::new(name)?;
         let name = name.as_ptr();
+        take_last_error();
         let window = unsafe { xlib::XDefaultRootWindow(self.display_ptr) };
         let ret = unsafe { xlib::XStoreName(self.display_ptr, window, name) };
         if ret < 0 {
             bail!("XStoreName failed: {}", ret);
         };
-        let ret = unsafe { xlib::XFlush(self.display_ptr) };
+        // XSync rather than XFlush, to wait for any errors to come back.
+        let ret = unsafe { xlib::XSync(self.display_ptr, xlib::False) };
         if ret < 0 {
-            bail!("XFlush failed: {}", ret);
+            bail!("XSync failed: {}", ret);
         };
+        if let Some(code) = take_last_error() {
+            bail!("X protocol error: {}", code);
+        }
         Ok(())
     }
+
+    fn disconnect(&mut self) {
+        if self.is_connected() {
+            unsafe {
+                xlib::XCloseDisplay(self.display_ptr);
+            }
+            self.display_ptr = std::ptr::null_mut();
+        }
+    }
 }
 
 impl Drop for X11 {
     fn drop(&mut self) {
-        unsafe {
-            xlib::XCloseDisplay(self.display_ptr);
-        }
+        self.disconnect();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use x11::xlib;
+
+    use super::{on_error, take_last_error, X11};
+
+    #[test]
+    fn error_handler() {
+        let mut event: xlib::XErrorEvent = unsafe { std::mem::zeroed() };
+        event.error_code = xlib::BadWindow;
+        assert_eq!(0, unsafe { on_error(std::ptr::null_mut(), &mut event) });
+        assert_eq!(Some(xlib::BadWindow), take_last_error());
+        assert_eq!(None, take_last_error());
+    }
+
+    #[test]
+    fn disconnected() {
+        let mut x11 = X11::disconnected();
+        assert!(!x11.is_connected());
+        assert!(x11.set_root_window_name("foo").is_err());
     }
 }